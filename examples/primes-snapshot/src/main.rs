@@ -1,7 +1,7 @@
 use core::sync::atomic::{AtomicU64, Ordering};
 
 use shm_fd::SharedFd;
-use shm_snapshot::{ConfigureFile, File, PreparedTransaction, Writer, Snapshot};
+use shm_snapshot::{ConfigureFile, File, PreparedTransaction, Writer};
 
 fn main() {
     let Some(fd) = (unsafe { SharedFd::from_env() }) else {
@@ -66,6 +66,11 @@ struct State {
 fn run_main_routine(mut tx: PreparedTransaction<'_>, num_range: core::ops::Range<u64>)
     -> Option<State>
 {
+    // The staged data holds the end of the range, checked before it is replaced by the count.
+    let mut staged = [0; 8];
+    tx.current(&mut staged);
+    debug_assert_eq!(u64::from_be_bytes(staged), num_range.end);
+
     let values = tx.tail();
 
     if values[0].load(Ordering::Relaxed) == 0 {
@@ -86,18 +91,19 @@ fn run_main_routine(mut tx: PreparedTransaction<'_>, num_range: core::ops::Range
 
     if pos >= values.len() {
         println!("No more primes to fill");
-        eprintln!("{:?}", &values[..]);
+        eprintln!("{:?}", values);
+        tx.abort();
         return None;
     }
 
     for candidate in num_range {
         // Check divisibility for all prior primes.
-        if !check_prime(candidate, &values[..pos as usize]) {
+        if !check_prime(candidate, &values[..pos]) {
             continue;
         }
 
         // Found a new prime.
-        values[pos as usize].store(candidate, Ordering::Relaxed);
+        values[pos].store(candidate, Ordering::Relaxed);
         num += 1;
         pos += 1;
     }
@@ -107,18 +113,19 @@ fn run_main_routine(mut tx: PreparedTransaction<'_>, num_range: core::ops::Range
     eprintln!("generated {} more primes, total {}", num, post_place);
     Some(State {
         prime_total: post_place,
-        prime_last: values[pos as usize].load(Ordering::Relaxed),
+        prime_last: values[pos].load(Ordering::Relaxed),
     })
 }
 
 fn check_prime(num: u64, primes: &[AtomicU64]) -> bool {
-    let bound = upper_int_sqrt(num);
+    // Any composite number has a prime factor not larger than its square root.
+    let bound = num.isqrt();
     for p in primes {
         if p.load(Ordering::Relaxed) > bound {
             break;
         }
 
-        if (num % p.load(Ordering::Relaxed)) == 0 {
+        if num.is_multiple_of(p.load(Ordering::Relaxed)) {
             return false;
         }
     }
@@ -126,67 +133,30 @@ fn check_prime(num: u64, primes: &[AtomicU64]) -> bool {
     true
 }
 
-fn upper_int_sqrt(num: u64) -> u64 {
-    let mut l = 0;
-    let mut r = num + 1;
-
-    // Loop invariant: l < sqrt(num) <= r
-    // Termination: r - l is strictly decreasing.
-    while l != r - 1 {
-        // Avoids overflow because l < r
-        // Also note that m < r for this reason
-        // And l < m as r - l > 1
-        let m = l + (r - l) / 2;
-        if m * m < num {
-            // preserves l < r
-            l = m;
-        } else {
-            r = m;
-        }
-    }
-
-    r
-}
-
 fn restore_from(fd: SharedFd) -> (Writer, State) {
-    struct ExtendWith<F>(F);
-
-    impl<F> Extend<Snapshot> for ExtendWith<F>
-        where F: FnMut(Snapshot)
-    {
-        fn extend<T: IntoIterator<Item = Snapshot>>(&mut self, iter: T) {
-            for item in iter {
-                (self.0)(item);
-            }
-        }
-    }
-
     let file = fd.into_file().expect("opening shared fd failed");
     let _ = file.set_len(100_000_000u64);
     let mapping = File::new(file).unwrap();
     let mut config = ConfigureFile::default();
 
-    let mut latest_snapshot = None;
-    if let Some(mapping) = mapping.recover(&mut config) {
-        let mut restore_state = ExtendWith(|snapshot: Snapshot| {
-            latest_snapshot = std::cmp::max_by_key(
-                    latest_snapshot, Some(snapshot),
-                    |x: &Option<Snapshot>| x.map(|v| v.offset)
-                );
-        });
-
-        mapping.valid(&mut restore_state);
-    }
+    // Each snapshot records the total number of primes, the most recent one has the most.
+    let latest_snapshot = mapping.recover(&mut config).and_then(|mapping| {
+        mapping.latest_by(|_, data| {
+            data.try_into().map_or(0, u64::from_be_bytes)
+        })
+    });
 
     config.or_insert_with(|cfg| {
         cfg.entries = 0x100;
         cfg.data = 0x800;
     });
 
-    let writer = mapping.configure(&config);
+    let writer = mapping.configure(&config).expect("failed to configure shared memory");
     let prime_total = if let Some(latest_snapshot) = latest_snapshot {
         let mut buffer = [0; 8];
-        writer.read(&latest_snapshot, &mut buffer);
+        writer
+            .read_exact(&latest_snapshot, &mut buffer)
+            .expect("the latest snapshot to hold the number of primes");
         u64::from_be_bytes(buffer)
     } else {
         0
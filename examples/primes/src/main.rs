@@ -69,38 +69,17 @@ fn run_main_routine(values: &mut [u64]) {
 }
 
 fn check_prime(num: u64, primes: &[u64]) -> bool {
-    let bound = upper_int_sqrt(num);
+    // Any composite number has a prime factor not larger than its square root.
+    let bound = num.isqrt();
     for &p in primes {
         if p > bound {
             break;
         }
 
-        if (num % p) == 0 {
+        if num.is_multiple_of(p) {
             return false;
         }
     }
 
     true
 }
-
-fn upper_int_sqrt(num: u64) -> u64 {
-    let mut l = 0;
-    let mut r = num + 1;
-
-    // Loop invariant: l < sqrt(num) <= r
-    // Termination: r - l is strictly decreasing.
-    while l != r - 1 {
-        // Avoids overflow because l < r
-        // Also note that m < r for this reason
-        // And l < m as r - l > 1
-        let m = l + (r - l) / 2;
-        if m * m < num {
-            // preserves l < r
-            l = m;
-        } else {
-            r = m;
-        }
-    }
-
-    r
-}
@@ -21,18 +21,27 @@ use core::ffi::c_int as RawFd;
 extern crate alloc;
 
 mod listenfd;
-// FIXME: tried, but not as useful as intended. There are a few types we use in interfaces and
-// representations which would have to be modelled, too (for the std::env::var_os and for
-// libc::AF_UNIX / libc::sendmsg mostly).
-//
-// Hence, this module is private for now until that representation is figured out.
+// The vtable only models the calls on the shared memory file itself. The environment and the
+// notification socket (std::env::var_os, libc::AF_UNIX / libc::sendmsg) are used directly.
 mod op;
 #[cfg(all(feature = "std", feature = "libc"))]
 mod notifyfd;
 
-pub use listenfd::{ListenFd, ListenInit};
+pub use listenfd::{
+    CreateError, Error as ListenFdError, FdAction, ListenFd, ListenInit, ProcPlan,
+};
+pub use op::{Shm, ShmError, ShmVTable, Stat};
 #[cfg(all(feature = "std", feature = "libc"))]
-pub use notifyfd::NotifyFd;
+pub use notifyfd::{NotifyError, NotifyFd, NotifyFdSender};
+
+/// The name of the shared memory file descriptor, unless configured otherwise.
+///
+/// The binaries accept another name with `--fd-name` or the environment variable
+/// [`SHM_FD_NAME`], and export the variable to the command they run.
+pub const SHM_SHARED_FD: &str = "SHM_SHARED_FD";
+
+/// The environment variable which overrides the name of the shared memory file descriptor.
+pub const SHM_FD_NAME: &str = "SHM_FD_NAME";
 
 /// A raw file descriptor, opened for us by the environment.
 ///
@@ -44,27 +53,60 @@ pub struct SharedFd {
 impl SharedFd {
     /// Import a shared file descriptor based on environment variable `SHM_SHARED_FDS`.
     ///
+    /// The descriptor is found by the name in [`SHM_FD_NAME`] if that variable is set, and
+    /// otherwise by [`SHM_SHARED_FD`].
+    ///
     /// # Safety
     /// Caller asserts that the environment variable has been set to a file descriptor that is not
     /// owned by any other resource.
     #[cfg(all(feature = "std", feature = "libc"))]
     pub unsafe fn from_env() -> Option<Self> {
         let listen = ListenFd::new()?.ok()?;
-        Self::from_listen(&listen)
+        match std::env::var(SHM_FD_NAME) {
+            Ok(name) => Self::from_listen_named(&listen, &name),
+            Err(_) => Self::from_listen(&listen),
+        }
     }
 
     /// Import a shared file descriptor based on the contents that would be in the environment variable `SHM_SHARED_FDS`.
-    #[cfg(all(feature = "libc"))]
+    ///
+    /// # Safety
+    /// Caller asserts that the passed description refers to file descriptors that are not owned
+    /// by any other resource.
+    #[cfg(feature = "libc")]
     pub unsafe fn from_listen(var: &ListenFd) -> Option<Self> {
-        let num = var.names.iter().position(|v|v == "SHM_SHARED_FD")?;
-        let fd: RawFd = var.fd_base + num as RawFd;
+        Self::from_listen_named(var, SHM_SHARED_FD)
+    }
 
-        if -1 == (op::ShmVTable::new_libc().fstat)(fd, None) {
-            // FIXME: Report that error?
-            return None;
-        }
+    /// Import the first valid shared file descriptor passed with the given name.
+    ///
+    /// # Safety
+    /// Caller asserts that the passed description refers to file descriptors that are not owned
+    /// by any other resource.
+    #[cfg(feature = "libc")]
+    pub unsafe fn from_listen_named(var: &ListenFd, name: &str) -> Option<Self> {
+        Self::all_named(var, name).next()
+    }
+
+    /// Import all shared file descriptors passed with the given name.
+    ///
+    /// Descriptors which do not refer to an open file are skipped.
+    ///
+    /// # Safety
+    /// Caller asserts that the passed description refers to file descriptors that are not owned
+    /// by any other resource.
+    #[cfg(feature = "libc")]
+    pub unsafe fn all_named<'lt>(
+        var: &'lt ListenFd,
+        name: &'lt str,
+    ) -> impl Iterator<Item = Self> + 'lt {
+        let fstat = op::ShmVTable::new_libc().fstat;
 
-        Some(SharedFd { fd })
+        var.iter()
+            .filter(move |&(fd_name, _)| fd_name == name)
+            // FIXME: Report that error?
+            .filter(move |&(_, fd)| -1 != fstat(fd, None))
+            .map(|(_, fd)| SharedFd { fd })
     }
 
     /// Open the file descriptor.
@@ -79,6 +121,39 @@ impl SharedFd {
         memfile::MemFile::from_file(fd).map_err(|err| err.into_error())
     }
 
+    /// Check that the file descriptor still refers to an open, regular file.
+    ///
+    /// Anonymous memory files are regular files, too. A descriptor that was closed or that now
+    /// refers to another kind of file, such as a socket, is not valid.
+    #[cfg(feature = "libc")]
+    pub fn is_valid(&self) -> bool {
+        op::Shm::new()
+            .stat(self)
+            .is_ok_and(|stat| stat.is_regular_file())
+    }
+
+    /// Query the current size of the file, in bytes.
+    #[cfg(feature = "libc")]
+    pub fn size(&self) -> Result<u64, ShmError> {
+        let stat = op::Shm::new().stat(self)?;
+        Ok(stat.st_size as u64)
+    }
+
+    /// Query the ID of the device containing the file.
+    #[cfg(feature = "libc")]
+    pub fn device_id(&self) -> Result<u64, ShmError> {
+        op::Shm::new().device_id(self)
+    }
+
+    /// Query the device and inode number of the underlying file.
+    ///
+    /// Compare the result across restarts to check that a descriptor refers to the same memory
+    /// object as before, instead of a freshly created file holding no prior state.
+    #[cfg(feature = "std")]
+    pub fn identity(&self, shm: &Shm) -> std::io::Result<(u64, u64)> {
+        Ok(shm.stat(self)?.identity())
+    }
+
     pub fn as_raw_fd(&self) -> RawFd {
         self.fd
     }
@@ -88,6 +163,29 @@ impl SharedFd {
         let _this = core::mem::ManuallyDrop::new(self);
         _this.fd
     }
+
+    /// Take ownership of the file descriptor, closing it when the result is dropped.
+    #[cfg(feature = "std")]
+    pub fn into_owned(self) -> std::os::fd::OwnedFd {
+        use std::os::fd::FromRawFd;
+        let fd = self.into_raw_fd();
+        // Safety: the `SharedFd` owned the descriptor and has been consumed without closing it.
+        unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) }
+    }
+
+    /// Borrow the file descriptor, for use with I/O-safe interfaces.
+    #[cfg(feature = "std")]
+    pub fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        // Safety: the descriptor stays open while `self` is alive, it is never closed by us.
+        unsafe { std::os::fd::BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::os::fd::AsFd for SharedFd {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        SharedFd::as_fd(self)
+    }
 }
 
 #[cfg(feature = "std")]
@@ -96,3 +194,107 @@ impl std::os::unix::io::AsRawFd for SharedFd {
         self.fd
     }
 }
+
+#[cfg(feature = "std")]
+impl std::os::unix::io::FromRawFd for SharedFd {
+    /// Wrap a file descriptor, which will not be closed by the `SharedFd`.
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        SharedFd { fd }
+    }
+}
+
+#[cfg(all(test, feature = "std", feature = "libc"))]
+mod tests {
+    use super::{ListenFd, SharedFd, Shm};
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    #[test]
+    fn into_owned_closes() {
+        // A high descriptor number, which is not allocated by concurrent tests.
+        const RAW: i32 = 1010;
+
+        let file = std::fs::File::open("/dev/null").unwrap();
+        assert_eq!(RAW, unsafe { libc::dup2(file.as_raw_fd(), RAW) });
+
+        let shared = unsafe { SharedFd::from_raw_fd(RAW) };
+        assert_eq!(shared.as_fd().as_raw_fd(), RAW);
+
+        let owned = shared.into_owned();
+        assert_eq!(owned.as_raw_fd(), RAW);
+        drop(owned);
+        assert_eq!(-1, unsafe { libc::fcntl(RAW, libc::F_GETFD) });
+    }
+
+    #[test]
+    fn identity_of_dup() {
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let other = std::fs::File::open("/dev/zero").unwrap();
+        let shm = Shm::new();
+
+        let fd = SharedFd { fd: file.as_raw_fd() };
+        let dup = SharedFd { fd: unsafe { libc::dup(file.as_raw_fd()) } };
+        let different = SharedFd { fd: other.as_raw_fd() };
+
+        let identity = fd.identity(&shm).unwrap();
+        assert_eq!(identity, dup.identity(&shm).unwrap());
+        assert_ne!(identity, different.identity(&shm).unwrap());
+        assert!(shm.stat(&fd).unwrap().st_nlink > 0);
+
+        drop(dup.into_owned());
+        let _ = fd.into_raw_fd();
+        let _ = different.into_raw_fd();
+    }
+
+    #[test]
+    fn vtable_from_functions() {
+        use crate::{ShmVTable, Stat};
+
+        fn fstat(_: i32, stat: Option<&mut Stat>) -> i32 {
+            if let Some(stat) = stat {
+                stat.st_size = 42;
+            }
+            0
+        }
+
+        let libc = ShmVTable::new_libc();
+        let vtable = ShmVTable::new(fstat, libc.close, libc.errno);
+        assert!(vtable.mmap.is_none() && vtable.munmap.is_none());
+
+        let shm = unsafe { Shm::new_unchecked(vtable) };
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let fd = SharedFd { fd: file.as_raw_fd() };
+        assert_eq!(shm.stat(&fd).unwrap().st_size, 42);
+        let _ = fd.into_raw_fd();
+    }
+
+    #[test]
+    fn all_named_skips_closed() {
+        // High descriptor numbers, which are not allocated by concurrent tests.
+        const BASE: i32 = 1000;
+
+        let file = std::fs::File::open("/dev/null").unwrap();
+        for fd in [BASE, BASE + 2] {
+            assert_eq!(fd, unsafe { libc::dup2(file.as_raw_fd(), fd) });
+        }
+
+        let listen = ListenFd {
+            fd_base: BASE,
+            fd_len: 4,
+            names: ["state", "state", "state", "scratch"].map(String::from).to_vec(),
+        };
+
+        let found: Vec<_> = unsafe { SharedFd::all_named(&listen, "state") }
+            .map(|fd| fd.as_raw_fd())
+            .collect();
+        assert_eq!(found, [BASE, BASE + 2]);
+
+        let named = unsafe { SharedFd::from_listen_named(&listen, "state") };
+        assert_eq!(named.map(|fd| fd.as_raw_fd()), Some(BASE));
+        assert!(unsafe { SharedFd::from_listen_named(&listen, "scratch") }.is_none());
+        assert!(unsafe { SharedFd::from_listen(&listen) }.is_none());
+
+        for fd in [BASE, BASE + 2] {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
@@ -31,6 +31,7 @@ pub struct ListenFd {
 /// the file was captured by finding its name in the `LISTN_FDNAMES` array then it is computed and
 /// in-bounds of the passed array. If the file was not captured, it is _added_ to the `listen`
 /// information and its new hypothetical descriptor is stored in `target`.
+#[non_exhaustive]
 pub struct ListenInit<F> {
     /// The originally, potentially modified, passed `ListenFd`.
     pub listen: ListenFd,
@@ -40,24 +41,116 @@ pub struct ListenInit<F> {
     ///
     /// See struct description.
     pub target: RawFd,
-    _inner: (),
 }
 
+/// The changes to a child process, as computed by [`ListenInit::plan`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcPlan {
+    /// The value to set for `LISTEN_FDS`.
+    pub listen_fds: String,
+    /// The value to set for `LISTEN_FDNAMES`.
+    pub listen_fdnames: String,
+    /// The operation to make the file available at its target descriptor, if we own the file.
+    pub fd: Option<FdAction>,
+}
+
+/// An operation on file descriptors, performed in the child before `exec`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FdAction {
+    /// The file already has the target descriptor, only clear its close-on-exec flag.
+    KeepOpen(RawFd),
+    /// Duplicate the file descriptor onto the target descriptor.
+    Dup { from: RawFd, to: RawFd },
+}
+
+/// An error interpreting the environment variables describing passed file descriptors.
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
+    /// `LISTEN_PID` is not a number, or not the ID of this process.
     BadPid,
+    /// `LISTEN_FDS` is not a valid count of file descriptors.
     BadFd,
+    /// `LISTEN_FDNAMES` is not valid UTF-8.
     BadNames,
 }
 
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::BadPid => write!(f, "LISTEN_PID does not refer to this process"),
+            Error::BadFd => write!(f, "LISTEN_FDS is not a valid file descriptor count"),
+            Error::BadNames => write!(f, "LISTEN_FDNAMES is not valid UTF-8"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// An error finding or creating a file descriptor with [`ListenInit::named_or_try_create_verified`].
+#[derive(Debug)]
+pub enum CreateError<R> {
+    /// The name was not passed, and creating a new file failed.
+    Create(R),
+    /// The descriptor passed with the name failed verification, and creating a replacement
+    /// failed.
+    Unverified {
+        /// The descriptor that failed verification.
+        fd: RawFd,
+        /// The error creating the replacement.
+        error: R,
+    },
+}
+
+impl<R: core::fmt::Display> core::fmt::Display for CreateError<R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CreateError::Create(error) => write!(f, "Failed to create a file: {error}"),
+            CreateError::Unverified { fd, error } => write!(
+                f,
+                "File descriptor {fd} failed verification, and creating a replacement failed: {error}"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::error::Error> std::error::Error for CreateError<R> {}
+
+impl FdAction {
+    /// Perform the operation in the current process.
+    ///
+    /// # Safety
+    ///
+    /// Modifies the process file descriptor table, the caller must ensure that the target
+    /// descriptor is not otherwise owned. Intended to be called only in a `pre_exec` hook.
+    #[cfg(feature = "std")]
+    unsafe fn apply(self) -> Result<(), std::io::Error> {
+        match self {
+            FdAction::KeepOpen(rawfd) => {
+                // We adjust the flags to not close-on-exec.
+                if -1 == unsafe { libc::fcntl(rawfd, libc::F_SETFD, 0) } {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            FdAction::Dup { from, to } => {
+                if -1 == unsafe { libc::dup2(from, to) } {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 // https://github.com/systemd/systemd/blob/414ae39821f0c103b076fc5f7432f827e0e79765/src/libsystemd/sd-daemon/sd-daemon.c#L92-L129
 impl ListenFd {
     /// Capture and translate the systemd standard environment variables.
     #[cfg(all(feature = "std", feature = "libc"))]
     pub fn new() -> Option<Result<Self, Error>> {
-        let Some(count) = std::env::var_os("LISTEN_FDS") else {
-            return None;
-        };
+        let count = std::env::var_os("LISTEN_FDS")?;
 
         if let Some(pid) = std::env::var_os("LISTEN_PID") {
             let Some(pid) = pid.to_str() else {
@@ -71,14 +164,14 @@ impl ListenFd {
             if pid != unsafe { libc::getpid() } {
                 return Some(Err(Error::BadPid));
             }
-        } else {};
+        }
 
         let Ok(count): Result<RawFd, _> = ({
             count
                 .to_str()
                 .map_or_else(
                     || Err(core::num::IntErrorKind::InvalidDigit),
-                    |st| st.parse::<RawFd>().map_err(|e| e.kind().clone()),
+                    |st| st.parse::<RawFd>().map_err(|e| *e.kind()),
                 )
         }) else {
             return Some(Err(Error::BadFd));
@@ -104,6 +197,43 @@ impl ListenFd {
 
         Some(Ok(listen))
     }
+
+    /// Iterate over all passed file descriptors, with their names.
+    ///
+    /// A descriptor without a name, because fewer names than descriptors were passed, is paired
+    /// with an empty name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, RawFd)> + '_ {
+        (0..self.fd_len.max(0)).map(move |idx| {
+            let name = self.names.get(idx as usize).map_or("", String::as_str);
+            (name, self.fd_base + idx)
+        })
+    }
+
+    /// Check that a passed file descriptor refers to a regular file.
+    ///
+    /// Anonymous memory files are regular files, too. A stale descriptor that was closed or that
+    /// refers to another kind of file, such as a socket, fails this check.
+    #[cfg(feature = "libc")]
+    pub fn is_regular_file(fd: RawFd) -> bool {
+        crate::op::Shm::new()
+            .stat(&crate::SharedFd { fd })
+            .is_ok_and(|stat| stat.is_regular_file())
+    }
+
+    /// Check that a name can be passed in `LISTEN_FDNAMES` and to the file descriptor store.
+    ///
+    /// Names are joined with `:` and sent to the store in newline separated messages. The empty
+    /// name is indistinguishable from an unnamed descriptor.
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty() && name.len() <= 255 && !name.contains(':') && !name.contains(char::is_control)
+    }
+
+    /// Find the first file descriptor passed with the given name.
+    pub fn by_name(&self, name: &str) -> Option<RawFd> {
+        self.iter()
+            .find(|&(fd_name, _)| fd_name == name)
+            .map(|(_, fd)| fd)
+    }
 }
 
 impl<F> ListenInit<F> {
@@ -113,9 +243,25 @@ impl<F> ListenInit<F> {
         fd_name: &str,
         with: impl FnOnce() -> Result<F, R>,
     ) -> Result<Self, R> {
+        Self::named_or_try_create_verified(this, fd_name, |_| true, with).map_err(|err| match err {
+            CreateError::Create(err) | CreateError::Unverified { error: err, .. } => err,
+        })
+    }
+
+    /// Derive a new ListenFd setup, finds a file descriptor that passes `verify` or adds one.
+    ///
+    /// A passed file descriptor of the name which fails verification, for instance a stale
+    /// descriptor that is not a memory file, is replaced by a newly created file at the same
+    /// target. See [`ListenFd::is_regular_file`] for a common verification.
+    pub fn named_or_try_create_verified<R>(
+        this: Option<ListenFd>,
+        fd_name: &str,
+        verify: impl FnOnce(RawFd) -> bool,
+        with: impl FnOnce() -> Result<F, R>,
+    ) -> Result<Self, CreateError<R>> {
         match this {
             None => {
-                let file = with()?;
+                let file = with().map_err(CreateError::Create)?;
                 let target = 3;
 
                 let listen = ListenFd {
@@ -128,7 +274,6 @@ impl<F> ListenInit<F> {
                     listen,
                     file: Some(file),
                     target,
-                    _inner: (),
                 })
             }
             Some(listen) => {
@@ -141,10 +286,16 @@ impl<F> ListenInit<F> {
                 let (target, file);
                 if let Some(position) = position {
                     target = listen.fd_base + position as RawFd;
-                    // FIXME: verify that this is a memfile?
-                    file = None;
+
+                    if verify(target) {
+                        file = None;
+                    } else {
+                        let _file = with()
+                            .map_err(|error| CreateError::Unverified { fd: target, error })?;
+                        file = Some(_file);
+                    }
                 } else {
-                    let _file = with()?;
+                    let _file = with().map_err(CreateError::Create)?;
                     file = Some(_file);
 
                     listen.names.push(fd_name.into());
@@ -156,27 +307,57 @@ impl<F> ListenInit<F> {
                     listen,
                     file,
                     target,
-                    _inner: (),
                 })
             }
         }
     }
 
     /// Notify systemd, if the file descriptor was not present.
+    ///
+    /// The notification socket is only borrowed, it remains usable for other notifications.
     #[cfg(feature = "std")]
-    pub fn maybe_notify(&self, notify: NotifyFd, fd_name: &str)
+    pub fn maybe_notify(&self, notify: &NotifyFd, fd_name: &str)
         -> Result<(), std::io::Error>
         where F: std::os::fd::AsRawFd
     {
         if let Some(newfile) = &self.file.as_ref() {
             let rawfd = newfile.as_raw_fd();
             let state = format!("FDSTORE=1\nFDNAME={fd_name}");
-            notify.notify_with_fds(&state, core::slice::from_ref(&rawfd))
+            notify
+                .try_clone()?
+                .into_fd_sender()
+                .notify_with_fds(&state, core::slice::from_ref(&rawfd))
         } else {
             Ok(())
         }
     }
 
+    /// Compute the changes which [`Self::wrap_proc`] applies to a child process.
+    ///
+    /// This does not modify any process state, the plan only describes the environment and the
+    /// file descriptor operation that would be performed before `exec`.
+    #[cfg(feature = "std")]
+    pub fn plan(&self) -> ProcPlan
+        where F: std::os::fd::AsRawFd,
+    {
+        let rawfd = self.file.as_ref().map(|v| v.as_raw_fd());
+        let target = self.target;
+
+        let fd = rawfd.map(|rawfd| {
+            if rawfd == target {
+                FdAction::KeepOpen(rawfd)
+            } else {
+                FdAction::Dup { from: rawfd, to: target }
+            }
+        });
+
+        ProcPlan {
+            listen_fds: self.listen.fd_len.to_string(),
+            listen_fdnames: self.listen.names.join(":"),
+            fd,
+        }
+    }
+
     /// Modify a command such that it copies the file descriptors at the appropriate location.
     ///
     /// # Safety
@@ -187,29 +368,15 @@ impl<F> ListenInit<F> {
     pub unsafe fn wrap_proc(&self, proc: &mut std::process::Command)
         where F: std::os::fd::AsRawFd,
     {
-        let rawfd = self.file.as_ref().map(|v| v.as_raw_fd());
-
-        proc.env("LISTEN_FDS", self.listen.fd_len.to_string());
-        proc.env("LISTEN_FDNAMES", self.listen.names.join(":"));
-        let target = self.target;
+        let plan = self.plan();
 
-        unsafe {
-            proc.pre_exec(move || {
-                if let Some(rawfd) = rawfd {
-                    if rawfd == target {
-                        // We adjust the flags to not close-on-exec.
-                        if -1 == libc::fcntl(rawfd, libc::F_SETFD, 0) {
-                            return Err(std::io::Error::last_os_error());
-                        }
-                    } else {
-                        if -1 == libc::dup2(rawfd, target) {
-                            return Err(std::io::Error::last_os_error());
-                        }
-                    }
-                }
+        proc.env("LISTEN_FDS", &plan.listen_fds);
+        proc.env("LISTEN_FDNAMES", &plan.listen_fdnames);
 
-                Ok(())
-            });
+        if let Some(action) = plan.fd {
+            unsafe {
+                proc.pre_exec(move || action.apply());
+            }
         }
     }
 
@@ -244,3 +411,139 @@ impl<F> ListenInit<F> {
         }
     }
 }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{CreateError, Error, FdAction, ListenFd, ListenInit};
+    use std::os::fd::AsRawFd;
+
+    fn devnull() -> std::fs::File {
+        std::fs::File::open("/dev/null").unwrap()
+    }
+
+    #[test]
+    fn plan_without_listen() {
+        let init = ListenInit::named_or_try_create(None, "shm", || {
+            Ok::<_, ()>(devnull())
+        }).unwrap();
+
+        let rawfd = init.file.as_ref().unwrap().as_raw_fd();
+        let plan = init.plan();
+
+        assert_eq!(plan.listen_fds, "1");
+        assert_eq!(plan.listen_fdnames, "shm");
+
+        if rawfd == 3 {
+            assert_eq!(plan.fd, Some(FdAction::KeepOpen(3)));
+        } else {
+            assert_eq!(plan.fd, Some(FdAction::Dup { from: rawfd, to: 3 }));
+        }
+    }
+
+    #[test]
+    fn plan_found_in_listen() {
+        let listen = ListenFd {
+            fd_base: 3,
+            fd_len: 2,
+            names: vec!["a".into(), "shm".into()],
+        };
+
+        let init = ListenInit::<std::fs::File>::named_or_try_create(Some(listen), "shm", || {
+            Err(())
+        }).unwrap();
+
+        assert_eq!(init.target, 4);
+        let plan = init.plan();
+
+        assert_eq!(plan.listen_fds, "2");
+        assert_eq!(plan.listen_fdnames, "a:shm");
+        assert_eq!(plan.fd, None);
+    }
+
+    #[test]
+    fn plan_appended_to_listen() {
+        let listen = ListenFd {
+            fd_base: 3,
+            fd_len: 2,
+            names: vec!["a".into(), "b".into()],
+        };
+
+        let init = ListenInit::named_or_try_create(Some(listen), "shm", || {
+            Ok::<_, ()>(devnull())
+        }).unwrap();
+
+        let rawfd = init.file.as_ref().unwrap().as_raw_fd();
+        assert_eq!(init.target, 5);
+        let plan = init.plan();
+
+        assert_eq!(plan.listen_fds, "3");
+        assert_eq!(plan.listen_fdnames, "a:b:shm");
+
+        if rawfd == 5 {
+            assert_eq!(plan.fd, Some(FdAction::KeepOpen(5)));
+        } else {
+            assert_eq!(plan.fd, Some(FdAction::Dup { from: rawfd, to: 5 }));
+        }
+    }
+
+    #[test]
+    fn error_names_variable() {
+        assert!(Error::BadPid.to_string().contains("LISTEN_PID"));
+        assert!(Error::BadFd.to_string().contains("LISTEN_FDS"));
+        assert!(Error::BadNames.to_string().contains("LISTEN_FDNAMES"));
+
+        let boxed: Box<dyn std::error::Error> = Box::new(Error::BadFd);
+        assert_eq!(boxed.to_string(), Error::BadFd.to_string());
+    }
+
+    #[test]
+    fn valid_names() {
+        assert!(ListenFd::is_valid_name("SHM_SHARED_FD"));
+        assert!(ListenFd::is_valid_name("state-2"));
+        for name in ["", "a:b", "a\nb", &"x".repeat(256)] {
+            assert!(!ListenFd::is_valid_name(name), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn iter_names() {
+        let listen = ListenFd {
+            fd_base: 3,
+            fd_len: 3,
+            names: vec!["shm".into(), "socket".into()],
+        };
+
+        let all: Vec<_> = listen.iter().collect();
+        assert_eq!(all, [("shm", 3), ("socket", 4), ("", 5)]);
+        assert_eq!(listen.by_name("socket"), Some(4));
+        assert_eq!(listen.by_name("missing"), None);
+    }
+
+    #[test]
+    fn verified_replaces_stale() {
+        let listen = || ListenFd {
+            fd_base: 3,
+            fd_len: 2,
+            names: vec!["a".into(), "shm".into()],
+        };
+
+        let init = ListenInit::named_or_try_create_verified(Some(listen()), "shm", |fd| {
+            assert_eq!(fd, 4);
+            false
+        }, || Ok::<_, ()>(devnull())).unwrap();
+
+        assert!(init.file.is_some());
+        assert_eq!(init.target, 4);
+        assert_eq!(init.plan().listen_fdnames, "a:shm");
+
+        let init = ListenInit::named_or_try_create_verified(Some(listen()), "shm", |_| true, || {
+            Ok::<_, ()>(devnull())
+        }).unwrap();
+        assert!(init.file.is_none());
+
+        let err = ListenInit::<std::fs::File>::named_or_try_create_verified(
+            Some(listen()), "shm", |_| false, || Err(()),
+        ).err();
+        assert!(matches!(err, Some(CreateError::Unverified { fd: 4, error: () })));
+    }
+}
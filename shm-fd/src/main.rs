@@ -1,15 +1,39 @@
-use shm_fd::{ListenFd, ListenInit, NotifyFd};
+use shm_fd::{ListenFd, ListenInit, NotifyFd, SHM_FD_NAME, SHM_SHARED_FD};
 use memfile::MemFile;
 
 use std::os::fd::AsRawFd;
 use std::process::Command;
 
 fn main() {
-    // FIXME: allow customization.
-    let fd_name = "SHM_SHARED_FD";
+    let mut args = std::env::args_os().skip(1).peekable();
 
-    let mut args = std::env::args_os().skip(1);
+    let fd_name = if args.next_if(|arg| arg == "--fd-name").is_some() {
+        args.next()
+            .expect("no name given to --fd-name")
+            .into_string()
+            .expect("file descriptor name must be UTF-8")
+    } else {
+        std::env::var(SHM_FD_NAME).unwrap_or_else(|_| SHM_SHARED_FD.into())
+    };
+
+    assert!(
+        ListenFd::is_valid_name(&fd_name),
+        "invalid file descriptor name {fd_name:?}, it must not be empty or contain `:`"
+    );
+
+    let fd_name = fd_name.as_str();
     let cmd = args.next().expect("no given");
+
+    // Discard the state on purpose, so the next start of the service begins with a new file.
+    if cmd == "--discard" {
+        let notify = NotifyFd::new()
+            .expect("no notify socket to discard the shm-file from")
+            .expect("failed to open notify socket");
+        notify.fdstore_remove(fd_name)
+            .expect("failed to remove the shm-file from the socket store");
+        return;
+    }
+
     let args: Vec<_> = args.collect();
 
     let listen = ListenFd::new()
@@ -20,9 +44,10 @@ fn main() {
         .transpose()
         .expect("failed to open notify socket");
 
-    let init = ListenInit::<MemFile>::named_or_try_create(
+    let init = ListenInit::<MemFile>::named_or_try_create_verified(
         listen,
         fd_name,
+        ListenFd::is_regular_file,
         || MemFile::create_sealable("persistent"),
     ).expect("failed to initialized shm-file");
 
@@ -35,7 +60,7 @@ fn main() {
         }
 
         // If we created a new file descriptor, pass it to systemd.
-        init.maybe_notify(notify, fd_name)
+        init.maybe_notify(&notify, fd_name)
             .expect("failed to setup socket store");
     }
 
@@ -45,6 +70,7 @@ fn main() {
 
     let mut proc = Command::new(&cmd);
     proc.args(&args);
+    proc.env(SHM_FD_NAME, fd_name);
     // Safety: we promise the file descriptor is safe to clone and not-close-on-exec in the child.
     unsafe { init.wrap_proc(&mut proc) }
 
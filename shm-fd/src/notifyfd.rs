@@ -16,13 +16,43 @@ pub struct NotifyFd {
     addr: Vec<libc::c_char>,
 }
 
+/// A notification socket reserved for passing file descriptors.
+///
+/// See [`NotifyFd::into_fd_sender`].
+pub struct NotifyFdSender {
+    inner: NotifyFd,
+}
+
+/// A failed notification, wrapped as the inner error of the returned `std::io::Error`.
+///
+/// Errors reported by the OS for the `sendmsg` call itself, i.e. messages the socket rejected, are
+/// returned as plain OS errors. Use `std::io::Error::get_ref` to inspect these details.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum NotifyError {
+    /// The state string exceeds the maximum datagram size the socket accepts (`EMSGSIZE`).
+    ///
+    /// Nothing was sent, including no file descriptors.
+    MessageTooLarge {
+        /// The length of the state string.
+        len: usize,
+    },
+    /// Only a prefix of the state string was sent.
+    Truncated {
+        /// The number of bytes that were sent.
+        sent: usize,
+        /// The length of the state string.
+        len: usize,
+        /// If file descriptors were passed with the truncated message.
+        fds_sent: bool,
+    },
+}
+
 // https://github.com/systemd/systemd/blob/414ae39821f0c103b076fc5f7432f827e0e79765/src/libsystemd/sd-daemon/sd-daemon.c#L454-L598
 impl NotifyFd {
     /// Open the notification socket, if configured in the environment.
     pub fn new() -> Option<Result<Self, std::io::Error>> {
-        let Some(addr) = env::var_os("NOTIFY_SOCKET") else {
-            return None;
-        };
+        let addr = env::var_os("NOTIFY_SOCKET")?;
 
         Self::from_env(addr).map(Some).transpose()
     }
@@ -34,7 +64,7 @@ impl NotifyFd {
     /// systemd documentation. Note that there's a maximum sensible length for the path named by
     /// this environment variable, as per `libc::sockaddr_un`.
     pub fn from_env(name: OsString) -> Result<Self, std::io::Error> {
-        let ty = name.as_encoded_bytes().get(0).cloned();
+        let ty = name.as_encoded_bytes().first().cloned();
 
         let name_bytes = match ty {
             Some(b'/') => {
@@ -57,21 +87,138 @@ impl NotifyFd {
         })
     }
 
-    // Consume the notify fd to send a FD notification.
-    //
-    // FIXME: That's what the c function is doing.
-    // <https://github.com/systemd/systemd/blob/414ae39821f0c103b076fc5f7432f827e0e79765/src/libsystemd/sd-daemon/sd-daemon.c#L454C12-L454C40>
-    //
-    // It's utterly confusing why we'd open a full file descriptor for every single message but oh
-    // well, here we are. The code sends the ucredentials and file descriptors as part of the
-    // *control* data, not the message data, of course, that's how you pass file descriptors, but
-    // it only sends control data once (even for streams). Thus we will only attempt at most one
-    // message with file descriptors and thus this method must consume the NotifyFd.
+    /// Send a state notification, without any file descriptors.
+    ///
+    /// The socket stays open and can be used for any number of status updates over the lifetime
+    /// of the process.
+    pub fn notify(&self, state: &str) -> Result<(), std::io::Error> {
+        self.send_raw(state, &[])
+    }
+
+    /// The recommended period of watchdog notifications, if the service manager expects them.
+    ///
+    /// Reads `WATCHDOG_USEC` and returns half of it, as recommended by systemd. Returns `None` if
+    /// the variable is not set or invalid, or if `WATCHDOG_PID` is set to another process.
+    pub fn watchdog_interval() -> Option<std::time::Duration> {
+        let usec = env::var_os("WATCHDOG_USEC")?;
+        let pid = env::var_os("WATCHDOG_PID");
+        Self::watchdog_from(&usec, pid.as_deref(), unsafe { libc::getpid() })
+    }
+
+    fn watchdog_from(
+        usec: &OsStr,
+        pid: Option<&OsStr>,
+        own_pid: libc::pid_t,
+    ) -> Option<std::time::Duration> {
+        if let Some(pid) = pid {
+            let pid: libc::pid_t = pid.to_str()?.parse().ok()?;
+
+            if pid != own_pid {
+                return None;
+            }
+        }
+
+        let usec: u64 = usec.to_str()?.parse().ok().filter(|&usec| usec > 0)?;
+        Some(std::time::Duration::from_micros(usec / 2))
+    }
+
+    /// Notify the service manager that the service is alive, `WATCHDOG=1`.
+    ///
+    /// Send this periodically, see [`NotifyFd::watchdog_interval`].
+    pub fn notify_watchdog(&self) -> Result<(), std::io::Error> {
+        self.notify("WATCHDOG=1")
+    }
+
+    /// Notify the service manager that startup finished, `READY=1`.
+    pub fn notify_ready(&self) -> Result<(), std::io::Error> {
+        self.notify("READY=1")
+    }
+
+    /// Notify the service manager that the service is reloading its configuration.
+    ///
+    /// Sends `RELOADING=1` with the current `MONOTONIC_USEC`, as required by services of
+    /// `Type=notify-reload`. Send [`NotifyFd::notify_ready`] once the reload is complete.
+    pub fn notify_reloading(&self) -> Result<(), std::io::Error> {
+        let mut now: libc::timespec = unsafe { core::mem::zeroed() };
+        if -1 == unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let usec = now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000;
+        self.notify(&format!("RELOADING=1\nMONOTONIC_USEC={usec}"))
+    }
+
+    /// Notify the service manager that the service is shutting down, `STOPPING=1`.
+    pub fn notify_stopping(&self) -> Result<(), std::io::Error> {
+        self.notify("STOPPING=1")
+    }
+
+    /// Tell the service manager the main process of the service, `MAINPID=`.
+    ///
+    /// Like all notifications without file descriptors this does not consume the socket, unlike
+    /// [`NotifyFd::notify_with_fds`].
+    pub fn notify_mainpid(&self, pid: libc::pid_t) -> Result<(), std::io::Error> {
+        self.notify(&format!("MAINPID={pid}"))
+    }
+
+    /// Publish a free-form status line, `STATUS=`.
+    ///
+    /// The message must be a single line, each line of a notification is one assignment. A message
+    /// with a newline is rejected with `InvalidInput`. This does not consume the socket.
+    pub fn notify_status(&self, msg: &str) -> Result<(), std::io::Error> {
+        if msg.contains('\n') {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "status must not contain a newline",
+            ));
+        }
+
+        self.notify(&format!("STATUS={msg}"))
+    }
+
+    /// Remove the file descriptors of the given name from the service manager's store.
+    ///
+    /// Sends `FDSTOREREMOVE=1` with the `FDNAME`, without passing any file descriptors. Use this
+    /// when state is intentionally discarded, so that it is not passed to the next start of the
+    /// service. Names containing `:` or a newline are rejected with `InvalidInput`.
+    pub fn fdstore_remove(&self, fd_name: &str) -> Result<(), std::io::Error> {
+        if fd_name.contains([':', '\n']) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "file descriptor name must not contain `:` or a newline",
+            ));
+        }
+
+        self.notify(&format!("FDSTOREREMOVE=1\nFDNAME={fd_name}"))
+    }
+
+    /// Open another handle to the same notification socket.
+    pub fn try_clone(&self) -> Result<Self, std::io::Error> {
+        Ok(NotifyFd {
+            fd: self.fd.try_clone()?,
+            addr: self.addr.clone(),
+        })
+    }
+
+    /// Reserve this socket for a single notification passing file descriptors.
+    ///
+    /// Use [`NotifyFd::try_clone`] first to keep a handle for other notifications.
+    pub fn into_fd_sender(self) -> NotifyFdSender {
+        NotifyFdSender { inner: self }
+    }
+
+    /// Consume the notify fd to send a FD notification.
+    ///
+    /// This is a shorthand for [`NotifyFd::into_fd_sender`] and [`NotifyFdSender::notify_with_fds`].
     pub fn notify_with_fds(
         self,
         state: &str,
         fds: &[RawFd]
     ) -> Result<(), std::io::Error> {
+        self.into_fd_sender().notify_with_fds(state, fds)
+    }
+
+    fn send_raw(&self, state: &str, fds: &[RawFd]) -> Result<(), std::io::Error> {
         let mut hdr: libc::msghdr = unsafe { core::mem::zeroed::<libc::msghdr>() };
         let mut iov: libc::iovec = unsafe { core::mem::zeroed::<libc::iovec>() };
         let mut addr: libc::sockaddr_un = unsafe { core::mem::zeroed::<libc::sockaddr_un>() };
@@ -129,13 +276,190 @@ impl NotifyFd {
         };
 
         if -1 == sent {
-            return Err(std::io::Error::last_os_error());
+            let err = std::io::Error::last_os_error();
+
+            if err.raw_os_error() == Some(libc::EMSGSIZE) {
+                let len = state.len();
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    NotifyError::MessageTooLarge { len },
+                ));
+            }
+
+            return Err(err);
         }
 
         if sent as usize != state.len() {
-            return Err(std::io::ErrorKind::InvalidData)?;
+            // Control data is delivered with the first byte of the message.
+            let sent = sent as usize;
+            let fds_sent = !fds.is_empty() && sent > 0;
+
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                NotifyError::Truncated { sent, len: state.len(), fds_sent },
+            ));
         }
 
         Ok(())
     }
 }
+
+impl NotifyFdSender {
+    // Consume the notify fd to send a FD notification.
+    //
+    // FIXME: That's what the c function is doing.
+    // <https://github.com/systemd/systemd/blob/414ae39821f0c103b076fc5f7432f827e0e79765/src/libsystemd/sd-daemon/sd-daemon.c#L454C12-L454C40>
+    //
+    // It's utterly confusing why we'd open a full file descriptor for every single message but oh
+    // well, here we are. The code sends the ucredentials and file descriptors as part of the
+    // *control* data, not the message data, of course, that's how you pass file descriptors, but
+    // it only sends control data once (even for streams). Thus we will only attempt at most one
+    // message with file descriptors and thus this method must consume the sender.
+    pub fn notify_with_fds(
+        self,
+        state: &str,
+        fds: &[RawFd]
+    ) -> Result<(), std::io::Error> {
+        self.inner.send_raw(state, fds)
+    }
+}
+
+impl core::fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            NotifyError::MessageTooLarge { len } => write!(
+                f,
+                "Notification of {len} bytes exceeds the datagram size of the socket"
+            ),
+            NotifyError::Truncated { sent, len, fds_sent } => write!(
+                f,
+                "Notification truncated, sent {sent} of {len} bytes ({})",
+                if *fds_sent { "file descriptors sent" } else { "no file descriptors sent" }
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{NotifyError, NotifyFd};
+    use std::os::unix::net::UnixDatagram;
+
+    fn bound_socket(name: &str) -> (std::path::PathBuf, UnixDatagram) {
+        let path = std::env::temp_dir()
+            .join(format!("shm-fd-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        (path, socket)
+    }
+
+    #[test]
+    fn notify_roundtrip() {
+        let (path, socket) = bound_socket("roundtrip");
+        let notify = NotifyFd::from_env(path.clone().into()).unwrap();
+
+        notify.notify("READY=1").unwrap();
+        notify.notify("STATUS=ok").unwrap();
+
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STATUS=ok");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn notify_lifecycle() {
+        let (path, socket) = bound_socket("lifecycle");
+        let notify = NotifyFd::from_env(path.clone().into()).unwrap();
+
+        notify.notify_ready().unwrap();
+        notify.notify_reloading().unwrap();
+        notify.notify_stopping().unwrap();
+
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        let len = socket.recv(&mut buf).unwrap();
+        let reloading = std::str::from_utf8(&buf[..len]).unwrap();
+        let usec = reloading.strip_prefix("RELOADING=1\nMONOTONIC_USEC=").unwrap();
+        assert!(usec.parse::<u64>().is_ok(), "{reloading}");
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STOPPING=1");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn notify_mainpid_status() {
+        let (path, socket) = bound_socket("mainpid-status");
+        let notify = NotifyFd::from_env(path.clone().into()).unwrap();
+
+        notify.notify_mainpid(42).unwrap();
+        notify.notify_status("Recovering 3 snapshots").unwrap();
+        let err = notify.notify_status("two\nlines").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"MAINPID=42");
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STATUS=Recovering 3 snapshots");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn fdstore_remove() {
+        let (path, socket) = bound_socket("fdstore-remove");
+        let notify = NotifyFd::from_env(path.clone().into()).unwrap();
+
+        notify.fdstore_remove("SHM_SHARED_FD").unwrap();
+        for name in ["a:b", "a\nb"] {
+            let err = notify.fdstore_remove(name).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"FDSTOREREMOVE=1\nFDNAME=SHM_SHARED_FD");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn watchdog_interval() {
+        use std::ffi::OsStr;
+        use std::time::Duration;
+
+        let usec = OsStr::new("3000000");
+        let interval = NotifyFd::watchdog_from(usec, None, 42);
+        assert_eq!(interval, Some(Duration::from_millis(1500)));
+        let interval = NotifyFd::watchdog_from(usec, Some(OsStr::new("42")), 42);
+        assert_eq!(interval, Some(Duration::from_millis(1500)));
+
+        assert_eq!(NotifyFd::watchdog_from(usec, Some(OsStr::new("41")), 42), None);
+        assert_eq!(NotifyFd::watchdog_from(usec, Some(OsStr::new("pid")), 42), None);
+        assert_eq!(NotifyFd::watchdog_from(OsStr::new("0"), None, 42), None);
+        assert_eq!(NotifyFd::watchdog_from(OsStr::new("1s"), None, 42), None);
+    }
+
+    #[test]
+    fn notify_too_large() {
+        let (path, _socket) = bound_socket("too-large");
+        let notify = NotifyFd::from_env(path.clone().into()).unwrap();
+
+        let state = "X".repeat(1 << 24);
+        let err = notify.notify(&state).unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        let inner = err.get_ref().and_then(|e| e.downcast_ref::<NotifyError>());
+        assert!(matches!(inner, Some(NotifyError::MessageTooLarge { len }) if *len == 1 << 24));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,21 +1,22 @@
 use super::SharedFd;
-use core::ffi::c_int;
+use core::ffi::{c_int, c_void};
 use alloc::sync::Arc;
 
 /// Interact with `shm*` and related calls.
-#[allow(dead_code)]
+///
+/// All OS interaction goes through a table of functions, see [`ShmVTable`].
+#[derive(Clone)]
 pub struct Shm {
     inner: Arc<ShmInner>,
 }
 
-#[allow(dead_code)]
 struct ShmInner {
     vtable: ShmVTable,
 }
 
 /// An error returned when interaction with a shared memory file.
-#[allow(dead_code)]
-pub struct ShmError(c_int);
+#[derive(Debug)]
+pub struct ShmError(pub(crate) c_int);
 
 /// *Fixed* type, not platform dependent.
 type OffT = i64;
@@ -23,9 +24,16 @@ type BlkSizeT = i64;
 type BlkCntT = i64;
 type TimeT = i64;
 
+/// The status of a file, as returned by `fstat`.
+///
+/// The fields have the meaning of the equally named fields of `struct stat`, with fixed types
+/// independent of the platform.
 #[non_exhaustive]
 #[derive(Default)]
 pub struct Stat {
+    pub st_dev: u64,
+    pub st_ino: u64,
+    pub st_nlink: u64,
     pub st_mode: u32,
     pub st_uid: u32,
     pub st_gid: u32,
@@ -40,6 +48,26 @@ pub struct Stat {
     pub st_ctime_nsec: i64,
 }
 
+impl Stat {
+    /// The device and inode number, which together identify the underlying file.
+    ///
+    /// Two descriptors with the same identity refer to the same file, even if they were opened
+    /// separately or passed between processes.
+    pub fn identity(&self) -> (u64, u64) {
+        (self.st_dev, self.st_ino)
+    }
+
+    /// Whether the file is a regular file, which includes anonymous memory files.
+    pub fn is_regular_file(&self) -> bool {
+        const S_IFMT: u32 = 0o170000;
+        const S_IFREG: u32 = 0o100000;
+        self.st_mode & S_IFMT == S_IFREG
+    }
+}
+
+/// The signature of [`ShmVTable::mmap`].
+type MmapFn = fn(len: usize, prot: c_int, flags: c_int, fd: c_int) -> *mut c_void;
+
 /// A table of OS functions.
 ///
 /// This enumerates the functions required to interact with the `SharedFd` object. A vtable must
@@ -48,26 +76,58 @@ pub struct Stat {
 /// table can be initialized when linking against `libc`.
 ///
 /// You're encouraged to provide your own objects here instead of hooking the functions themselves
-/// with override/linker tricks.
+/// with override/linker tricks. The table can not be constructed directly, as further functions
+/// may be added. Start from [`ShmVTable::new`] or [`ShmVTable::new_libc`] and replace the
+/// functions instead.
+///
+/// The mapping functions are optional as `shm-fd` itself never maps the file. They are provided
+/// such that crates mapping the file, such as `shm-state`, share a single table of functions.
 #[non_exhaustive]
 pub struct ShmVTable {
     pub fstat: fn(c_int, Option<&mut Stat>) -> c_int,
     pub close: fn(c_int) -> c_int,
     pub errno: fn() -> c_int,
+    /// Simplified `mmap`, creating a shared mapping of the file from its start.
+    ///
+    /// The `flags` are passed in addition to `MAP_SHARED`. Returns a null pointer on failure,
+    /// instead of `MAP_FAILED`.
+    pub mmap: Option<MmapFn>,
+    pub munmap: Option<fn(*mut c_void, usize) -> c_int>,
+    /// Write back a mapping, `msync` with `MS_SYNC`.
+    pub msync: Option<fn(*mut c_void, usize) -> c_int>,
+    pub ftruncate: Option<fn(c_int, i64) -> c_int>,
+    /// The value of `PROT_READ` passed to `mmap`.
+    pub prot_read: c_int,
+    /// The value of `PROT_WRITE` passed to `mmap`.
+    pub prot_write: c_int,
+    /// The value of `MAP_POPULATE` passed to `mmap`, `0` if not supported.
+    pub map_populate: c_int,
+    /// The value of `MAP_HUGETLB` passed to `mmap`, `0` if not supported.
+    pub map_hugetlb: c_int,
+    /// The value of `MAP_HUGE_SHIFT`, the position of the huge page size in the flags.
+    pub map_huge_shift: c_int,
 }
 
-#[allow(dead_code)]
 impl Shm {
     /// Create an `Shm` from a customized vtable.
     ///
     /// # Safety
     ///
-    /// The VTable must contain a correct pair of functions that implement the `shm*` interface.
+    /// Each function of the vtable must behave as the equally named libc function. In particular
+    /// `fstat` must only write a status when it succeeds, returning `0`, and otherwise return a
+    /// negative value with the error available from `errno`. `close` must close the descriptor
+    /// passed, as it may be called on descriptors owned by a `SharedFd`. If present, `mmap`
+    /// and `munmap` must be a matching pair and `mmap` must return a null pointer on failure.
+    ///
+    /// Without the `libc` feature, create the table with [`ShmVTable::new`] from the required
+    /// functions and set the optional ones as needed.
     pub unsafe fn new_unchecked(vtable: ShmVTable) -> Self {
         Shm {
             inner: Arc::new(ShmInner { vtable }),
         }
     }
+
+    /// Create an `Shm` calling the libc functions.
     #[cfg(feature = "libc")]
     pub fn new() -> Self {
         unsafe {
@@ -75,19 +135,89 @@ impl Shm {
         }
     }
 
+    /// The table of functions used by this instance.
+    pub fn vtable(&self) -> &ShmVTable {
+        &self.inner.vtable
+    }
+
+    /// Query the status of the file.
     pub fn stat(&self, shared: &SharedFd) -> Result<Stat, ShmError> {
         let mut stat = Stat::default();
         let inner = (self.inner.vtable.fstat)(shared.fd, Some(&mut stat));
 
         if inner < 0 {
-            return Err(ShmError((self.inner.vtable.errno)()));
+            Err(ShmError((self.inner.vtable.errno)()))
         } else {
             Ok(stat)
         }
     }
+
+    /// Query the ID of the device containing the file.
+    ///
+    /// Two files with different device IDs are on different file systems, copies between them
+    /// can not be offloaded with `copy_file_range`.
+    pub fn device_id(&self, shared: &SharedFd) -> Result<u64, ShmError> {
+        Ok(self.stat(shared)?.st_dev)
+    }
+}
+
+#[cfg(feature = "libc")]
+impl Default for Shm {
+    fn default() -> Self {
+        Shm::new()
+    }
+}
+
+impl ShmError {
+    /// The `errno` reported by the failed call.
+    pub fn raw_os_error(&self) -> c_int {
+        self.0
+    }
+}
+
+impl core::fmt::Display for ShmError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Shared memory operation failed with errno {}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ShmError {}
+
+#[cfg(feature = "std")]
+impl From<ShmError> for std::io::Error {
+    fn from(err: ShmError) -> Self {
+        std::io::Error::from_raw_os_error(err.0)
+    }
 }
 
 impl ShmVTable {
+    /// A table of the required functions, without any of the optional ones.
+    ///
+    /// The mapping functions are `None` and the `mmap` flags are zero. Set them as well to map
+    /// the file through this table, see [`Shm::new_unchecked`] for the requirements.
+    pub fn new(
+        fstat: fn(c_int, Option<&mut Stat>) -> c_int,
+        close: fn(c_int) -> c_int,
+        errno: fn() -> c_int,
+    ) -> Self {
+        ShmVTable {
+            fstat,
+            close,
+            errno,
+            mmap: None,
+            munmap: None,
+            msync: None,
+            ftruncate: None,
+            prot_read: 0,
+            prot_write: 0,
+            map_populate: 0,
+            map_hugetlb: 0,
+            map_huge_shift: 0,
+        }
+    }
+
+    /// The table of the functions as provided by libc.
     #[cfg(feature = "libc")]
     pub fn new_libc() -> Self {
         fn _fstat(fd: c_int, stat: Option<&mut Stat>) -> c_int {
@@ -100,6 +230,9 @@ impl ShmVTable {
                 let lstat = unsafe { uninit.assume_init() };
                 if let Some(stat) = stat {
                     *stat = Stat {
+                        st_dev: lstat.st_dev,
+                        st_ino: lstat.st_ino,
+                        st_nlink: lstat.st_nlink,
                         st_mode: lstat.st_mode,
                         st_uid: lstat.st_uid,
                         st_gid: lstat.st_gid,
@@ -127,10 +260,42 @@ impl ShmVTable {
             unsafe { *libc::__errno_location() }
         }
 
+        fn _mmap(len: usize, prot: c_int, flags: c_int, fd: c_int) -> *mut c_void {
+            let flags = libc::MAP_SHARED | flags;
+            let ptr = unsafe { libc::mmap(core::ptr::null_mut(), len, prot, flags, fd, 0) };
+
+            if ptr == libc::MAP_FAILED {
+                core::ptr::null_mut()
+            } else {
+                ptr
+            }
+        }
+
+        fn _munmap(addr: *mut c_void, len: usize) -> c_int {
+            unsafe { libc::munmap(addr, len) }
+        }
+
+        fn _msync(addr: *mut c_void, len: usize) -> c_int {
+            unsafe { libc::msync(addr, len, libc::MS_SYNC) }
+        }
+
+        fn _ftruncate(fd: c_int, len: i64) -> c_int {
+            unsafe { libc::ftruncate(fd, len) }
+        }
+
         ShmVTable {
             fstat: _fstat,
             close: _close_inner,
             errno: _errno,
+            mmap: Some(_mmap),
+            munmap: Some(_munmap),
+            msync: Some(_msync),
+            ftruncate: Some(_ftruncate),
+            prot_read: libc::PROT_READ,
+            prot_write: libc::PROT_WRITE,
+            map_populate: libc::MAP_POPULATE,
+            map_hugetlb: libc::MAP_HUGETLB,
+            map_huge_shift: libc::MAP_HUGE_SHIFT,
         }
     }
 }
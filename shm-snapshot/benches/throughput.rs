@@ -0,0 +1,104 @@
+//! Throughput of committing, reading, and recovering snapshots.
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use memfile::{CreateOptions, MemFile};
+use shm_snapshot::{ConfigureFile, File, Writer};
+
+const FILE_LEN: u64 = 0x100_0000;
+const ENTRIES: u64 = 0x1000;
+const DATA: u64 = 0x80_0000;
+
+fn memory_file() -> MemFile {
+    let file = CreateOptions::new()
+        .create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(FILE_LEN).unwrap();
+    file
+}
+
+fn writer(file: &MemFile) -> Writer {
+    let file = File::new(file.try_clone().unwrap()).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = ENTRIES;
+        cfg.data = DATA;
+    });
+
+    file.configure(&cfg).unwrap()
+}
+
+fn commit(c: &mut Criterion) {
+    let mut group = c.benchmark_group("commit");
+    let file = memory_file();
+    let mut writer = writer(&file);
+
+    for size in [16usize, 256, 4096, 0x1_0000] {
+        let data = vec![0x42; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            b.iter(|| writer.commit(data).unwrap())
+        });
+    }
+}
+
+/// Commits that start and end off word boundaries, the byte-wise path of copying.
+fn commit_unaligned(c: &mut Criterion) {
+    let mut group = c.benchmark_group("commit_unaligned");
+    let file = memory_file();
+    let mut writer = writer(&file);
+
+    for size in [16usize, 256, 4096, 0x1_0000] {
+        let data = vec![0x42; size + 3];
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            b.iter(|| writer.commit(data).unwrap())
+        });
+    }
+}
+
+fn read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read");
+    let file = memory_file();
+    let mut writer = writer(&file);
+
+    for size in [16usize, 256, 4096, 0x1_0000] {
+        let idx = writer.commit(&vec![0x42; size]).unwrap();
+        let snapshot = writer.snapshot_at(idx);
+        let mut buffer = vec![0; size];
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| writer.read(&snapshot, &mut buffer).unwrap())
+        });
+    }
+}
+
+fn recover(c: &mut Criterion) {
+    let file = memory_file();
+    let mut writer = writer(&file);
+
+    // Fill the whole sequence ring with valid entries.
+    for idx in 0..ENTRIES {
+        writer.commit(&idx.to_ne_bytes()).unwrap();
+    }
+
+    drop(writer);
+
+    c.bench_function("recover", |b| {
+        b.iter_batched(
+            || File::new(file.try_clone().unwrap()).unwrap(),
+            |file| {
+                let mut cfg = ConfigureFile::default();
+                let mut valid = vec![];
+                file.recover(&mut cfg).unwrap().valid(&mut valid);
+                assert_eq!(valid.len() as u64, ENTRIES);
+                valid
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, commit, commit_unaligned, read, recover);
+criterion_main!(benches);
@@ -2,20 +2,26 @@ use std::collections::HashSet;
 use std::ffi::{OsString, OsStr};
 use std::{fs::OpenOptions, process, path::Path};
 use std::os::unix::{
+    fs::MetadataExt,
     fs::OpenOptionsExt,
     io::AsRawFd,
+    io::FromRawFd,
     io::RawFd,
     io::IntoRawFd,
+    io::OwnedFd,
 };
 
 use clap::{Parser, ValueEnum};
 use memfile::MemFile;
-use memmap2::MmapRaw;
-use shm_fd::{ListenFd, ListenInit, NotifyFd, SharedFd};
+use memmap2::MmapOptions;
+use shm_fd::{ListenFd, ListenInit, NotifyFd, SharedFd, SHM_FD_NAME, SHM_SHARED_FD};
 
 fn main() {
     let RestoreCommand {
         snapshot,
+        interval_ms,
+        fd_name,
+        ignore_signal,
         file,
         command,
         args,
@@ -33,8 +39,11 @@ fn main() {
         .with(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    // FIXME: allow customization.
-    let fd_name = "SHM_SHARED_FD";
+    let fd_name = fd_name
+        .or_else(|| std::env::var(SHM_FD_NAME).ok())
+        .unwrap_or_else(|| SHM_SHARED_FD.into());
+    let fd_name = parse_fd_name(&fd_name).expect("invalid file descriptor name");
+    let fd_name = fd_name.as_str();
 
     let listen = ListenFd::new()
         .transpose()
@@ -44,14 +53,15 @@ fn main() {
         .transpose()
         .expect("failed to open notify socket");
 
-    let init = ListenInit::<MemFile>::named_or_try_create::<std::io::Error>(
+    let init = ListenInit::<MemFile>::named_or_try_create_verified::<std::io::Error>(
         listen,
         fd_name,
+        ListenFd::is_regular_file,
         || MemFile::create_sealable("persistent"),
     ).expect("failed to initialized shm-file");
 
     if let Some(notify) = notify_sd {
-        init.maybe_notify(notify, fd_name)
+        init.maybe_notify(&notify, fd_name)
             .expect("failed to setup socket store");
     }
 
@@ -66,7 +76,7 @@ fn main() {
         // control of that file descriptor. (This program has not taken any of the file descriptors
         // up to this point).
         shmfd = unsafe {
-            SharedFd::from_listen(&init.listen).expect("failed to map shmfd")
+            SharedFd::from_listen_named(&init.listen, fd_name).expect("failed to map shmfd")
         };
 
         shmfd.as_raw_fd()
@@ -75,7 +85,7 @@ fn main() {
     let duped_shmfd = {
         // Safety: file descriptor 'borrowed', and thus valid.
         match unsafe { libc::dup(shmfd_borrowed_fd) } {
-            -1 => Err(std::io::Error::last_os_error()).expect("failed to dup"),
+            -1 => panic!("failed to dup: {}", std::io::Error::last_os_error()),
             safe => safe,
         }
     };
@@ -89,8 +99,18 @@ fn main() {
         .open(&file)
         .expect("Failed to open backup file");
 
+    // Files on different devices can not be copied with `copy_file_range`.
+    let same_device = {
+        // Safety: file descriptor owned by us, `SharedFd` does not close it.
+        let shm = unsafe { SharedFd::from_raw_fd(duped_shmfd) };
+        let shm_device = shm.device_id().expect("failed to stat shm file");
+        let bck_device = backup_file.metadata().expect("failed to stat backup file").dev();
+        shm_device == bck_device
+    };
+
     let mut proc = process::Command::new(command);
     proc.args(&args);
+    proc.env(SHM_FD_NAME, fd_name);
 
     unsafe { init.wrap_proc(&mut proc) };
     unsafe { init._set_pid(&mut proc) };
@@ -99,7 +119,8 @@ fn main() {
     unsafe { fcntl_cloexec(backup_file.as_raw_fd()).expect("failed to set close-on-exec") };
 
     // Ignore SIGTERM and SIGCHLD as we always wait for our child to exit first.
-    unsafe { posixly_ignore_signals() };
+    let ignore_signal: Vec<_> = ignore_signal.iter().map(|sig| sig.0).collect();
+    unsafe { posixly_ignore_signals(&ignore_signal) }.expect("failed to ignore signals");
 
     // FIXME: if we unwind right away, it's bad. We will overwrite the backing file with this
     // currently raw, potentially bad, state causing data loss. Fu..
@@ -107,7 +128,7 @@ fn main() {
         writeback_protector(WriteBack {
             shm: duped_shmfd,
             bck: backup_file.as_raw_fd(),
-        })
+        }, same_device)
     }.expect("Can protect with write back");
 
     // Before we start, let's prepare whatever backup already exists.
@@ -138,6 +159,10 @@ fn main() {
 
             let mut protector = protector;
             let mut child = proc.spawn().expect("can receive status");
+            let interval = std::time::Duration::from_millis(interval_ms);
+            // Without a pidfd, e.g. on kernels before 5.3, the child's exit is noticed only after
+            // the interval elapsed.
+            let exit = pidfd_open(child.id()).ok();
 
             let status = loop {
                 if let Some(code) = child.try_wait().expect("can receive status") {
@@ -149,6 +174,8 @@ fn main() {
                         eprintln!("Error making backup: {err}");
                     }
                 }
+
+                wait_for_exit(exit.as_ref(), interval);
             };
 
             drop(protector);
@@ -169,6 +196,25 @@ struct RestoreCommand {
     #[arg(value_enum, long)]
     snapshot: Option<SnapshotMode>,
 
+    /// The time between snapshots, in milliseconds.
+    ///
+    /// An exit of the command interrupts the wait.
+    #[arg(long, default_value_t = 250)]
+    interval_ms: u64,
+
+    /// The name of the shared memory file descriptor.
+    ///
+    /// Defaults to the environment variable `SHM_FD_NAME`, or `SHM_SHARED_FD` if it is unset. The
+    /// name is exported as `SHM_FD_NAME` to the command.
+    #[arg(long, value_parser = parse_fd_name)]
+    fd_name: Option<String>,
+
+    /// A signal to ignore while waiting for the command, by name or number. May be repeated.
+    ///
+    /// The signals are still delivered to the command, e.g. from a terminal or service manager.
+    #[arg(long, value_parser = Signal::parse, default_values = ["TERM", "INT", "CHLD"])]
+    ignore_signal: Vec<Signal>,
+
     #[arg(help = "The backup file")]
     file: OsString,
 
@@ -178,6 +224,14 @@ struct RestoreCommand {
     args: Vec<OsString>,
 }
 
+fn parse_fd_name(name: &str) -> Result<String, String> {
+    if ListenFd::is_valid_name(name) {
+        Ok(name.to_owned())
+    } else {
+        Err(format!("invalid file descriptor name {name:?}, it must not be empty or contain `:`"))
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum SnapshotMode {
     /// Use a lock-free, optimistic snapshot functionality.
@@ -186,6 +240,36 @@ enum SnapshotMode {
     RestoreV1,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Signal(libc::c_int);
+
+impl Signal {
+    const NAMED: &'static [(&'static str, libc::c_int)] = &[
+        ("HUP", libc::SIGHUP),
+        ("INT", libc::SIGINT),
+        ("QUIT", libc::SIGQUIT),
+        ("USR1", libc::SIGUSR1),
+        ("USR2", libc::SIGUSR2),
+        ("PIPE", libc::SIGPIPE),
+        ("ALRM", libc::SIGALRM),
+        ("TERM", libc::SIGTERM),
+        ("CHLD", libc::SIGCHLD),
+    ];
+
+    fn parse(name: &str) -> Result<Self, String> {
+        if let Ok(num) = name.parse() {
+            return Ok(Signal(num));
+        }
+
+        let short = name.strip_prefix("SIG").unwrap_or(name);
+        Self::NAMED
+            .iter()
+            .find(|(named, _)| named.eq_ignore_ascii_case(short))
+            .map(|&(_, num)| Signal(num))
+            .ok_or_else(|| format!("unknown signal `{name}`"))
+    }
+}
+
 struct WriteBack {
     shm: RawFd,
     bck: RawFd,
@@ -196,8 +280,39 @@ struct Dropped {
     how: fn(RawFd, RawFd),
 }
 
+/* On drop, copy all data back to the backup file.
+ */
+impl Drop for Dropped {
+    fn drop(&mut self) {
+        (self.how)(self.write_back.shm, self.write_back.bck);
+    }
+}
+
+/// Write all `len` bytes at `data`, advancing past the bytes each call of `write` accepted.
+///
+/// Returns the length written, or `-1` if a call failed or made no progress.
+fn write_all(
+    dest: RawFd,
+    data: *const u8,
+    len: usize,
+    write: fn(RawFd, *const u8, usize) -> libc::ssize_t,
+) -> libc::ssize_t {
+    let mut offset = 0;
+
+    while offset < len {
+        match write(dest, data.wrapping_add(offset), len - offset) {
+            -1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {}
+            written if written <= 0 => return -1,
+            written => offset += written as usize,
+        }
+    }
+
+    len as libc::ssize_t
+}
+
 unsafe fn writeback_protector(
     WriteBack { shm, bck }: WriteBack,
+    same_device: bool,
 ) -> Result<Dropped, std::io::Error> {
     fn copy_file_range(source: RawFd, dest: RawFd) -> libc::ssize_t {
         unsafe {
@@ -226,27 +341,25 @@ unsafe fn writeback_protector(
             libc::ftruncate(dest, length);
         }
 
-        let Ok(file) = MmapRaw::map_raw(&source) else {
+        let Ok(file) = MmapOptions::new().map_raw_read_only(source) else {
             return -1;
         };
 
-        let start_ptr = file.as_ptr() as *const libc::c_void;
-        let start_len = file.len();
-
-        let mut remaining = start_len;
-        while remaining > 0 {
-            let written = unsafe {
-                libc::write(dest, start_ptr, start_len)
-            };
-
-            if written < 0 {
-                return -1;
-            }
-
-            remaining = remaining.saturating_sub(written as usize);
+        fn write(fd: RawFd, buf: *const u8, len: usize) -> libc::ssize_t {
+            unsafe { libc::write(fd, buf as *const libc::c_void, len) }
         }
 
-        start_len as libc::ssize_t
+        // The region is live, it is passed as a pointer and never read as a slice.
+        write_all(dest, file.as_ptr(), file.len(), write)
+    }
+
+    if !same_device {
+        return Ok(Dropped {
+            write_back: WriteBack { shm, bck },
+            how: |source, dest| {
+                copy_file_all(source, dest);
+            },
+        });
     }
 
     /* First copy existing data to the shared memory.
@@ -271,14 +384,6 @@ unsafe fn writeback_protector(
         },
     };
 
-    /* On drop, copy all data back to the backup file.
-     */
-    impl Drop for Dropped {
-        fn drop(&mut self) {
-            (self.how)(self.write_back.shm, self.write_back.bck);
-        }
-    }
-
     Ok(Dropped {
         write_back: WriteBack { shm, bck },
         how,
@@ -297,7 +402,13 @@ fn file_with_parent(file: &OsStr) -> Option<FileWithParent<'_>> {
 fn try_restore_v1(dropped: &mut Dropped, backup: FileWithParent) -> Result<(), std::io::Error> {
     let mut now = std::time::Instant::now();
     let FileWithParent(backup_path, parent) = backup;
-    let snapshot = shm_snapshot::File::new(dropped.write_back.shm)?;
+    // Only the copy is modified, the live region is read without write access.
+    let snapshot = shm_snapshot::File::new_readonly(dropped.write_back.shm)?;
+    // Do not race a commit that is just being written, if it finishes quickly. Otherwise its
+    // entry is discarded by the retain below.
+    let _ = snapshot.wait_for_commit(std::time::Duration::from_millis(10));
+    // Write back the live region, if backed by a file, before copying it.
+    snapshot.flush()?;
 
     let mut pre_valid = HashSet::new();
     let mut pre_cfg = shm_snapshot::ConfigureFile::default();
@@ -334,6 +445,9 @@ fn try_restore_v1(dropped: &mut Dropped, backup: FileWithParent) -> Result<(), s
         recovery.valid(&mut post_valid);
     }
 
+    // The invalidated entries must reach the copy before it replaces the backup.
+    post_snapshot.flush()?;
+
     let time_to_retain = now.elapsed();
     now += time_to_retain;
 
@@ -369,16 +483,46 @@ fn try_restore_v1(dropped: &mut Dropped, backup: FileWithParent) -> Result<(), s
     Ok(())
 }
 
+/// Open a file descriptor that becomes readable when the process exits.
+fn pidfd_open(pid: u32) -> Result<OwnedFd, std::io::Error> {
+    match unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        // Safety: the syscall returned a new file descriptor, owned by us.
+        fd => Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) }),
+    }
+}
+
+/// Sleep for `timeout`, or until the process of the pidfd exited.
+fn wait_for_exit(pidfd: Option<&OwnedFd>, timeout: std::time::Duration) {
+    let Some(pidfd) = pidfd else {
+        return std::thread::sleep(timeout);
+    };
+
+    let mut poll = libc::pollfd {
+        fd: pidfd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    let timeout = timeout.as_millis().try_into().unwrap_or(libc::c_int::MAX);
+    // Returning early, e.g. on a signal, only makes the next snapshot happen sooner.
+    unsafe { libc::poll(&mut poll, 1, timeout) };
+}
+
 // Ignore SIGTERM..
-unsafe fn posixly_ignore_signals() {
+unsafe fn posixly_ignore_signals(signals: &[libc::c_int]) -> Result<(), std::io::Error> {
     let mut action: libc::sigaction = core::mem::zeroed();
 
     type Sigaction = fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void);
     action.sa_sigaction = (|_, _, _| ()) as Sigaction as usize;
 
-    libc::sigaction(libc::SIGTERM, &mut action as *mut _, core::ptr::null_mut());
-    libc::sigaction(libc::SIGINT, &mut action as *mut _, core::ptr::null_mut());
-    libc::sigaction(libc::SIGCHLD, &mut action as *mut _, core::ptr::null_mut());
+    for &signal in signals {
+        if -1 == libc::sigaction(signal, &mut action as *mut _, core::ptr::null_mut()) {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
 }
 
 unsafe fn fcntl_cloexec(fd: RawFd) -> Result<(), std::io::Error> {
@@ -394,3 +538,111 @@ unsafe fn fcntl_cloexec(fd: RawFd) -> Result<(), std::io::Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        pidfd_open, posixly_ignore_signals, wait_for_exit, write_all, writeback_protector, Signal,
+        WriteBack,
+    };
+    use memfile::MemFile;
+    use std::os::unix::{fs::FileExt, io::AsRawFd};
+
+    #[test]
+    fn parse_signals() {
+        assert_eq!(Signal::parse("TERM"), Ok(Signal(libc::SIGTERM)));
+        assert_eq!(Signal::parse("SIGHUP"), Ok(Signal(libc::SIGHUP)));
+        assert_eq!(Signal::parse("quit"), Ok(Signal(libc::SIGQUIT)));
+        assert_eq!(Signal::parse("10"), Ok(Signal(10)));
+        assert!(Signal::parse("SIGNOPE").is_err());
+    }
+
+    #[test]
+    fn ignored_signals_installed() {
+        unsafe fn handler(signal: libc::c_int) -> libc::sighandler_t {
+            let mut action: libc::sigaction = core::mem::zeroed();
+            assert_eq!(0, libc::sigaction(signal, core::ptr::null(), &mut action));
+            action.sa_sigaction
+        }
+
+        unsafe {
+            assert_eq!(handler(libc::SIGUSR2), libc::SIG_DFL);
+            posixly_ignore_signals(&[libc::SIGUSR2]).unwrap();
+            assert_ne!(handler(libc::SIGUSR2), libc::SIG_DFL);
+            assert_eq!(handler(libc::SIGUSR1), libc::SIG_DFL);
+
+            assert!(posixly_ignore_signals(&[libc::SIGKILL]).is_err());
+        }
+    }
+
+    #[test]
+    fn fallback_copy_exact_length() {
+        const LEN: usize = 3 * 4096 + 17;
+        let source: Vec<u8> = (0..LEN).map(|idx| idx as u8).collect();
+
+        let shm = MemFile::create_default("shm").unwrap();
+        shm.write_all_at(&source, 0).unwrap();
+        // A longer backup with stale data, which must be truncated.
+        let bck = MemFile::create_default("backup").unwrap();
+        bck.write_all_at(&[0xff; 5 * 4096], 0).unwrap();
+
+        let write_back = WriteBack {
+            shm: shm.as_raw_fd(),
+            bck: bck.as_raw_fd(),
+        };
+
+        // Not on the same device, the copy must not be attempted with `copy_file_range`.
+        let protector = unsafe { writeback_protector(write_back, false) }.unwrap();
+        drop(protector);
+
+        assert_eq!(bck.metadata().unwrap().len(), LEN as u64);
+        let mut copied = vec![0; LEN];
+        bck.read_exact_at(&mut copied, 0).unwrap();
+        assert!(copied == source);
+    }
+
+    #[test]
+    fn write_all_advances_on_short_writes() {
+        use std::cell::RefCell;
+
+        std::thread_local! {
+            static SINK: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+        }
+
+        // Accepts at most 1000 bytes per call.
+        fn short_write(_: libc::c_int, buf: *const u8, len: usize) -> libc::ssize_t {
+            let len = len.min(1000);
+            let buf = unsafe { core::slice::from_raw_parts(buf, len) };
+            SINK.with(|sink| sink.borrow_mut().extend_from_slice(buf));
+            len as libc::ssize_t
+        }
+
+        let source: Vec<u8> = (0..4321u32).map(|idx| (idx % 251) as u8).collect();
+        let written = write_all(-1, source.as_ptr(), source.len(), short_write);
+        assert_eq!(written, source.len() as libc::ssize_t);
+        SINK.with(|sink| assert!(*sink.borrow() == source));
+
+        fn no_progress(_: libc::c_int, _: *const u8, _: usize) -> libc::ssize_t {
+            0
+        }
+
+        assert_eq!(write_all(-1, source.as_ptr(), source.len(), no_progress), -1);
+    }
+
+    #[test]
+    fn wait_for_exit_wakes_early() {
+        use std::time::{Duration, Instant};
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let Ok(pidfd) = pidfd_open(child.id()) else {
+            // Not supported by this kernel.
+            child.wait().unwrap();
+            return;
+        };
+
+        let start = Instant::now();
+        wait_for_exit(Some(&pidfd), Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(child.try_wait().unwrap().is_some());
+    }
+}
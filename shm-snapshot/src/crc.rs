@@ -0,0 +1,40 @@
+//! CRC-32C (Castagnoli), the checksum of entries in files configured with
+//! [`ConfigureFile::checksum`](crate::ConfigureFile::checksum).
+
+/// The reflected polynomial of CRC-32C.
+const POLY: u32 = 0x82f6_3b78;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut idx = 0;
+
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+
+        table[idx] = crc;
+        idx += 1;
+    }
+
+    table
+};
+
+/// Compute the checksum of a sequence of bytes.
+pub(crate) fn crc32c(bytes: impl IntoIterator<Item = u8>) -> u32 {
+    crc32c_extend(0, bytes)
+}
+
+/// Continue the checksum `crc` of preceding bytes with more bytes.
+///
+/// The checksum of two parts is `crc32c_extend(crc32c(first), second)`.
+pub(crate) fn crc32c_extend(crc: u32, bytes: impl IntoIterator<Item = u8>) -> u32 {
+    !bytes.into_iter().fold(!crc, |crc, byte| {
+        TABLE[usize::from(crc as u8 ^ byte)] ^ (crc >> 8)
+    })
+}
+
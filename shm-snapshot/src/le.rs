@@ -0,0 +1,33 @@
+//! A `u64` of the head page and the sequence ring, in little-endian byte order.
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// An atomic `u64` with the layout of a little-endian `u64`, independent of the host.
+///
+/// Files are portable between hosts of either byte order. Values are converted with `to_le` and
+/// `from_le` on each access, which is free on little-endian hosts.
+#[repr(transparent)]
+pub(crate) struct LeU64(AtomicU64);
+
+impl LeU64 {
+    pub(crate) const fn new(value: u64) -> Self {
+        LeU64(AtomicU64::new(value.to_le()))
+    }
+
+    pub(crate) fn load(&self, ordering: Ordering) -> u64 {
+        u64::from_le(self.0.load(ordering))
+    }
+
+    pub(crate) fn store(&self, value: u64, ordering: Ordering) {
+        self.0.store(value.to_le(), ordering)
+    }
+
+    pub(crate) fn swap(&self, value: u64, ordering: Ordering) -> u64 {
+        u64::from_le(self.0.swap(value.to_le(), ordering))
+    }
+}
+
+impl Default for LeU64 {
+    fn default() -> Self {
+        LeU64::new(0)
+    }
+}
@@ -1,23 +1,31 @@
 //! Interact with a memory-mapped file in the systemd File Descriptor store, for snapshot-restore of some state.
+mod crc;
+mod le;
+mod mapping;
 #[cfg(test)]
 mod tests;
 mod writer;
 
-pub use writer::{ConfigureFile, File, FileDiscovery, PreparedTransaction, Snapshot, Writer};
+pub use writer::{
+    CommitError, ConfigureError, ConfigureFile, File, FileDiscovery, GrowError, HeaderView,
+    PreparedTransaction, ReadError, Reader, Reservation, RecoverError, Snapshot, SnapshotIter, SnapshotReader,
+    Writer, WriterStats,
+};
+pub use mapping::Mapping;
+use mapping::{MmapFile, StaticRegion};
 use writer::Head;
 
 use core::sync::atomic::AtomicU64;
-use memmap2::MmapRaw;
 
 /// The index of a snapshot in a file wrapped with a [`Writer`].
 ///
 /// Requires the file metadata (the size of the entry ring) to determine a precise memory offset in
-/// the file. This index does not guarantee that a snapshot is, or will stay, valid.
+/// the file. This index does not guarantee that a snapshot is, or will stay, valid. The
+/// [`Snapshot`] read with [`Writer::snapshot_at`] carries the slot of this index in the ring.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SnapshotIndex {
-    /// The entry index at which we have in fact committed. Not clear what use it would be to make
-    /// this number available but we want to debug the struct anyways.
-    #[allow(dead_code)]
+    /// The entry index at which we have in fact committed.
     entry: u64,
 }
 
@@ -48,52 +56,219 @@ impl RetainSnapshot for Vec<Snapshot> {
     }
 }
 
-/// An error, trying to commit a snapshot with [`Writer::commit`].
-pub struct WriterCommitError {
-    _inner: (),
-}
-
 impl File {
     pub fn new<T: std::os::unix::io::AsRawFd>(fd: T) -> Result<Self, std::io::Error> {
-        let file = MmapRaw::map_raw(&fd)?;
-        let head = Head::from_map(file);
-        Ok(File { head })
+        Ok(Self::from_mapping(MmapFile::new(&fd)?))
+    }
+
+    /// Wrap memory mapped by other means than [`Self::new`], see [`Mapping`].
+    pub fn from_mapping(mapping: impl Mapping + 'static) -> Self {
+        let head = Head::from_map(Box::new(mapping));
+        File { head, writable: true }
+    }
+
+    /// Wrap a region of memory that is not backed by a file, for instance in tests.
+    ///
+    /// The region is not unmapped when the file is dropped, several files may wrap the same one.
+    /// Flushing does nothing and [`Writer::grow`] fails with [`GrowError::Io`] as the region can
+    /// not be resized.
+    pub fn from_static(region: &'static [AtomicU64]) -> Self {
+        Self::from_mapping(StaticRegion(region))
+    }
+
+    /// Map a file without write access, for instance to verify or back up its entries.
+    ///
+    /// This also works for a file descriptor that was opened read-only. The file can be recovered
+    /// and read but not modified: [`Self::configure`] fails, [`Self::zero_uninitialized`] does
+    /// nothing, and [`FileDiscovery::retain`] as well as [`Self::into_writer_unguarded`] panic.
+    pub fn new_readonly<T: std::os::unix::io::AsRawFd>(fd: T) -> Result<Self, std::io::Error> {
+        let head = Head::from_map(Box::new(MmapFile::new_readonly(&fd)?));
+        Ok(File { head, writable: false })
     }
 
     /// Attempt to recover the configuration from existing data.
     ///
     /// This method writes the read information into the output argument `cfg` and returns a proxy
     /// with the recovered configuration. The proxy can be used to partially access the contained
-    /// entries as well, if the discovery succeeded.
+    /// entries as well, if the discovery succeeded. See [`Self::try_recover`] for the reason of a
+    /// failed discovery.
     pub fn recover(&self, cfg: &mut ConfigureFile) -> Option<FileDiscovery<'_>> {
+        self.try_recover(cfg).ok()
+    }
+
+    /// Attempt to recover the configuration from existing data, explaining a failure.
+    ///
+    /// Like [`Self::recover`], this writes the read information into `cfg`. A file that is
+    /// [`RecoverError::Uninitialized`] can be configured from scratch, while one with an unknown
+    /// format holds data that should not be overwritten. A layout whose rings, padded to its page
+    /// size, do not fit into the file fails with [`RecoverError::TooSmall`] while `cfg` still
+    /// describes it.
+    pub fn try_recover(&self, cfg: &mut ConfigureFile) -> Result<FileDiscovery<'_>, RecoverError> {
+        if self.head.is_too_small() {
+            return Err(RecoverError::TooSmall);
+        }
+
         self.head.discover(cfg);
 
         if !cfg.is_initialized() {
-            return None;
+            return Err(match cfg.layout_version {
+                0 => RecoverError::Uninitialized,
+                found => RecoverError::FormatVersionMismatch { found },
+            });
         }
 
-        Some(FileDiscovery {
+        self.head
+            .check_layout(cfg)
+            .map_err(|_| RecoverError::TooSmall)?;
+
+        Ok(FileDiscovery {
             file: self,
             configuration: ConfigureFile { ..*cfg },
         })
     }
 
+    /// Recover the configuration and read all valid entries with their data.
+    ///
+    /// Entries are returned in the order in which they were committed, see
+    /// [`FileDiscovery::valid_ordered`]. This is a shorthand for tools that dump or migrate the
+    /// contents of a file. Entries whose data can not be read, for instance as it does not match
+    /// their checksum, are skipped.
+    pub fn read_all(&self) -> Result<Vec<(Snapshot, Vec<u8>)>, RecoverError> {
+        let mut cfg = ConfigureFile::default();
+        let recovery = self.try_recover(&mut cfg)?;
+
+        let mut snapshots = vec![];
+        recovery.valid_ordered(&mut snapshots);
+
+        Ok(snapshots
+            .into_iter()
+            .filter_map(|snapshot| {
+                let mut data = vec![0; snapshot.length as usize];
+                recovery.read_exact(&snapshot, &mut data).ok()?;
+                Some((snapshot, data))
+            })
+            .collect())
+    }
+
+    /// Clear stale entries of a file that holds no initialized layout.
+    ///
+    /// A fresh memory file reads as zero, but a region reused after a crash may contain arbitrary
+    /// bytes before its header was initialized. These must not be interpreted as valid entries
+    /// when configuring. This zeroes the sequence ring of the layout described by `cfg`, the data
+    /// is left untouched. Does nothing, and returns `false`, if the file is already initialized.
+    pub fn zero_uninitialized(&mut self, cfg: &ConfigureFile) -> bool {
+        self.writable && self.head.zero_uninitialized(cfg)
+    }
+
+    /// Ask the operating system to write back the mapped file, see [`Writer::flush`].
+    ///
+    /// This also works for a file mapped with [`Self::new_readonly`].
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        self.head.flush()
+    }
+
+    /// Read the header page of the file.
+    ///
+    /// This is the raw layout as stored, it is not validated. Use [`Self::recover`] to interpret
+    /// the file.
+    pub fn header(&self) -> HeaderView {
+        self.head.header()
+    }
+
+    /// Read the commit marker of the file.
+    ///
+    /// The marker is odd while a writer is committing an entry and even otherwise, and it changes
+    /// with each commit. A consumer copying the file can compare the marker before and after the
+    /// copy to detect a commit that raced with it.
+    pub fn commit_marker(&self) -> u64 {
+        self.head.commit_marker()
+    }
+
+    /// Check if a writer is currently committing an entry.
+    pub fn is_commit_in_flight(&self) -> bool {
+        self.commit_marker() & 1 == 1
+    }
+
+    /// Wait until no commit is in flight, returning the commit marker.
+    ///
+    /// Returns `None` if a commit is still in flight after `timeout`. A writer that was
+    /// interrupted during a commit leaves the marker odd until its next commit, so waiting without
+    /// a timeout is not advisable.
+    pub fn wait_for_commit(&self, timeout: std::time::Duration) -> Option<u64> {
+        let start = std::time::Instant::now();
+
+        loop {
+            let marker = self.commit_marker();
+
+            if marker & 1 == 0 {
+                return Some(marker);
+            }
+
+            if start.elapsed() >= timeout {
+                return None;
+            }
+
+            std::thread::yield_now();
+        }
+    }
+
     /// Change the metadata of the file, to the one described in the configuration.
-    pub fn configure(mut self, cfg: &ConfigureFile) -> Writer {
-        self.head.configure(cfg);
-        self.into_writer_unguarded()
+    ///
+    /// Fails, without modifying the file, if the layout does not satisfy the requirements of the
+    /// configuration such as [`ConfigureFile::min_entry_len`] and [`ConfigureFile::tail_reserve`],
+    /// or if the file holds data of an unknown format. Files of a known prior format are migrated
+    /// to the current one.
+    pub fn configure(mut self, cfg: &ConfigureFile) -> Result<Writer, ConfigureError> {
+        if !self.writable {
+            return Err(ConfigureError::ReadOnly);
+        }
+
+        self.head.configure(cfg)?;
+        Ok(self.into_writer_unguarded())
     }
 
     /// Convert this into a writer, without minding data consistency.
+    ///
+    /// # Panics
+    ///
+    /// If the file was opened with [`Self::new_readonly`].
     pub fn into_writer_unguarded(self) -> Writer {
+        assert!(self.writable, "Can not write to a file opened read-only");
         Writer { head: self.head }
     }
 }
 
 impl FileDiscovery<'_> {
+    /// Read data described by a snapshot and confirm it was not overwritten while copying.
+    ///
+    /// This is the read for a file that is concurrently written, for instance by the process
+    /// whose state is backed up. The entry is checked again after the data was copied, if it no
+    /// longer holds the snapshot the copy is discarded with [`ReadError::Stale`]. Retry with a
+    /// newer snapshot in that case.
+    pub fn read_consistent(&self, snapshot: &Snapshot, buffer: &mut [u8]) -> Result<usize, ReadError> {
+        let data = snapshot_buffer(snapshot, buffer)?;
+        self.file.head.read_consistent_at(snapshot, data, &self.configuration)?;
+        Ok(data.len())
+    }
+
     /// Read data described by a snapshot, with discovered metadata in the file.
-    pub fn read(&self, snapshot: &Snapshot, buffer: &mut [u8]) {
-        self.file.head.read_at(snapshot, buffer, &self.configuration)
+    ///
+    /// Returns the number of bytes the snapshot holds, which are written to the start of the
+    /// buffer. Bytes past its length are left unchanged. Fails if the buffer is too short, or if
+    /// the data does not match the checksum of its entry, see [`ConfigureFile::checksum`].
+    pub fn read(&self, snapshot: &Snapshot, buffer: &mut [u8]) -> Result<usize, ReadError> {
+        let data = snapshot_buffer(snapshot, buffer)?;
+        if !self.file.head.read_at(snapshot, data, &self.configuration) {
+            return Err(ReadError::ChecksumMismatch);
+        }
+
+        Ok(data.len())
+    }
+
+    /// Read data described by a snapshot into a buffer of exactly its length.
+    pub fn read_exact(&self, snapshot: &Snapshot, buffer: &mut [u8]) -> Result<(), ReadError> {
+        exact_buffer(snapshot, buffer)?;
+        self.read(snapshot, buffer).map(drop)
     }
 
     /// Iteratively read all valid entries from the file.
@@ -102,30 +277,221 @@ impl FileDiscovery<'_> {
     /// buffer (similar to `VecDeque`) and likely are iterating in the order of the underlying raw
     /// slice, not the order of the actual logical data layout.
     ///
-    /// More specific interfaces for external iteration with an iterator may be added. Send changes
-    /// if you have an implementation.
+    /// Use [`Self::iter`] to iterate without collecting, or [`Self::valid_ordered`] to iterate in
+    /// commit order.
     #[inline(always)]
     pub fn valid(&self, into: &mut impl Extend<Snapshot>) {
         self.file.head.valid_at(into, &self.configuration)
     }
 
+    /// Iterate over all valid entries of the file.
+    ///
+    /// Yields the same entries in the same order as [`Self::valid`], reading each entry from the
+    /// file when it is reached.
+    pub fn iter(&self) -> SnapshotIter<'_> {
+        self.file.head.iter_at(&self.configuration)
+    }
+
+    /// Read all valid entries from the file, in the order in which they were committed.
+    ///
+    /// The oldest entry comes first. Entries are ordered by their position in the stream of data
+    /// relative to the write offset recorded in the file, which is consistent even when that
+    /// offset wraps around.
+    pub fn valid_ordered(&self, into: &mut impl Extend<Snapshot>) {
+        let mut snapshots = vec![];
+        self.valid(&mut snapshots);
+
+        let write_offset = self.configuration.initial_offset;
+        snapshots.sort_by_key(|snapshot| {
+            core::cmp::Reverse(write_offset.wrapping_sub(snapshot.offset))
+        });
+
+        into.extend(snapshots);
+    }
+
+    /// Check whether the newest entry may be the remains of an interrupted commit.
+    ///
+    /// A commit stores the write offset in the header page after it publishes the entry, so the
+    /// newest entry ends exactly at the write offset of a file that was not interrupted. If it
+    /// ends elsewhere, a writer stopped after publishing the entry of a commit. The newest entry
+    /// may then be torn, for instance with the offset of the interrupted commit but the length of
+    /// the entry its slot held previously. A commit interrupted before its entry was published
+    /// leaves the previous entries as they were, they are not suspect. A file without entries is
+    /// never suspect.
+    ///
+    /// The newest entry is found among all stored entries, including those of another epoch or
+    /// with a mismatched checksum which are not valid. An entry removed with [`Self::retain`] is
+    /// no longer stored though. If that was the newest one then the file is reported as suspect,
+    /// so check this before retaining entries.
+    pub fn last_entry_suspect(&self) -> bool {
+        let newest = self.file.head.newest_entry_at(&self.configuration);
+
+        newest.is_some_and(|last| {
+            last.offset.wrapping_add(last.length) != self.configuration.initial_offset
+        })
+    }
+
+    /// Check that a snapshot is still held by its entry in the sequence ring.
+    ///
+    /// Any modification of the data of an entry is preceded by its invalidation, so the data read
+    /// for a snapshot before this returns `true` was not modified in between. Snapshots without an
+    /// entry, see [`Snapshot::new`], are never current.
+    pub fn is_current(&self, snapshot: &Snapshot) -> bool {
+        self.file.head.is_current_at(snapshot, &self.configuration)
+    }
+
+    /// Find the valid entry with the greatest key, computed from the entry and its data.
+    ///
+    /// This selects the entry to recover from by an application-defined order, for instance a
+    /// sequence number or timestamp stored in the data. Of entries with equal keys, the last one
+    /// in the order of [`Self::valid`] is returned. The data of each entry is read once with a
+    /// [`SnapshotReader`], entries that fail to read are skipped.
+    pub fn latest_by<K: Ord>(&self, key: impl Fn(&Snapshot, &[u8]) -> K) -> Option<Snapshot> {
+        let mut snapshots = vec![];
+        self.valid(&mut snapshots);
+
+        let mut reader = self.reader();
+        snapshots
+            .into_iter()
+            .filter_map(|snapshot| Some((key(&snapshot, reader.read(&snapshot).ok()?), snapshot)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, snapshot)| snapshot)
+    }
+
+    /// Create a reader for the data of snapshots, which reuses its buffer.
+    pub fn reader(&self) -> SnapshotReader<'_> {
+        SnapshotReader {
+            discovery: self,
+            buffer: vec![],
+        }
+    }
+
+    /// Stream the data of a snapshot, without a buffer of its length.
+    ///
+    /// The data is checked against the checksum of its entry once the end is reached, see
+    /// [`Reader`].
+    pub fn stream(&self, snapshot: Snapshot) -> Reader<'_> {
+        self.file.head.stream_at(snapshot, &self.configuration)
+    }
+
+    /// Collect all valid entries matching a predicate.
+    ///
+    /// In contrast to [`Self::retain`] this does not modify the file. Entries that do not match
+    /// the predicate are skipped but stay valid. The order of entries is the same as in
+    /// [`Self::valid`].
+    pub fn filter(&self, pred: impl Fn(&Snapshot) -> bool, into: &mut impl Extend<Snapshot>) {
+        struct Filter<'lt, P, E: ?Sized>(P, &'lt mut E);
+
+        impl<P, E> Extend<Snapshot> for Filter<'_, P, E>
+        where
+            P: Fn(&Snapshot) -> bool,
+            E: Extend<Snapshot> + ?Sized,
+        {
+            fn extend<T: IntoIterator<Item = Snapshot>>(&mut self, iter: T) {
+                let pred = &self.0;
+                self.1.extend(iter.into_iter().filter(|snapshot| pred(snapshot)))
+            }
+        }
+
+        self.valid(&mut Filter(pred, into))
+    }
+
     /// Invalidate some entries, as determined by the retained configuration.
     ///
     /// For instance, delete snapshots which are known to have been potentially invalidated by
     /// modifications into the covered memory.
+    ///
+    /// # Panics
+    ///
+    /// If the file was opened with [`File::new_readonly`].
     pub fn retain(&self, retain: &dyn RetainSnapshot) {
+        assert!(self.file.writable, "Can not retain entries of a file opened read-only");
         self.file.head.retain_at(retain, &self.configuration);
     }
 }
 
+impl SnapshotReader<'_> {
+    /// Read the data described by a snapshot.
+    ///
+    /// The buffer only grows when a snapshot is larger than all previously read ones, so reading
+    /// many snapshots does not allocate for each of them. Fails like
+    /// [`FileDiscovery::read_exact`], for instance if the data does not match its checksum. A
+    /// snapshot longer than the data ring fails with [`ReadError::ExceedsCapacity`] before the
+    /// buffer grows.
+    pub fn read(&mut self, snapshot: &Snapshot) -> Result<&[u8], ReadError> {
+        let length = snapshot.length;
+        let len = usize::try_from(length)
+            .ok()
+            .filter(|_| length <= self.discovery.configuration.data)
+            .ok_or(ReadError::ExceedsCapacity { length })?;
+        if self.buffer.len() < len {
+            self.buffer.resize(len, 0);
+        }
+
+        let data = &mut self.buffer[..len];
+        self.discovery.read_exact(snapshot, data)?;
+        Ok(data)
+    }
+}
+
+/// Reads the data of the snapshot in chunks.
+///
+/// Returns the end of file after the length of the snapshot. If the data read does not match the
+/// checksum of its entry, see [`ConfigureFile::checksum`], reading the end fails with an error of
+/// kind [`std::io::ErrorKind::InvalidData`] instead.
+impl std::io::Read for Reader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.read_next(buf);
+
+        if len == 0 && !buf.is_empty() && !self.matches() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                ReadError::ChecksumMismatch,
+            ));
+        }
+
+        Ok(len)
+    }
+}
+
 /// Public interface of the writer.
 impl Writer {
     /// Insert some data into the atomic log of the shared memory.
-    pub fn commit(&mut self, data: &[u8]) -> Result<SnapshotIndex, WriterCommitError> {
-        match self.head.write_with(data, &mut |_tx| true)  {
-            Ok(entry) => Ok(SnapshotIndex { entry }),
-            Err(_) => Err(WriterCommitError { _inner: () })
-        }
+    ///
+    /// Resolve the returned index with [`Self::snapshot_at`], for instance to record the snapshot
+    /// in a set passed to [`FileDiscovery::retain`] later.
+    pub fn commit(&mut self, data: &[u8]) -> Result<SnapshotIndex, CommitError> {
+        self.commit_chunks(&[data])
+    }
+
+    /// Insert data, given as a sequence of chunks, as one entry into the shared memory.
+    ///
+    /// The entry holds the concatenation of all chunks. This avoids an intermediate buffer when
+    /// the data is naturally split, for instance into a header and a payload.
+    pub fn commit_chunks(&mut self, chunks: &[&[u8]]) -> Result<SnapshotIndex, CommitError> {
+        let entry = self.head.write_with(chunks, &mut |_tx| true)?;
+        Ok(SnapshotIndex { entry })
+    }
+
+    /// Insert data, given as vectored buffers, as one entry into the shared memory.
+    ///
+    /// Like [`Self::commit_chunks`], for callers that already hold the data as `IoSlice`s. The
+    /// combined length of all buffers must fit the data ring.
+    pub fn commit_vectored(
+        &mut self,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Result<SnapshotIndex, CommitError> {
+        let entry = self.head.write_with(bufs, &mut |_tx| true)?;
+        Ok(SnapshotIndex { entry })
+    }
+
+    /// Reserve an entry of `len` bytes, to be written in place and then committed.
+    ///
+    /// This avoids copying large data from an intermediate buffer, see [`Reservation`] for how
+    /// the reserved bytes are accessed. Space for the entry is freed right away, invalidating
+    /// older entries as in [`Self::commit`], even if the reservation is dropped without commit.
+    pub fn reserve(&mut self, len: usize) -> Result<Reservation<'_>, CommitError> {
+        self.head.reserve(len)
     }
 
     /// Insert some data into the atomic log of the shared memory.
@@ -133,18 +499,19 @@ impl Writer {
     /// This also invokes a function such that it's effects are sequenced after the reservation of
     /// the new slot but before committing the data. The function can also introduce changes that
     /// appear correctly from the semantics view of the ring. Changes to the tail can be made via
-    /// the passed `PreparedTransaction` object.
+    /// the passed `PreparedTransaction` object. Returning `None`, or calling
+    /// [`PreparedTransaction::abort`], discards the entry.
     pub fn commit_with<T>(
         &mut self,
         data: &[u8],
         intermediate: impl FnOnce(PreparedTransaction) -> Option<T>
-    ) -> Result<(SnapshotIndex, T), WriterCommitError> {
+    ) -> Result<(SnapshotIndex, T), CommitError> {
         let mut dropped = Some(intermediate);
         let mut result = None;
-        let ref mut result_ref = result;
+        let result_ref = &mut result;
 
         let mut intermediate = move |tx: PreparedTransaction<'_>| {
-            dropped.take().map_or(false, |fn_| {
+            dropped.take().is_some_and(|fn_| {
                 if let Some(val) = fn_(tx) {
                     *result_ref = Some(val);
                     true
@@ -154,23 +521,54 @@ impl Writer {
             })
         };
 
-        match self.head.write_with(data, &mut intermediate)  {
-            Ok(entry) => {
-                let val = result.expect("written when returning `true`");
-                Ok((SnapshotIndex { entry }, val))
-            },
-            Err(_) => Err(WriterCommitError { _inner: () })
-        }
+        let entry = self.head.write_with(&[data], &mut intermediate)?;
+        let val = result.expect("written when returning `true`");
+        Ok((SnapshotIndex { entry }, val))
     }
 
     /// Read the snapshot associated with a written index.
+    ///
+    /// The entry is loaded with `Acquire` ordering. If the slot was reused by later commits the
+    /// returned snapshot describes the newer entry, or is invalid with a length of zero. It is only
+    /// meaningful while the entry was not overwritten, a reader of the snapshot confirms that with
+    /// [`FileDiscovery::is_current`] after copying its data.
     pub fn snapshot_at(&self, idx: SnapshotIndex) -> Snapshot {
         self.head.entry_at(idx)
     }
 
-    /// Read data described by a snapshot, with discovered metadata in the file.
-    pub fn read(&self, snapshot: &Snapshot, buffer: &mut [u8]) {
-        self.head.read(snapshot, buffer);
+    /// Read data described by a snapshot and confirm it was not overwritten while copying.
+    ///
+    /// See [`FileDiscovery::read_consistent`], this is useful if another process writes to the
+    /// same file.
+    pub fn read_consistent(&self, snapshot: &Snapshot, buffer: &mut [u8]) -> Result<usize, ReadError> {
+        let data = snapshot_buffer(snapshot, buffer)?;
+        self.head.read_consistent(snapshot, data)?;
+        Ok(data.len())
+    }
+
+    /// Read data described by a snapshot.
+    ///
+    /// Returns the number of bytes the snapshot holds, which are written to the start of the
+    /// buffer. Bytes past its length are left unchanged. Fails if the buffer is too short, or if
+    /// the data does not match the checksum of its entry, see [`ConfigureFile::checksum`].
+    pub fn read(&self, snapshot: &Snapshot, buffer: &mut [u8]) -> Result<usize, ReadError> {
+        let data = snapshot_buffer(snapshot, buffer)?;
+        if !self.head.read(snapshot, data) {
+            return Err(ReadError::ChecksumMismatch);
+        }
+
+        Ok(data.len())
+    }
+
+    /// Read data described by a snapshot into a buffer of exactly its length.
+    pub fn read_exact(&self, snapshot: &Snapshot, buffer: &mut [u8]) -> Result<(), ReadError> {
+        exact_buffer(snapshot, buffer)?;
+        self.read(snapshot, buffer).map(drop)
+    }
+
+    /// Stream the data of a snapshot, see [`FileDiscovery::stream`].
+    pub fn stream(&self, snapshot: Snapshot) -> Reader<'_> {
+        self.head.stream(snapshot)
     }
 
     /// Collect all currently valid snapshot entries.
@@ -179,6 +577,69 @@ impl Writer {
         self.head.valid(into)
     }
 
+    /// Iterate over all currently valid snapshot entries.
+    pub fn iter(&self) -> SnapshotIter<'_> {
+        self.head.iter()
+    }
+
+    /// Grow the sequence ring to hold `entries` snapshots, keeping all valid entries.
+    ///
+    /// The header is invalidated while entries are moved and published again once the new layout
+    /// is complete, readers recovering the file in between find it uninitialized. If the larger
+    /// ring needs additional pages then the file grows by that number of pages and the data ring
+    /// and the tail are moved back, the tail keeps its contents and its length. Other processes
+    /// must map the file again to see the new length.
+    /// Entries keep their data but are held in other slots, i.e. [`Snapshot::entry`] changes.
+    pub fn grow_entries(&mut self, entries: u64) -> Result<(), GrowError> {
+        self.head.grow_entries(entries)
+    }
+
+    /// Resize the file to `len` bytes and map it again, using new space for the data ring.
+    ///
+    /// The data ring grows to the largest power of two that keeps the length of the tail, the
+    /// tail is moved behind it with its contents and gains any remaining space. All valid entries
+    /// and the write offset are kept. A shorter file cuts off the end of the tail, a length that
+    /// can not hold the rings fails with [`GrowError::Shrink`].
+    ///
+    /// The header is invalidated while data is moved, as in [`Self::grow_entries`]. Other
+    /// processes must map the file again to see the new length.
+    pub fn grow(&mut self, len: u64) -> Result<(), GrowError> {
+        self.head.grow(len)
+    }
+
+    /// Switch to an application-defined epoch, invalidating all entries committed in another.
+    ///
+    /// An application that changes the format of its data can use this to ignore all data written
+    /// in a prior format. Compare the epoch stored in the file, see [`File::header`], to the
+    /// current one after recovery and set it if they differ. Every entry is tagged with the epoch
+    /// it was committed in and entries of other epochs are skipped by all readers, but the data is
+    /// not erased until it is overwritten. Setting the current epoch again keeps all entries.
+    ///
+    /// Fails with [`ConfigureError::NoEpochs`] unless the layout was configured with
+    /// [`ConfigureFile::epochs`].
+    pub fn set_epoch(&mut self, epoch: u64) -> Result<(), ConfigureError> {
+        self.head.set_epoch(epoch)
+    }
+
+    /// Ask the operating system to write back all commits to the file, with `msync`.
+    ///
+    /// This is advisory, for instance before suspending modifications to copy the file externally.
+    /// It does not make commits durable against a crash of the machine at any other point and does
+    /// not change the ordering observed by concurrent readers of the shared memory.
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        self.head.flush()
+    }
+
+    /// Flush all commits to the file and convert back into a read view.
+    ///
+    /// After this returns, all committed entries have been synchronized with `msync`. This is a
+    /// clean point for a supervisor to copy the file, for instance when the program is about to
+    /// exit, without racing any further modifications by this writer.
+    pub fn finalize(self) -> Result<File, std::io::Error> {
+        let head = self.head.finalize()?;
+        Ok(File { head, writable: true })
+    }
+
     /// Access the tail of the underlying shared memory file.
     ///
     /// This refers to the portion of the file after the header, the entry ring, and the data ring
@@ -187,16 +648,213 @@ impl Writer {
     pub fn tail(&self) -> &[AtomicU64] {
         self.head.tail()
     }
+
+    /// View the tail as a slice of plain data values.
+    ///
+    /// The slice holds as many values as fit into the tail, remaining bytes are not covered.
+    /// Returns `None` if `T` is zero-sized or the tail is not aligned for it. The tail of a mapped
+    /// file is page aligned, memory wrapped with [`File::from_static`] or [`File::from_mapping`]
+    /// may only be aligned to `8` bytes.
+    ///
+    /// # Safety
+    ///
+    /// The tail must not be modified while the slice is alive. The slice is not atomic, a
+    /// concurrent write through [`Self::tail`], [`PreparedTransaction::tail`], or by another
+    /// process mapping the same file is a data race. Use this only if a single process ever
+    /// writes the tail and it does not do so while reading through this view.
+    #[cfg(feature = "bytemuck")]
+    pub unsafe fn tail_as<T: bytemuck::Pod>(&self) -> Option<&[T]> {
+        let tail = self.tail();
+        let size = core::mem::size_of::<T>();
+        if size == 0 {
+            return None;
+        }
+
+        let len = core::mem::size_of_val(tail) / size * size;
+        // Safety: the tail is initialized memory of the map, the caller promises that it is not
+        // modified while the returned borrow of `self` lives.
+        let bytes = unsafe { core::slice::from_raw_parts(tail.as_ptr() as *const u8, len) };
+        bytemuck::try_cast_slice(bytes).ok()
+    }
+
+    /// The number of bytes in the tail, see [`Self::tail`].
+    pub fn tail_len(&self) -> usize {
+        core::mem::size_of_val(self.tail())
+    }
+
+    /// The number of entries the sequence ring holds.
+    ///
+    /// Committing more entries than this invalidates the oldest ones.
+    pub fn entry_capacity(&self) -> u64 {
+        self.head.entry_capacity()
+    }
+
+    /// The number of bytes in the data ring.
+    ///
+    /// The largest entry that can be committed is one byte shorter, longer data fails with
+    /// [`CommitError::DataTooLarge`].
+    pub fn data_capacity(&self) -> u64 {
+        self.head.data_capacity()
+    }
+
+    /// The number of bytes of the data ring occupied by valid entries.
+    ///
+    /// This spans from the data of the oldest valid entry to the end of the newest one, including
+    /// any bytes skipped in between. Committing more than the remaining bytes invalidates the
+    /// oldest entries.
+    pub fn data_used(&self) -> u64 {
+        self.head.data_used()
+    }
+
+    /// Summarize the layout and occupancy of the rings, for diagnostics.
+    ///
+    /// Counts the valid entries by scanning the sequence ring, like [`Self::iter`]. The `Debug`
+    /// representation of the writer prints the same values.
+    pub fn stats(&self) -> WriterStats {
+        self.head.stats()
+    }
 }
 
-impl core::fmt::Debug for WriterCommitError {
+impl core::fmt::Debug for Writer {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let WriterStats {
+            entry_capacity,
+            data_capacity,
+            data_used,
+            entries_valid,
+            write_offset,
+            layout_version,
+        } = self.stats();
+
+        f.debug_struct("Writer")
+            .field("entry_capacity", &entry_capacity)
+            .field("data_capacity", &data_capacity)
+            .field("data_used", &data_used)
+            .field("entries_valid", &entries_valid)
+            .field("write_offset", &write_offset)
+            .field("layout_version", &format_args!("{layout_version:#x}"))
+            .finish()
+    }
+}
+
+/// The part of the buffer which holds the data of a snapshot.
+fn snapshot_buffer<'buf>(
+    snapshot: &Snapshot,
+    buffer: &'buf mut [u8],
+) -> Result<&'buf mut [u8], ReadError> {
+    let length = snapshot.length;
+    usize::try_from(length)
+        .ok()
+        .and_then(|len| buffer.get_mut(..len))
+        .ok_or(ReadError::BufferTooSmall { length })
+}
+
+/// Check the buffer is not longer than the snapshot.
+fn exact_buffer(snapshot: &Snapshot, buffer: &[u8]) -> Result<(), ReadError> {
+    let length = snapshot.length;
+    if buffer.len() as u64 > length {
+        return Err(ReadError::BufferTooLarge { length });
+    }
+
+    Ok(())
+}
+
+impl core::fmt::Display for CommitError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("WriterCommitError").finish()
+        match self {
+            CommitError::DataTooLarge { len, capacity } => write!(
+                f,
+                "Data of {len} bytes does not fit the data ring, at most {capacity} bytes"
+            ),
+            CommitError::Rejected => write!(f, "The transaction was rejected before committing"),
+            CommitError::Uninitialized => write!(f, "The file was not configured for writing"),
+        }
     }
 }
 
-impl core::fmt::Display for WriterCommitError {
+impl std::error::Error for CommitError {}
+
+impl core::fmt::Display for ConfigureError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Failed to commit snapshot data")
+        match self {
+            ConfigureError::EntryTooLarge { required, max_entry_len } => write!(
+                f,
+                "Entries of {required} bytes do not fit the data ring, at most {max_entry_len} bytes"
+            ),
+            ConfigureError::TailTooSmall { required, available } => write!(
+                f,
+                "The tail of {available} bytes is smaller than the required {required} bytes"
+            ),
+            ConfigureError::FormatVersionMismatch { found } => write!(
+                f,
+                "The file holds data of an unknown format version {found:#x}"
+            ),
+            ConfigureError::ReadOnly => write!(f, "The file was opened read-only"),
+            ConfigureError::TooSmall { needed, have } => write!(
+                f,
+                "The layout requires {needed} bytes but the file holds only {have} bytes"
+            ),
+            ConfigureError::NotPowerOfTwo => {
+                write!(f, "The number of entries and data bytes must be powers of two")
+            }
+            ConfigureError::Uninitialized => write!(f, "The configuration is not initialized"),
+            ConfigureError::PageSize { page_size } => write!(
+                f,
+                "The page size of {page_size} bytes is not a supported power of two"
+            ),
+            ConfigureError::NoEpochs => write!(f, "The layout does not tag entries with an epoch"),
+        }
     }
 }
+
+impl std::error::Error for ConfigureError {}
+
+impl core::fmt::Display for GrowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GrowError::NotPowerOfTwo => write!(f, "The requested size is not a power of two"),
+            GrowError::Shrink => write!(f, "The requested size is smaller than the current one"),
+            GrowError::TooSmall => write!(f, "The file is too small for the requested layout"),
+            GrowError::Io(kind) => write!(f, "Resizing the file failed: {kind}"),
+        }
+    }
+}
+
+impl std::error::Error for GrowError {}
+
+impl core::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::BufferTooSmall { length } => {
+                write!(f, "The buffer is shorter than the snapshot of {length} bytes")
+            }
+            ReadError::BufferTooLarge { length } => {
+                write!(f, "The buffer is longer than the snapshot of {length} bytes")
+            }
+            ReadError::ChecksumMismatch => {
+                write!(f, "The data of the snapshot does not match its checksum")
+            }
+            ReadError::Stale => write!(f, "The snapshot was overwritten while reading"),
+            ReadError::ExceedsCapacity { length } => {
+                write!(f, "The snapshot of {length} bytes is longer than the data ring")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl core::fmt::Display for RecoverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecoverError::TooSmall => write!(f, "The file is too small to hold a snapshot layout"),
+            RecoverError::Uninitialized => write!(f, "The file holds no snapshot layout"),
+            RecoverError::FormatVersionMismatch { found } => write!(
+                f,
+                "The file holds data of an unknown format version {found:#x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RecoverError {}
@@ -0,0 +1,114 @@
+//! The memory a [`File`](crate::File) is mapped to.
+use core::sync::atomic::AtomicU64;
+use memmap2::MmapRaw;
+
+/// The memory of a snapshot file, mapped into the address space.
+///
+/// [`File::new`](crate::File::new) maps a file descriptor with `memmap2`. Implement this to map the
+/// file by other means, for instance in a sandbox that intercepts system calls, and wrap it with
+/// [`File::from_mapping`](crate::File::from_mapping).
+///
+/// # Safety
+///
+/// `as_mut_ptr` must return the same pointer on each call, pointing to `len` bytes of memory and
+/// aligned to `8` bytes. The memory must stay valid for atomic reads and writes until the mapping
+/// is dropped, other processes may modify it concurrently.
+///
+/// The tail is only aligned as the memory is. Align the memory to a page for `Writer::tail_as`
+/// to view the tail as types of a larger alignment.
+pub unsafe trait Mapping: Send + Sync {
+    /// The start of the mapped memory.
+    fn as_mut_ptr(&self) -> *mut u8;
+
+    /// The length of the mapped memory in bytes.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Ask the operating system to write back modifications to the file, with `msync`.
+    fn flush(&self) -> Result<(), std::io::Error>;
+
+    /// Resize the file to `len` bytes and map all of it.
+    ///
+    /// This mapping stays valid until dropped, the new mapping refers to the same file. On failure
+    /// the file should keep its length.
+    fn resize(&self, len: u64) -> Result<Box<dyn Mapping>, std::io::Error>;
+}
+
+/// A file mapped with `memmap2`.
+pub(crate) struct MmapFile {
+    map: MmapRaw,
+    /// A duplicate of the mapped file descriptor, to resize and map the file again.
+    fd: std::fs::File,
+}
+
+impl MmapFile {
+    pub(crate) fn new(fd: &impl std::os::unix::io::AsRawFd) -> Result<Self, std::io::Error> {
+        let map = MmapRaw::map_raw(fd)?;
+        Ok(MmapFile { map, fd: Self::duplicate(fd)? })
+    }
+
+    pub(crate) fn new_readonly(
+        fd: &impl std::os::unix::io::AsRawFd,
+    ) -> Result<Self, std::io::Error> {
+        let map = memmap2::MmapOptions::new().map_raw_read_only(fd)?;
+        Ok(MmapFile { map, fd: Self::duplicate(fd)? })
+    }
+
+    /// Keep our own handle to the file, to resize it in [`Writer::grow`](crate::Writer::grow).
+    fn duplicate(fd: &impl std::os::unix::io::AsRawFd) -> Result<std::fs::File, std::io::Error> {
+        // Safety: the caller keeps the descriptor open while it is borrowed here.
+        let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(fd.as_raw_fd()) };
+        Ok(fd.try_clone_to_owned()?.into())
+    }
+}
+
+// Safety: `MmapRaw` maps the whole file at a page aligned address, keeping it until dropped.
+unsafe impl Mapping for MmapFile {
+    fn as_mut_ptr(&self) -> *mut u8 {
+        self.map.as_mut_ptr()
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn flush(&self) -> Result<(), std::io::Error> {
+        self.map.flush()
+    }
+
+    fn resize(&self, len: u64) -> Result<Box<dyn Mapping>, std::io::Error> {
+        let map_len = usize::try_from(len).map_err(|_| std::io::ErrorKind::InvalidInput)?;
+
+        // Map before resizing, so that a failure leaves the file as it was.
+        let map = memmap2::MmapOptions::new().len(map_len).map_raw(&self.fd)?;
+        self.fd.set_len(len)?;
+
+        let fd = self.fd.try_clone()?;
+        Ok(Box::new(MmapFile { map, fd }))
+    }
+}
+
+/// Memory that is not mapped from a file, see [`File::from_static`](crate::File::from_static).
+pub(crate) struct StaticRegion(pub(crate) &'static [AtomicU64]);
+
+// Safety: a slice of `AtomicU64` is aligned for them and valid for atomic access forever.
+unsafe impl Mapping for StaticRegion {
+    fn as_mut_ptr(&self) -> *mut u8 {
+        self.0.as_ptr() as *mut u8
+    }
+
+    fn len(&self) -> usize {
+        core::mem::size_of_val(self.0)
+    }
+
+    fn flush(&self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    fn resize(&self, _: u64) -> Result<Box<dyn Mapping>, std::io::Error> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
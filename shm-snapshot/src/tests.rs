@@ -1,4 +1,4 @@
-use crate::writer::{DataPage, HeadCache, HeadPage, SequencePage, WriteHead};
+use crate::writer::{ConfigureFile, DataPage, HeadCache, HeadPage, SequencePage, WriteHead};
 use core::sync::atomic::Ordering;
 
 #[test]
@@ -10,7 +10,7 @@ fn initialize_inner_basic() {
 
         head.pre_configure_pages(0x80);
         head.pre_configure_entries(0x10);
-        head.configure_pages();
+        head.configure_pages().unwrap();
 
         let mut entry = head.entry();
         const DATA: &[u8] = b"Hello, world!";
@@ -35,11 +35,12 @@ fn commit_not() {
 
         head.pre_configure_pages(0x80);
         head.pre_configure_entries(0x10);
-        head.configure_pages();
+        head.configure_pages().unwrap();
 
-        let mut entry = head.entry();
-        entry.copy_from_slice(b"Hello, world!");
-        drop(entry);
+        {
+            let mut entry = head.entry();
+            entry.copy_from_slice(b"Hello, world!");
+        }
 
         head.iter_valid(&mut valids, Ordering::Relaxed);
         assert_eq!(valids.len(), 0);
@@ -58,6 +59,189 @@ fn commit_not() {
     })
 }
 
+#[test]
+fn configure_interrupted() {
+    let mut valids = vec![];
+    with_setup(|mut head| {
+        head.pre_configure_pages(0x80);
+        head.pre_configure_entries(0x10);
+        head.configure_pages().unwrap();
+
+        for data in [b"Hello", b"world"] {
+            let mut entry = head.entry();
+            let end_ptr = entry
+                .new_write_offset(data.len())
+                .expect("Invalid, can't determine end offset of data");
+            entry.invalidate_heads(end_ptr);
+            entry.copy_from_slice(data);
+            entry.commit();
+        }
+
+        // A new layout is staged but the process crashes before publishing it.
+        head.pre_configure_entries(0x1);
+        head.stage_meta();
+
+        head.iter_valid(&mut valids, Ordering::Relaxed);
+        assert_eq!(valids.len(), 2);
+    });
+}
+
+#[test]
+fn crc32c_check_value() {
+    assert_eq!(crate::crc::crc32c(*b""), 0);
+    assert_eq!(crate::crc::crc32c(*b"123456789"), 0xe306_9283);
+
+    let first = crate::crc::crc32c(*b"1234");
+    assert_eq!(crate::crc::crc32c_extend(first, *b"56789"), 0xe306_9283);
+}
+
+#[test]
+fn configure_for_file_sizes() {
+    const PAGE: u64 = 4096;
+
+    let pages_for = |cfg: &ConfigureFile| {
+        1 + cfg.entries.div_ceil(PAGE / 16) + cfg.data.div_ceil(PAGE)
+    };
+
+    for len in [0, PAGE, 2 * PAGE, 3 * PAGE - 1] {
+        assert!(!ConfigureFile::for_file(len).is_initialized(), "{len}");
+    }
+
+    for len in [3 * PAGE, 3 * PAGE + 1, 5 * PAGE, 0x10_0000, 0x10_0fff, 0x1_0000_0000] {
+        let cfg = ConfigureFile::for_file(len);
+
+        assert!(cfg.is_initialized(), "{len}");
+        assert!(cfg.entries.is_power_of_two(), "{len}: {cfg:?}");
+        assert!(cfg.data.is_power_of_two(), "{len}: {cfg:?}");
+        assert!(pages_for(&cfg) * PAGE <= len, "{len}: {cfg:?}");
+
+        // Neither ring could be doubled.
+        let double_data = ConfigureFile { data: 2 * cfg.data, ..ConfigureFile::for_file(len) };
+        assert!(pages_for(&double_data) * PAGE > len, "{len}: {cfg:?}");
+        let double_entries = ConfigureFile { entries: 2 * cfg.entries, ..ConfigureFile::for_file(len) };
+        assert!(pages_for(&double_entries) * PAGE > len, "{len}: {cfg:?}");
+    }
+}
+
+#[test]
+fn configure_fill_sizes() {
+    const PAGE: u64 = 4096;
+
+    let pages_for = |cfg: &ConfigureFile| {
+        1 + cfg.entries.div_ceil(PAGE / 16) + cfg.data.div_ceil(PAGE)
+    };
+
+    for len in [0, PAGE, 2 * PAGE, 3 * PAGE - 1] {
+        assert!(!ConfigureFile::fill(len).is_initialized(), "{len}");
+    }
+
+    // One sequence page, and a power-of-two number of data pages, fill the file completely.
+    for len in [3 * PAGE, 4 * PAGE, 6 * PAGE, 0x10_1000, 0x10_2000] {
+        let cfg = ConfigureFile::fill(len);
+        assert_eq!(cfg.tail_len(len), Some(0), "{len}: {cfg:?}");
+    }
+
+    for len in [3 * PAGE + 1, 5 * PAGE, 0x10_0000, 0x10_0fff, 0x1_0000_0000] {
+        let cfg = ConfigureFile::fill(len);
+
+        assert!(cfg.is_initialized(), "{len}");
+        assert!(cfg.entries.is_power_of_two(), "{len}: {cfg:?}");
+        assert!(cfg.data.is_power_of_two(), "{len}: {cfg:?}");
+        assert!(pages_for(&cfg) * PAGE <= len, "{len}: {cfg:?}");
+
+        let tail = cfg.tail_len(len).expect("the layout to fit");
+        assert_eq!(tail, (len / PAGE - pages_for(&cfg)) * PAGE, "{len}: {cfg:?}");
+
+        // Never wastes more than the largest layout.
+        let largest = ConfigureFile::for_file(len);
+        assert!(tail <= largest.tail_len(len).unwrap(), "{len}: {cfg:?}");
+    }
+
+    // A layout that does not fit has no tail.
+    let cfg = ConfigureFile::fill(0x10_0000);
+    assert_eq!(cfg.tail_len(0x8_0000), None);
+}
+
+#[test]
+fn data_words_are_little_endian() {
+    with_setup(|mut head| {
+        head.pre_configure_pages(0x80);
+        head.pre_configure_entries(0x10);
+        head.configure_pages().unwrap();
+
+        // Start off a word boundary, the copy mixes byte-wise and word-wise stores.
+        let data: Vec<u8> = (1..=21).collect();
+        let mut entry = head.entry();
+        entry.copy_from_slice(&[0; 3]);
+        entry.copy_from_slice(&data);
+        entry.commit();
+
+        let words: Vec<u64> = head.data[0].data[..3]
+            .iter()
+            .map(|word| word.load(Ordering::Relaxed))
+            .collect();
+
+        assert_eq!(words[0], u64::from_le_bytes([0, 0, 0, 1, 2, 3, 4, 5]));
+        assert_eq!(words[1], u64::from_le_bytes([6, 7, 8, 9, 10, 11, 12, 13]));
+        assert_eq!(words[2], u64::from_le_bytes([14, 15, 16, 17, 18, 19, 20, 21]));
+
+        for offset in 0..24 {
+            let len = 24 - offset;
+            let mut into = vec![0; len];
+            let snapshot = crate::Snapshot::new(offset as u64, len as u64);
+            assert!(head.read(&snapshot, &mut into));
+
+            let expected: Vec<u8> = (0..24u8).map(|b| b.saturating_sub(2)).collect();
+            assert_eq!(into, expected[offset..], "{offset}");
+        }
+    })
+}
+
+#[test]
+fn adjacent_byte_writes_are_not_lost() {
+    with_setup(|mut head| {
+        head.pre_configure_pages(0x80);
+        head.pre_configure_entries(0x10);
+        head.configure_pages().unwrap();
+
+        let head = &head;
+        let start = &std::sync::Barrier::new(2);
+        std::thread::scope(|scope| {
+            for idx in 0..2u64 {
+                scope.spawn(move || {
+                    start.wait();
+                    // Each thread checks its previous write survived before writing the next.
+                    for n in 1..=1_000_000u32 {
+                        let byte = head.read_word_at(0).to_le_bytes()[idx as usize];
+                        assert_eq!(byte, (n - 1) as u8, "lost write to byte {idx}");
+                        head.write_at(idx, n as u8);
+                    }
+                });
+            }
+        });
+    })
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serde_round_trip() {
+    use crate::{Snapshot, SnapshotIndex};
+
+    let snapshots = [Snapshot { offset: 0x40, length: 12, entry: 3 }, Snapshot::new(0, 8)];
+    let json = serde_json::to_string(&snapshots).unwrap();
+    let back: Vec<Snapshot> = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, snapshots);
+
+    // Manifests may describe data without the slot of an entry.
+    let detached: Snapshot = serde_json::from_str(r#"{"offset":16,"length":4}"#).unwrap();
+    assert_eq!(detached, Snapshot::new(16, 4));
+
+    let index = SnapshotIndex { entry: 7 };
+    let json = serde_json::to_string(&index).unwrap();
+    let back: SnapshotIndex = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.entry, index.entry);
+}
+
 #[derive(Default)]
 struct TestSetup {
     head: HeadPage,
@@ -72,6 +256,8 @@ fn with_setup(method: impl FnOnce(WriteHead)) {
         cache: HeadCache::new(),
         meta: &mut test.head,
         sequence: &mut test.sequence,
+        checksums: &[],
+        epochs: &[],
         data: &mut test.data,
         tail: &[],
     })
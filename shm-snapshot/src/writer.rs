@@ -1,6 +1,9 @@
 use core::iter::Extend;
 use core::sync::atomic::{AtomicU64, Ordering};
-use memmap2::MmapRaw;
+use crate::le::LeU64;
+use crate::mapping::Mapping;
+
+use crate::crc::crc32c;
 
 /// A memory-mapped file into which this writer adds new snapshot.
 pub struct Writer {
@@ -12,6 +15,8 @@ pub struct Writer {
 /// Can be used to recover data, or convert into a `Writer`.
 pub struct File {
     pub(crate) head: Head,
+    /// Whether the file was mapped with write access, see [`File::new_readonly`].
+    pub(crate) writable: bool,
 }
 
 /// A view onto a memory-mapped file, which has a configured layout.
@@ -20,6 +25,55 @@ pub struct FileDiscovery<'lt> {
     pub(crate) configuration: ConfigureFile,
 }
 
+/// Reads the data of snapshots into a buffer that is reused between reads.
+///
+/// Created with [`FileDiscovery::reader`].
+pub struct SnapshotReader<'lt> {
+    pub(crate) discovery: &'lt FileDiscovery<'lt>,
+    pub(crate) buffer: Vec<u8>,
+}
+
+/// Streams the data of one snapshot, as [`std::io::Read`].
+///
+/// Created with [`FileDiscovery::stream`] or [`Writer::stream`]. In contrast to
+/// [`SnapshotReader`] the caller need not hold a buffer of the whole snapshot, for instance to
+/// deserialize directly from the file. Reading ends after the length of the snapshot.
+pub struct Reader<'lt> {
+    /// A head configured with the layout of the file, as for `Head::read_at`.
+    pub(crate) head: WriteHead,
+    pub(crate) snapshot: Snapshot,
+    /// The number of bytes of the snapshot already read.
+    pub(crate) position: u64,
+    /// The checksum of the bytes already read.
+    pub(crate) crc: u32,
+    pub(crate) file: core::marker::PhantomData<&'lt File>,
+}
+
+/// An iterator over the valid snapshots of a file.
+///
+/// Created with [`FileDiscovery::iter`] or [`Writer::iter`]. Entries are visited in the order of
+/// the sequence ring, the same order as [`FileDiscovery::valid`]. Clone the iterator to restart a
+/// scan from its current position.
+#[derive(Clone)]
+pub struct SnapshotIter<'lt> {
+    sequence: &'lt [SequencePage],
+    idx: u64,
+    max: u64,
+    /// The number of bytes of the data ring, no valid entry is longer.
+    capacity: u64,
+    epoch: u64,
+    epochs: Option<&'lt [AtomicU64]>,
+    checksums: Option<EntryChecksums<'lt>>,
+}
+
+/// The checksums of entries, with the data ring they cover.
+#[derive(Clone, Copy)]
+struct EntryChecksums<'lt> {
+    sums: &'lt [AtomicU64],
+    data: &'lt [DataPage],
+    page_mask: u64,
+}
+
 /// Describes the layout of a shared memory in a [`Writer`].
 #[derive(Default, Debug)]
 pub struct ConfigureFile {
@@ -29,28 +83,258 @@ pub struct ConfigureFile {
     pub data: u64,
     /// The offset of the next-to-write entry.
     pub initial_offset: u64,
+    /// The length of entries that the data ring must be able to hold, checked when configuring.
+    ///
+    /// This is not stored in the file. Set it to fail configuration early if the layout, for
+    /// instance one recovered from an existing file, can not hold the records you intend to
+    /// commit. See [`ConfigureFile::max_entry_len`].
+    pub min_entry_len: u64,
+    /// The number of bytes the tail must hold at least, checked when configuring.
+    ///
+    /// The tail is the remainder of the file after the rings, its size otherwise follows from the
+    /// size of the rings. This is not stored in the file.
+    pub tail_reserve: u64,
+    /// Keep the data of every entry contiguous in the data ring.
+    ///
+    /// An entry that would straddle the end of the data ring is instead written at its start, the
+    /// bytes skipped at the end are left unused as padding. This is stored in the file.
+    pub contiguous_entries: bool,
+    /// Store a checksum of the data of every entry.
+    ///
+    /// The CRC-32C of the data is computed when an entry is committed. Entries whose data does
+    /// not match, for instance in a copy of the file that raced a commit, are skipped when
+    /// iterating and fail to be read. The checksums take one page per 512 entries following the
+    /// sequence ring. This is stored in the file.
+    pub checksum: bool,
+    /// Tag every entry with the epoch it was committed in, see [`Writer::set_epoch`].
+    ///
+    /// Entries whose tag differs from the epoch in the head page are skipped when iterating. The
+    /// tags take one page per 512 entries following the checksums. This is stored in the file.
+    pub epochs: bool,
+    /// The size of pages in bytes, the unit to which the head page and both rings are padded.
+    ///
+    /// Zero selects pages of 4096 bytes, the layout of files without a page size. Otherwise this
+    /// must be a power of two and at least 4096. With the `libc` feature it must also be at least
+    /// the page size of the host, see `ConfigureFile::host_page_size`. With larger pages the data
+    /// ring and the tail start at a page boundary of the file. This is stored in the file.
+    pub page_size: u64,
     /// The indicate version in the file, or an explicit invalid number.
     ///
     /// Can't allow it to be public, it's not supposed to be arbitrarily set.
     pub(crate) layout_version: u64,
 }
 
+/// An error configuring the layout of a [`File`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConfigureError {
+    /// The data ring can not hold entries of the required minimum length.
+    EntryTooLarge {
+        /// The requested [`ConfigureFile::min_entry_len`].
+        required: u64,
+        /// The largest entry the layout can hold.
+        max_entry_len: u64,
+    },
+    /// The tail left by the rings can not hold the requested reserve.
+    TailTooSmall {
+        /// The requested [`ConfigureFile::tail_reserve`].
+        required: u64,
+        /// The number of bytes left for the tail by the layout.
+        available: u64,
+    },
+    /// The file holds data of an unknown format, which would be overwritten.
+    FormatVersionMismatch {
+        /// The version tag found in the file.
+        found: u64,
+    },
+    /// The file was opened with [`File::new_readonly`].
+    ReadOnly,
+    /// The file can not hold the head page and both rings of the layout.
+    TooSmall {
+        /// The number of bytes the head page and the rings require.
+        needed: u64,
+        /// The number of bytes of whole pages in the file.
+        have: u64,
+    },
+    /// The number of entries or the length of the data ring is not a power of two.
+    NotPowerOfTwo,
+    /// The configuration is not initialized, see [`ConfigureFile::or_insert_with`].
+    Uninitialized,
+    /// The page size is not supported, see [`ConfigureFile::page_size`].
+    PageSize {
+        /// The requested [`ConfigureFile::page_size`].
+        page_size: u64,
+    },
+    /// The layout does not tag entries with an epoch, see [`ConfigureFile::epochs`].
+    NoEpochs,
+}
+
+/// An error committing a snapshot with [`Writer::commit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CommitError {
+    /// The data ring can not hold an entry of this length.
+    DataTooLarge {
+        /// The length of the data, saturated if the chunks are longer than the address space.
+        len: u64,
+        /// The length of the largest entry the data ring can hold.
+        capacity: u64,
+    },
+    /// The function passed to [`Writer::commit_with`] rejected the transaction.
+    Rejected,
+    /// The file holds no layout, the writer was not configured.
+    Uninitialized,
+}
+
+/// An error recovering the contents of a [`File`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RecoverError {
+    /// The file is too small to hold the head page and both rings.
+    TooSmall,
+    /// The file does not hold any layout, it was never configured.
+    Uninitialized,
+    /// The file holds data of an unknown format.
+    FormatVersionMismatch {
+        /// The version tag found in the file.
+        found: u64,
+    },
+}
+
+/// An error reading the data of a [`Snapshot`] into a buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReadError {
+    /// The buffer is shorter than the snapshot.
+    BufferTooSmall {
+        /// The number of bytes the snapshot holds.
+        length: u64,
+    },
+    /// The buffer is longer than the snapshot, reported when an exact read was requested.
+    BufferTooLarge {
+        /// The number of bytes the snapshot holds.
+        length: u64,
+    },
+    /// The data read does not match the checksum of the entry holding the snapshot.
+    ///
+    /// The buffer holds the data, it was modified or torn since it was committed.
+    ChecksumMismatch,
+    /// The entry no longer holds the snapshot, its data may have been overwritten while copying.
+    ///
+    /// Also returned for snapshots without an entry, which can not be confirmed.
+    Stale,
+    /// The snapshot is longer than the data ring, it does not describe data of the file.
+    ExceedsCapacity {
+        /// The number of bytes the snapshot holds.
+        length: u64,
+    },
+}
+
+/// An error growing the rings of a [`Writer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum GrowError {
+    /// The requested size is not a power of two.
+    NotPowerOfTwo,
+    /// The requested size is smaller than the current one.
+    Shrink,
+    /// The file is too small to hold the requested layout.
+    TooSmall,
+    /// Resizing or mapping the file failed.
+    Io(std::io::ErrorKind),
+}
+
 pub struct Head {
     head: WriteHead,
-    /// The memory map protecting the validity of the write head. This is purely for safety, not
-    /// accessing the field besides `Drop`.
-    #[allow(dead_code)]
-    file: MmapRaw,
+    /// The memory map protecting the validity of the write head. This is mostly for safety, it is
+    /// only accessed to re-derive the pointers of the head when changing the layout.
+    file: Box<dyn Mapping>,
 }
 
 /// The descriptor of a singular snapshot.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Snapshot {
     /// The offset of data in the data ring.
     pub offset: u64,
     /// The length of data in the data ring. A non-zero length marks a valid entry, a zero length
     /// an invalid entry.
     pub length: u64,
+    /// The slot in the sequence ring holding this snapshot, or [`Snapshot::NO_ENTRY`].
+    ///
+    /// Use [`FileDiscovery::is_current`] to check that the slot still holds this snapshot.
+    #[cfg_attr(feature = "serde", serde(default = "Snapshot::no_entry"))]
+    pub entry: u64,
+}
+
+impl Snapshot {
+    /// The entry of a snapshot that was not read from the sequence ring.
+    pub const NO_ENTRY: u64 = u64::MAX;
+
+    #[cfg(feature = "serde")]
+    fn no_entry() -> u64 {
+        Self::NO_ENTRY
+    }
+
+    /// Describe data in the data ring, not associated with any entry of the sequence ring.
+    pub fn new(offset: u64, length: u64) -> Self {
+        Snapshot {
+            offset,
+            length,
+            entry: Self::NO_ENTRY,
+        }
+    }
+}
+
+/// A copy of the header page of a file, as read with [`File::header`].
+///
+/// The header page is the first 4096 bytes of the file. All fields are little-endian `u64`, the
+/// byte offset of each field in the page is noted in its documentation. The masks and write offset
+/// are stored twice, at offset `8` and at offset `48`, and `current` selects the copy in use. This
+/// view holds the values of the selected copy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct HeaderView {
+    /// The format version of the file, at offset `0`. Zero if the file was never configured.
+    pub version: u64,
+    /// The layout copy in use, at offset `40`. `0` for the copy at offset `8`, `1` for the copy at
+    /// offset `48`.
+    pub current: u64,
+    /// The number of entries in the sequence ring, minus one. At offset `0` of a layout copy.
+    pub entry_mask: u64,
+    /// The number of bytes in the data ring, minus one. At offset `8` of a layout copy.
+    pub page_mask: u64,
+    /// The stream offset of the next byte to write. At offset `16` of a layout copy.
+    pub page_write_offset: u64,
+    /// Layout options, a bit set. At offset `24` of a layout copy.
+    ///
+    /// The bit `1` is set if entries are not split across the end of the data ring. The bit `2` is
+    /// set if entries carry a checksum. The bit `4` is set if entries carry the epoch they were
+    /// committed in. The bits `8..16` hold the page size, as the power of two
+    /// by which it exceeds 4096 bytes.
+    pub flags: u64,
+    /// The commit marker, at offset `80`. See [`File::commit_marker`].
+    pub commits: u64,
+    /// The application-defined epoch, at offset `88`. See [`Writer::set_epoch`].
+    pub epoch: u64,
+}
+
+/// The layout and occupancy of a [`Writer`], as returned by [`Writer::stats`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct WriterStats {
+    /// The number of entries the sequence ring holds, see [`Writer::entry_capacity`].
+    pub entry_capacity: u64,
+    /// The number of bytes in the data ring, see [`Writer::data_capacity`].
+    pub data_capacity: u64,
+    /// The number of bytes of the data ring occupied by valid entries, see [`Writer::data_used`].
+    pub data_used: u64,
+    /// The number of valid entries in the sequence ring.
+    pub entries_valid: u64,
+    /// The stream offset of the next byte to write.
+    pub write_offset: u64,
+    /// The format version stored in the file, zero while the layout is being changed.
+    pub layout_version: u64,
 }
 
 pub(crate) trait Collect<T> {
@@ -77,6 +361,21 @@ pub struct PreparedTransaction<'lt> {
     length: u64,
     head: &'lt mut WriteHead,
     tail: &'lt [DataPage],
+    /// Set by [`PreparedTransaction::abort`], the entry is then not committed.
+    aborted: &'lt core::cell::Cell<bool>,
+}
+
+/// An entry of a writer's ring, reserved to be filled in place before it is committed.
+///
+/// Returned by [`Writer::reserve`]. The data ring already holds the bytes of older entries at the
+/// reserved range, all of them must be written. The entry is discarded when this is dropped
+/// without calling [`Reservation::commit`].
+///
+/// The reserved range is split into whole words of the data ring, see [`Reservation::words`],
+/// and up to seven bytes at each edge that share their word with neighbouring data. Those are
+/// written with [`Reservation::write`].
+pub struct Reservation<'lt> {
+    entry: Option<Entry<'lt>>,
 }
 
 /// Resolved pointers _into_ a memory map.
@@ -98,11 +397,26 @@ pub(crate) struct WriteHead {
     pub(crate) cache: HeadCache,
     pub(crate) meta: &'static HeadPage,
     pub(crate) sequence: &'static [SequencePage],
+    /// The checksum of each entry of the sequence ring, empty unless configured.
+    pub(crate) checksums: &'static [DataPage],
+    /// The epoch of each entry of the sequence ring, empty unless configured.
+    pub(crate) epochs: &'static [DataPage],
     pub(crate) data: &'static [DataPage],
     /// Data pages from the shared memory which we do not touch ourselves, i.e. user reserved.
     pub(crate) tail: &'static [DataPage],
 }
 
+/// The placement of the rings, in blocks of 4096 bytes following the first block of the file.
+///
+/// With larger pages the head page fills its whole page and the rings are padded to whole pages,
+/// so that the data ring and the tail start at a page boundary.
+struct PageLayout {
+    sequence: core::ops::Range<usize>,
+    checksums: core::ops::Range<usize>,
+    epochs: core::ops::Range<usize>,
+    data: core::ops::Range<usize>,
+}
+
 struct HeadMapRaw {
     meta: *const HeadPage,
     sequence: *const [SequencePage],
@@ -112,48 +426,411 @@ struct HeadMapRaw {
 impl Head {
     fn fitting_power_of_two(value: u64) -> u64 {
         const HIGEST_BIT_SET: u64 = !((!0) >> 1);
-        // Must be a power of two, use the next lower one.
-        HIGEST_BIT_SET >> value.leading_zeros()
+        // Must be a power of two, use the next lower one. No power of two fits into zero.
+        HIGEST_BIT_SET.checked_shr(value.leading_zeros()).unwrap_or(0)
+    }
+
+    /// Check if the file can not hold the head page and a page for each ring.
+    pub(crate) fn is_too_small(&self) -> bool {
+        self.head.data.len() < 2
     }
 
     pub(crate) fn discover(&self, cfg: &mut ConfigureFile) {
-        let entry_mask = self.head.meta.entry_mask.load(Ordering::Relaxed);
-        let data_mask = self.head.meta.page_mask.load(Ordering::Relaxed);
-        let page_write_offset = self.head.meta.page_write_offset.load(Ordering::Relaxed);
+        let layout = self.head.meta.current();
+        let entry_mask = layout.entry_mask.load(Ordering::Relaxed);
+        let data_mask = layout.page_mask.load(Ordering::Relaxed);
+        let page_write_offset = layout.page_write_offset.load(Ordering::Relaxed);
+        let flags = layout.flags.load(Ordering::Relaxed);
 
         let layout_version = self.head.meta.version.load(Ordering::Relaxed);
-        assert!(entry_mask < usize::MAX as u64);
-        assert!(data_mask < usize::MAX as u64);
+        // A mask of all ones, from a corrupted head, describes an empty ring.
+        let entries = entry_mask.wrapping_add(1);
 
-        let sequence = (entry_mask + 1) as usize;
+        let checksum = flags & HeadPage::FLAG_CHECKSUM != 0;
+        let epochs = flags & HeadPage::FLAG_EPOCHS != 0;
+        let page_shift = ((flags >> HeadPage::PAGE_SHIFT_BIT) & 0xff) as u32;
         // Assume this refers to the whole tail at this point?
         let pages = self.head.data.len();
-        let psequence = sequence / SequencePage::DATA_COUNT
-            + usize::from(sequence % SequencePage::DATA_COUNT != 0);
+        // The pages left for data once the sequence ring is placed, none if it does not fit.
+        let data_pages = PageLayout::new(entries, 0, checksum, epochs, page_shift)
+            .and_then(|layout| pages.checked_sub(layout.data.start))
+            .unwrap_or(0);
 
-        let data_space = (pages - psequence) as u64 * core::mem::size_of::<DataPage>() as u64;
-        let available_entries = Self::fitting_power_of_two(entry_mask + 1);
+        let data_space = data_pages as u64 * core::mem::size_of::<DataPage>() as u64;
+        let available_entries = Self::fitting_power_of_two(entries);
         let available_data = Self::fitting_power_of_two(data_space);
 
+        // A layout without entries or data can not hold any snapshot, it is not initialized.
+        if available_entries == 0 || available_data == 0 {
+            cfg.entries = 0;
+            cfg.data = 0;
+            cfg.layout_version = 0;
+            return;
+        }
+
         cfg.entries = available_entries;
-        cfg.data = available_data.min(data_mask + 1);
+        cfg.data = available_data.min(data_mask.saturating_add(1));
         cfg.initial_offset = page_write_offset;
+        cfg.contiguous_entries = flags & HeadPage::FLAG_CONTIGUOUS_ENTRIES != 0;
+        cfg.checksum = checksum;
+        cfg.epochs = epochs;
+        cfg.page_size = ConfigureFile::page_size_for(page_shift);
         cfg.layout_version = layout_version;
+
+        if let Some(migrate) = ConfigureFile::migration_for(layout_version) {
+            migrate(cfg);
+        }
+    }
+
+    pub(crate) fn configure(&mut self, cfg: &ConfigureFile) -> Result<(), ConfigureError> {
+        let found = self.head.meta.version.load(Ordering::Acquire);
+        if found != 0 && !ConfigureFile::is_known_version(found) {
+            return Err(ConfigureError::FormatVersionMismatch { found });
+        }
+
+        if cfg.page_size != 0 && cfg.page_size < ConfigureFile::min_page_size() {
+            return Err(ConfigureError::PageSize {
+                page_size: cfg.page_size,
+            });
+        }
+
+        if cfg.max_entry_len() < cfg.min_entry_len {
+            return Err(ConfigureError::EntryTooLarge {
+                required: cfg.min_entry_len,
+                max_entry_len: cfg.max_entry_len(),
+            });
+        }
+
+        // Rings that do not fit are reported when laying out the pages.
+        if let Some(available) = self.tail_len_for(cfg) {
+            if available < cfg.tail_reserve {
+                return Err(ConfigureError::TailTooSmall {
+                    required: cfg.tail_reserve,
+                    available,
+                });
+            }
+        }
+
+        Self::pre_configure_head(&mut self.head, cfg)?;
+        self.head.configure_pages()?;
+        self.head.restore_cursors();
+        Ok(())
+    }
+
+    /// The number of bytes in the tail with the layout of `cfg`, `None` if the rings do not fit.
+    fn tail_len_for(&self, cfg: &ConfigureFile) -> Option<u64> {
+        let page = core::mem::size_of::<DataPage>() as u64;
+        let pages = self.head.data.len();
+
+        PageLayout::of(cfg)
+            .and_then(|layout| pages.checked_sub(layout.data.end))
+            .map(|tail| tail as u64 * page)
+    }
+
+    /// Zero the sequence ring described by `cfg`, if the file holds no initialized layout.
+    ///
+    /// Returns whether the ring was zeroed. The data ring is left as is, no entry refers to it.
+    pub(crate) fn zero_uninitialized(&mut self, cfg: &ConfigureFile) -> bool {
+        if ConfigureFile::is_known_version(self.head.meta.version.load(Ordering::Acquire)) {
+            return false;
+        }
+
+        let pages = self.head.sequence.len();
+        let psequence = PageLayout::of(cfg).map_or(0..pages, |layout| {
+            layout.sequence.start.min(pages)..layout.sequence.end.min(pages)
+        });
+
+        for entry in self.head.sequence[psequence].iter().flat_map(|seq| &seq.data) {
+            entry.offset.store(0, Ordering::Relaxed);
+            entry.length.store(0, Ordering::Relaxed);
+        }
+
+        true
+    }
+
+    /// Move all entries into a larger sequence ring.
+    ///
+    /// The sequence ring precedes the data ring in the file. If the larger sequence ring requires
+    /// additional pages, the file grows by that number of pages first and the data ring and the
+    /// tail are moved back into them, keeping the whole tail.
+    pub(crate) fn grow_entries(&mut self, entries: u64) -> Result<(), GrowError> {
+        if !entries.is_power_of_two() {
+            return Err(GrowError::NotPowerOfTwo);
+        }
+
+        let current = self.head.cache.entry_mask + 1;
+        if entries < current {
+            return Err(GrowError::Shrink);
+        }
+
+        if entries == current {
+            return Ok(());
+        }
+
+        let cache = &self.head.cache;
+        let layout_for = |entries| {
+            let (checksum, epochs) = (cache.checksum, cache.epochs);
+            PageLayout::new(entries, cache.page_mask + 1, checksum, epochs, cache.page_shift)
+        };
+
+        let old = layout_for(current).ok_or(GrowError::TooSmall)?;
+        let new = layout_for(entries).ok_or(GrowError::TooSmall)?;
+
+        // Make room for the added pages, so that the end of the tail is not shifted out.
+        if new.data.start > old.data.start {
+            let added = (new.data.start - old.data.start) as u64 * HeadPage::PAGE_SZ as u64;
+            let len = u64::try_from(self.file.len())
+                .ok()
+                .and_then(|len| len.checked_add(added))
+                .ok_or(GrowError::TooSmall)?;
+            let file = self.file.resize(len).map_err(|err| GrowError::Io(err.kind()))?;
+            self.remap(file)?;
+        }
+
+        // Safety: the pointer and length describe the mapping we own.
+        let full = unsafe { Self::map_all_raw(self.file.as_mut_ptr(), self.file.len()) }
+            .ok_or(GrowError::TooSmall)?;
+        // Safety: see `from_map`, the mapping is kept alive by `self.file`.
+        let (sequence, data) = unsafe { (&*full.sequence, &*full.data) };
+
+        if new.data.end > data.len() {
+            return Err(GrowError::TooSmall);
+        }
+
+        let live: Vec<_> = {
+            let head = &self.head;
+            let read = head.cache.entry_read_offset;
+            let count = head.cache.entry_write_offset.wrapping_sub(read);
+            (0..count)
+                .map(|n| read.wrapping_add(n))
+                .map(|idx| {
+                    let snapshot = head.entry_at_relaxed(idx);
+                    (idx, snapshot, head.checksum_at(idx), head.epoch_at(idx))
+                })
+                .collect()
+        };
+
+        // Readers must not interpret the file while we are moving entries.
+        self.head.meta.version.store(0, Ordering::Relaxed);
+        core::sync::atomic::fence(Ordering::Release);
+
+        for entry in self.head.sequence.iter().flat_map(|seq| &seq.data) {
+            entry.offset.store(0, Ordering::Relaxed);
+            entry.length.store(0, Ordering::Relaxed);
+        }
+
+        if new.data.start > old.data.start {
+            let shift = new.data.start - old.data.start;
+
+            for dst in (new.data.start..data.len()).rev() {
+                for (to, from) in data[dst].data.iter().zip(&data[dst - shift].data) {
+                    to.store(from.load(Ordering::Relaxed), Ordering::Relaxed);
+                }
+            }
+        }
+
+        // The new sequence pages, all checksums and epochs, which are stored again below.
+        for page in &data[old.sequence.end..new.data.start] {
+            for word in &page.data {
+                word.store(0, Ordering::Relaxed);
+            }
+        }
+
+        self.head.sequence = sequence;
+        self.head.data = data;
+        self.head.pre_configure_entries(entries);
+        self.head
+            .layout_pages()
+            .expect("Checked the grown layout fits the file");
+
+        for (idx, snapshot, checksum, epoch) in live {
+            if let Some(checksum) = checksum {
+                self.head.store_checksum(idx, checksum);
+            }
+
+            if let Some(epoch) = epoch {
+                self.head.store_epoch(idx, epoch);
+            }
+
+            self.head.insert_at(idx, snapshot);
+        }
+
+        self.head.store_meta();
+        Ok(())
+    }
+
+    /// Resize the file to `len` bytes and map it again, keeping all entries and the tail.
+    ///
+    /// The data ring is doubled for as long as the tail keeps its length, and the tail is moved
+    /// behind it. The live data is moved to its offset in the larger ring. A shorter file cuts off
+    /// the end of the tail, but never the rings.
+    pub(crate) fn grow(&mut self, len: u64) -> Result<(), GrowError> {
+        let block = HeadPage::PAGE_SZ as u64;
+        let data = self.head.cache.page_mask + 1;
+        let tail = self.head.tail.len();
+
+        // The blocks after the head block, as in `map_all_raw`.
+        let pages = usize::try_from(len / block)
+            .ok()
+            .and_then(|pages| pages.checked_sub(1))
+            .ok_or(GrowError::Shrink)?;
+
+        let (old, grown) = {
+            let cache = &self.head.cache;
+            let layout_for = |data| {
+                let (checksum, epochs) = (cache.checksum, cache.epochs);
+                PageLayout::new(cache.entry_mask + 1, data, checksum, epochs, cache.page_shift)
+            };
+
+            let old = layout_for(data).ok_or(GrowError::TooSmall)?;
+            let fits = |data| {
+                layout_for(data)
+                    .and_then(|layout| layout.data.end.checked_add(tail))
+                    .is_some_and(|end| end <= pages)
+            };
+
+            let mut grown = data;
+            while let Some(larger) = grown.checked_mul(2).filter(|&larger| fits(larger)) {
+                grown = larger;
+            }
+
+            let grown = layout_for(grown).filter(|_| grown > data).map(|layout| (grown, layout));
+            (old, grown)
+        };
+
+        if pages < old.data.end {
+            return Err(GrowError::Shrink);
+        }
+
+        let file = self.file.resize(len).map_err(|err| GrowError::Io(err.kind()))?;
+        self.remap(file)?;
+
+        // Safety: see `from_map`, the mapping is kept alive by `self.file`.
+        let full = unsafe { Self::map_all_raw(self.file.as_mut_ptr(), self.file.len()) }
+            .ok_or(GrowError::TooSmall)?;
+        let (sequence, pages) = unsafe { (&*full.sequence, &*full.data) };
+
+        let Some((grown, new)) = grown else {
+            return Ok(());
+        };
+
+        // Every position of the grown ring is addressable, the casts to `usize` below are exact.
+        let ring_len = usize::try_from(grown).map_err(|_| GrowError::TooSmall)?;
+        let block = HeadPage::PAGE_SZ;
+        // The byte offset in the file of a page following the head block, as in `map_all_raw`.
+        let byte_of = |page: usize| (page + 1) * block;
+        let ring = byte_of(new.data.start);
+
+        // Readers must not interpret the file while we are moving data.
+        self.head.meta.version.store(0, Ordering::Relaxed);
+        core::sync::atomic::fence(Ordering::Release);
+
+        // Back to front: first the tail, then the data that moves within the grown ring.
+        self.copy_within(byte_of(old.data.end), byte_of(new.data.end), tail * block);
+        self.zero(ring + data as usize, ring_len - data as usize);
+
+        // The last `data` bytes of the stream are all that entries can refer to. They are split
+        // only where the stream wraps around the old ring, which the grown ring wraps with.
+        let write = self.head.cache.page_write_offset;
+        let mut offset = write.saturating_sub(data);
+        while offset < write {
+            let end = write.min((offset | (data - 1)).saturating_add(1));
+            let src = ring + (offset & (data - 1)) as usize;
+            let dst = ring + (offset & (grown - 1)) as usize;
+            self.copy_within(src, dst, (end - offset) as usize);
+            offset = end;
+        }
+
+        self.head.sequence = sequence;
+        self.head.data = pages;
+        self.head.pre_configure_pages(grown);
+        self.head
+            .layout_pages()
+            .expect("Checked the grown layout fits the file");
+
+        self.head.store_meta();
+        Ok(())
+    }
+
+    /// Move `len` bytes of the mapping from the offset `src` to `dst`, the ranges may overlap.
+    fn copy_within(&self, src: usize, dst: usize, len: usize) {
+        let end = src.max(dst).checked_add(len);
+        assert!(end.is_some_and(|end| end <= self.file.len()));
+        // Safety: both ranges are in bounds of the mapping, which is kept alive by `self.file`.
+        // Only the writer modifies it, and readers ignore the file while its header is invalid.
+        unsafe {
+            let base = self.file.as_mut_ptr();
+            core::ptr::copy(base.add(src), base.add(dst), len);
+        }
+    }
+
+    /// Clear `len` bytes of the mapping from the offset `at`.
+    fn zero(&self, at: usize, len: usize) {
+        assert!(at.checked_add(len).is_some_and(|end| end <= self.file.len()));
+        // Safety: as in `copy_within`.
+        unsafe { core::ptr::write_bytes(self.file.as_mut_ptr().add(at), 0, len) }
     }
 
-    pub(crate) fn configure(&mut self, cfg: &ConfigureFile) {
-        Self::configure_head(&mut self.head, cfg)
+    /// Switch to a resized mapping of the file, keeping the current layout.
+    fn remap(&mut self, file: Box<dyn Mapping>) -> Result<(), GrowError> {
+        // Safety: the pointer and length describe the new mapping, which is kept alive by
+        // `self.file` below. See `from_map`.
+        let full = unsafe { Self::map_all_raw(file.as_mut_ptr(), file.len()) }
+            .ok_or(GrowError::TooSmall)?;
+        let (meta, sequence, pages) = unsafe { (&*full.meta, &*full.sequence, &*full.data) };
+
+        self.head.meta = meta;
+        self.head.sequence = sequence;
+        self.head.data = pages;
+        self.head
+            .layout_pages()
+            .expect("Checked the layout fits the resized file");
+        // The head no longer refers to the previous mapping.
+        self.file = file;
+        Ok(())
+    }
+
+    /// Check that a discovered configuration describes a layout fitting into the file.
+    pub(crate) fn check_layout(&self, cfg: &ConfigureFile) -> Result<(), ConfigureError> {
+        let mut alternate_head = WriteHead {
+            cache: HeadCache { ..self.head.cache },
+            ..self.head
+        };
+
+        Self::pre_configure_head(&mut alternate_head, cfg)?;
+        alternate_head.layout_pages()
     }
 
+    /// Configure the layout of an alternate head, used to read with a discovered configuration.
+    ///
+    /// This does not store the header, readers must not modify it.
     fn configure_head(head: &mut WriteHead, cfg: &ConfigureFile) {
-        assert!(cfg.entries.next_power_of_two() == cfg.entries);
-        assert!(cfg.data.next_power_of_two() == cfg.data);
-        assert!(cfg.is_initialized());
+        Self::pre_configure_head(head, cfg)
+            .and_then(|()| head.layout_pages())
+            .expect("Checked the discovered layout fits the file in `File::try_recover`");
+    }
+
+    fn pre_configure_head(head: &mut WriteHead, cfg: &ConfigureFile) -> Result<(), ConfigureError> {
+        if !cfg.is_initialized() {
+            return Err(ConfigureError::Uninitialized);
+        }
+
+        if !cfg.entries.is_power_of_two() || !cfg.data.is_power_of_two() {
+            return Err(ConfigureError::NotPowerOfTwo);
+        }
+
+        let page_shift = cfg.page_shift().ok_or(ConfigureError::PageSize {
+            page_size: cfg.page_size,
+        })?;
 
         head.pre_configure_entries(cfg.entries);
         head.pre_configure_pages(cfg.data);
         head.pre_configure_write(cfg.initial_offset);
-        head.configure_pages();
+        head.pre_configure_contiguous(cfg.contiguous_entries);
+        head.pre_configure_checksum(cfg.checksum);
+        head.pre_configure_epochs(cfg.epochs);
+        head.pre_configure_page_shift(page_shift);
+        Ok(())
     }
 
     #[inline(always)]
@@ -171,6 +848,20 @@ impl Head {
         Self::valid_in_head(&alternate_head, into);
     }
 
+    pub(crate) fn iter(&self) -> SnapshotIter<'static> {
+        self.head.snapshots()
+    }
+
+    pub(crate) fn iter_at(&self, cfg: &ConfigureFile) -> SnapshotIter<'static> {
+        let mut alternate_head = WriteHead {
+            cache: HeadCache { ..self.head.cache },
+            ..self.head
+        };
+
+        Self::configure_head(&mut alternate_head, cfg);
+        alternate_head.snapshots()
+    }
+
     pub(crate) fn retain_at(&self, retain: &dyn super::RetainSnapshot, cfg: &ConfigureFile) {
         let mut alternate_head = WriteHead {
             cache: HeadCache { ..self.head.cache },
@@ -181,6 +872,35 @@ impl Head {
         Self::retain_in_head(&alternate_head, retain);
     }
 
+    /// The newest stored entry of the sequence ring with the layout of `cfg`.
+    pub(crate) fn newest_entry_at(&self, cfg: &ConfigureFile) -> Option<Snapshot> {
+        let mut alternate_head = WriteHead {
+            cache: HeadCache { ..self.head.cache },
+            ..self.head
+        };
+
+        Self::configure_head(&mut alternate_head, cfg);
+        alternate_head.newest_entry()
+    }
+
+    /// Check that the sequence ring, with the layout of `cfg`, still holds the snapshot.
+    pub(crate) fn is_current_at(&self, snapshot: &Snapshot, cfg: &ConfigureFile) -> bool {
+        if snapshot.length == 0 || snapshot.entry > cfg.entries.wrapping_sub(1) {
+            return false;
+        }
+
+        let mut alternate_head = WriteHead {
+            cache: HeadCache { ..self.head.cache },
+            ..self.head
+        };
+
+        Self::configure_head(&mut alternate_head, cfg);
+        // Order the data loads before, see `SequenceEntry`.
+        core::sync::atomic::fence(Ordering::Acquire);
+        alternate_head.entry_at_relaxed(snapshot.entry) == *snapshot
+            && alternate_head.is_current_epoch(snapshot.entry)
+    }
+
     pub(crate) fn entry_at(&self, idx: super::SnapshotIndex) -> Snapshot {
         let snapshot = self.head.entry_at_relaxed(idx.entry);
         core::sync::atomic::fence(Ordering::Acquire);
@@ -200,7 +920,7 @@ impl Head {
             }
         }
 
-        // Relaxed ordering is enough since we're the only reader still.
+        // Collecting never invalidates, the ordering is unused.
         head.iter_valid(&mut Collector(into), Ordering::Relaxed);
     }
 
@@ -216,38 +936,79 @@ impl Head {
         head.iter_valid(&mut Retain(into), Ordering::Relaxed);
     }
 
-    pub(crate) fn read(&self, snapshot: &Snapshot, into: &mut [u8]) {
-        self.head.read(snapshot, into);
+    /// Read the data of a snapshot, returns whether it matches its checksum.
+    pub(crate) fn read(&self, snapshot: &Snapshot, into: &mut [u8]) -> bool {
+        self.head.read(snapshot, into)
+    }
+
+    pub(crate) fn read_consistent(&self, snapshot: &Snapshot, into: &mut [u8]) -> Result<(), ReadError> {
+        self.head.read_consistent(snapshot, into)
+    }
+
+    pub(crate) fn read_consistent_at(
+        &self,
+        snapshot: &Snapshot,
+        into: &mut [u8],
+        cfg: &ConfigureFile,
+    ) -> Result<(), ReadError> {
+        let mut alternate_head = WriteHead {
+            cache: HeadCache { ..self.head.cache },
+            ..self.head
+        };
+
+        Self::configure_head(&mut alternate_head, cfg);
+        alternate_head.read_consistent(snapshot, into)
+    }
+
+    pub(crate) fn stream(&self, snapshot: Snapshot) -> Reader<'_> {
+        let head = WriteHead {
+            cache: HeadCache { ..self.head.cache },
+            ..self.head
+        };
+
+        Reader::new(head, snapshot)
+    }
+
+    pub(crate) fn stream_at(&self, snapshot: Snapshot, cfg: &ConfigureFile) -> Reader<'_> {
+        let mut alternate_head = WriteHead {
+            cache: HeadCache { ..self.head.cache },
+            ..self.head
+        };
+
+        Self::configure_head(&mut alternate_head, cfg);
+        Reader::new(alternate_head, snapshot)
     }
 
-    pub(crate) fn read_at(&self, snapshot: &Snapshot, into: &mut [u8], cfg: &ConfigureFile) {
+    pub(crate) fn read_at(&self, snapshot: &Snapshot, into: &mut [u8], cfg: &ConfigureFile) -> bool {
         let mut alternate_head = WriteHead {
             cache: HeadCache { ..self.head.cache },
             ..self.head
         };
 
         Self::configure_head(&mut alternate_head, cfg);
-        alternate_head.read(snapshot, into);
+        alternate_head.read(snapshot, into)
     }
 
     /// Construct this wrapper
-    pub(crate) fn from_map(file: MmapRaw) -> Self {
+    pub(crate) fn from_map(file: Box<dyn Mapping>) -> Self {
         /// The head page we simulate if the file is too small to contain anything.
         ///
         /// The user will just notice that we can't write, but the construction itself won't fail.
         /// That happens later when the head is converted to a writer and the caller selected some
         /// minimum requirements. Here we just fulfill validity.
         static FALLBACK_HEAD: HeadPage = HeadPage {
-            version: AtomicU64::new(ConfigureFile::MAGIC_VERSION),
-            entry_mask: AtomicU64::new(0),
-            page_mask: AtomicU64::new(0),
-            page_write_offset: AtomicU64::new(0),
+            version: LeU64::new(ConfigureFile::MAGIC_VERSION),
+            layout: HeadLayout::new(),
+            current: LeU64::new(0),
+            shadow: HeadLayout::new(),
+            commits: LeU64::new(0),
+            epoch: LeU64::new(0),
         };
 
         let ptr = file.as_mut_ptr();
         let len = file.len();
 
-        let head = if let Some(head) = unsafe { Self::map_all_raw(ptr, len) } {
+        let mut head = if let Some(head) = unsafe { Self::map_all_raw(ptr, len) } {
             // Safety: pointers returned are still in-bounds. By keeping `file` we also ensure that
             // the mapping is kept in place. The types themselves are full atomics, meaning we do
             // not have any uniqueness requirements on the pointer.
@@ -260,6 +1021,8 @@ impl Head {
                     cache: HeadCache::new(),
                     meta: &*head.meta,
                     sequence: &*head.sequence,
+                    checksums: &[],
+                    epochs: &[],
                     data: &*head.data,
                     tail: &[],
                 }
@@ -270,10 +1033,13 @@ impl Head {
                 meta: &FALLBACK_HEAD,
                 data: &[],
                 sequence: &[],
+                checksums: &[],
+                epochs: &[],
                 tail: &[],
             }
         };
 
+        head.cache.layout = head.meta.current.load(Ordering::Acquire) & 1;
         Head { head, file }
     }
 
@@ -281,6 +1047,43 @@ impl Head {
         DataPage::as_slice_of_u64(self.head.tail)
     }
 
+    pub(crate) fn entry_capacity(&self) -> u64 {
+        self.head.cache.entry_mask.wrapping_add(1)
+    }
+
+    pub(crate) fn data_capacity(&self) -> u64 {
+        self.head.cache.page_mask.wrapping_add(1)
+    }
+
+    pub(crate) fn data_used(&self) -> u64 {
+        self.head.data_used()
+    }
+
+    /// Scans the sequence ring for valid entries.
+    pub(crate) fn stats(&self) -> WriterStats {
+        WriterStats {
+            entry_capacity: self.entry_capacity(),
+            data_capacity: self.data_capacity(),
+            data_used: self.data_used(),
+            entries_valid: self.iter().count() as u64,
+            write_offset: self.head.cache.page_write_offset,
+            layout_version: self.head.meta.version.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Synchronize all modifications of the mapping with the file, with `msync`.
+    pub(crate) fn flush(&self) -> Result<(), std::io::Error> {
+        self.file.flush()
+    }
+
+    /// Synchronize all modifications of the mapping with the file, then forget the layout.
+    ///
+    /// The returned head maps the whole file again, as if freshly constructed with `from_map`.
+    pub(crate) fn finalize(self) -> Result<Self, std::io::Error> {
+        self.file.flush()?;
+        Ok(Head::from_map(self.file))
+    }
+
     /// Safety:
     ///
     /// Call promises that `ptr` points to an allocation valid for at least `len` bytes, that is
@@ -302,49 +1105,250 @@ impl Head {
     }
 }
 
+/// Adjusts a configuration discovered in a file of a prior format.
+type Migration = fn(&mut ConfigureFile);
+
 impl ConfigureFile {
-    pub(crate) const MAGIC_VERSION: u64 = 0x96c2_a6f4b68519b3;
+    /// The version of the current format, written by `configure`.
+    ///
+    /// All fields of the head page and the sequence ring are little-endian. The last two bytes of
+    /// the magic in the file read `LE`, marking this byte order.
+    pub(crate) const MAGIC_VERSION: u64 = 0x454c_9d3e_2a71_c6b5;
 
-    /// Is the configuration data complete?
-    pub fn is_initialized(&self) -> bool {
-        self.layout_version == Self::MAGIC_VERSION
+    /// Prior formats that can still be read, with a hook to migrate the discovered configuration.
+    ///
+    /// The file itself is migrated by configuring it with the migrated configuration.
+    const PRIOR_VERSIONS: &'static [(u64, Migration)] = &[
+        // The released format, with a single layout at the position of the first layout slot.
+        // The fields added since read as zero: the slot selector as the first slot, no layout
+        // flags, epoch zero, no checksums or epoch tags and pages of 4096 bytes. Its fields are
+        // in the byte order of the host that wrote it, which agrees only on little-endian hosts.
+        // Elsewhere its version reads byte-swapped and is not recognized.
+        (0x96c2_a6f4b68519b3, |cfg| cfg.contiguous_entries = false),
+    ];
+
+    fn is_known_version(version: u64) -> bool {
+        version == Self::MAGIC_VERSION || Self::migration_for(version).is_some()
     }
 
-    /// Complete this configuration, if it is not already.
-    pub fn or_insert_with(&mut self, replace: impl FnOnce(&mut Self)) {
-        if !self.is_initialized() {
-            replace(self);
-            self.layout_version = ConfigureFile::MAGIC_VERSION;
-        }
+    fn migration_for(version: u64) -> Option<Migration> {
+        Self::PRIOR_VERSIONS
+            .iter()
+            .find(|&&(prior, _)| prior == version)
+            .map(|&(_, migrate)| migrate)
     }
-}
 
-impl Head {
-    pub(crate) fn write_with(
-        &mut self,
-        data: &[u8],
-        intermediate: &mut dyn FnMut(PreparedTransaction) -> bool,
-    ) -> Result<u64, ()> {
-        let mut entry = self.head.entry();
-        let Some(end_ptr) = entry.new_write_offset(data.len()) else {
-            return Err(());
-        };
+    /// The largest supported page size, 1 GiB.
+    const MAX_PAGE_SHIFT: u32 = 18;
 
-        entry.invalidate_heads(end_ptr);
-        entry.copy_from_slice(data);
+    /// The size of pages of the host, the smallest [`Self::page_size`] that can be configured.
+    ///
+    /// Requires the `libc` feature. Without it, page sizes are not checked against the host.
+    #[cfg(feature = "libc")]
+    pub fn host_page_size() -> u64 {
+        // Safety: `sysconf` has no preconditions.
+        let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        u64::try_from(size).unwrap_or(HeadPage::PAGE_SZ as u64)
+    }
 
-        if intermediate(PreparedTransaction {
-            offset: entry.offset,
-            length: entry.length,
-            tail: entry.head.tail,
-            head: entry.head,
-        }) {
-            Ok(entry.commit())
-        } else {
-            Err(())
+    /// The smallest page size that can be configured, the host's if it is known.
+    fn min_page_size() -> u64 {
+        #[cfg(feature = "libc")]
+        let size = Self::host_page_size();
+        #[cfg(not(feature = "libc"))]
+        let size = HeadPage::PAGE_SZ as u64;
+        size
+    }
+
+    /// The power of two by which pages exceed 4096 bytes, `None` for an unsupported page size.
+    fn page_shift(&self) -> Option<u32> {
+        let block = HeadPage::PAGE_SZ as u64;
+
+        match self.page_size {
+            0 => Some(0),
+            size if size.is_power_of_two() && size >= block => {
+                let shift = (size / block).trailing_zeros();
+                (shift <= Self::MAX_PAGE_SHIFT).then_some(shift)
+            }
+            _ => None,
         }
     }
-}
+
+    /// The page size stored as `page_shift`, zero for the default pages of 4096 bytes.
+    fn page_size_for(page_shift: u32) -> u64 {
+        match page_shift {
+            0 => 0,
+            shift => (HeadPage::PAGE_SZ as u64) << shift,
+        }
+    }
+
+    /// The largest layout fitting into a file of `len` bytes.
+    ///
+    /// The layout has pages of 4096 bytes. The data ring gets the largest power-of-two number of pages that leaves room for at least
+    /// one page of the sequence ring, the sequence ring then holds as many entries as fit into the
+    /// remaining pages. The tail holds only those pages left over by rounding. If the file is too
+    /// small to hold any layout, the returned configuration is not initialized.
+    pub fn for_file(len: u64) -> ConfigureFile {
+        let page = HeadPage::PAGE_SZ as u64;
+        let pages = len.saturating_sub(page) / page;
+
+        if pages < 2 {
+            return ConfigureFile::default();
+        }
+
+        let data_pages = Head::fitting_power_of_two(pages - 1);
+        let sequence_pages = pages - data_pages;
+        let entries = Head::fitting_power_of_two(sequence_pages * SequencePage::DATA_COUNT as u64);
+
+        ConfigureFile {
+            entries,
+            data: data_pages * page,
+            layout_version: ConfigureFile::MAGIC_VERSION,
+            ..ConfigureFile::default()
+        }
+    }
+
+    /// The layout leaving the fewest unused pages in a file of `len` bytes.
+    ///
+    /// The layout has pages of 4096 bytes. In contrast to [`Self::for_file`], which maximizes the data ring, this tries every
+    /// power-of-two size of the data ring and gives the remaining pages to the sequence ring. The
+    /// layout with the smallest tail is chosen, among those the one with the larger data ring. Use
+    /// [`Self::tail_len`] for the residual tail. If the file is too small to hold any layout, the
+    /// returned configuration is not initialized.
+    pub fn fill(len: u64) -> ConfigureFile {
+        let page = HeadPage::PAGE_SZ as u64;
+        let pages = len.saturating_sub(page) / page;
+        let per_page = SequencePage::DATA_COUNT as u64;
+
+        let best = (0..u64::BITS)
+            .map(|shift| 1u64 << shift)
+            .take_while(|&data_pages| data_pages < pages)
+            .map(|data_pages| {
+                let entries = Head::fitting_power_of_two((pages - data_pages) * per_page);
+                let waste = pages - data_pages - entries.div_ceil(per_page);
+                (waste, data_pages, entries)
+            })
+            .min_by_key(|&(waste, data_pages, _)| (waste, core::cmp::Reverse(data_pages)));
+
+        let Some((_, data_pages, entries)) = best else {
+            return ConfigureFile::default();
+        };
+
+        ConfigureFile {
+            entries,
+            data: data_pages * page,
+            layout_version: ConfigureFile::MAGIC_VERSION,
+            ..ConfigureFile::default()
+        }
+    }
+
+    /// The number of bytes left for the tail in a file of `len` bytes with this layout.
+    ///
+    /// Returns `None` if the layout does not fit into the file.
+    pub fn tail_len(&self, len: u64) -> Option<u64> {
+        let page = HeadPage::PAGE_SZ as u64;
+        let pages = len.checked_sub(page)? / page;
+        let used = PageLayout::of(self)?.data.end as u64;
+        Some(pages.checked_sub(used)? * page)
+    }
+
+    /// The length of the largest entry that can be committed with this layout.
+    pub fn max_entry_len(&self) -> u64 {
+        self.data.saturating_sub(1)
+    }
+
+    /// Is the configuration data complete?
+    ///
+    /// This is the case for the current format as well as known prior formats, which are migrated
+    /// to the current one when configuring.
+    pub fn is_initialized(&self) -> bool {
+        Self::is_known_version(self.layout_version)
+    }
+
+    /// Complete this configuration, if it is not already.
+    pub fn or_insert_with(&mut self, replace: impl FnOnce(&mut Self)) {
+        if !self.is_initialized() {
+            replace(self);
+            self.layout_version = ConfigureFile::MAGIC_VERSION;
+        }
+    }
+}
+
+impl Head {
+    /// Write an entry consisting of all chunks in sequence.
+    ///
+    /// Chunks are byte slices or anything dereferencing to one, such as `IoSlice`.
+    pub(crate) fn write_with<T: core::ops::Deref<Target = [u8]>>(
+        &mut self,
+        chunks: &[T],
+        intermediate: &mut dyn FnMut(PreparedTransaction) -> bool,
+    ) -> Result<u64, CommitError> {
+        let len = chunks.iter().try_fold(0usize, |acc, data| acc.checked_add(data.len()));
+        let mut entry = self.head.begin_entry(len)?;
+
+        for data in chunks {
+            entry.copy_from_slice(data);
+        }
+
+        let aborted = core::cell::Cell::new(false);
+        let accepted = intermediate(PreparedTransaction {
+            offset: entry.offset,
+            length: entry.length,
+            tail: entry.head.tail,
+            head: entry.head,
+            aborted: &aborted,
+        });
+
+        let committed = if accepted && !aborted.get() {
+            Ok(entry.commit())
+        } else {
+            Err(CommitError::Rejected)
+        };
+
+        self.head.end_commit();
+        committed
+    }
+
+    /// Start an entry of `len` bytes, which the caller fills in place.
+    pub(crate) fn reserve(&mut self, len: usize) -> Result<Reservation<'_>, CommitError> {
+        let mut entry = self.head.begin_entry(Some(len))?;
+        entry.reserve(len as u64);
+        Ok(Reservation { entry: Some(entry) })
+    }
+
+    pub(crate) fn header(&self) -> HeaderView {
+        let meta = self.head.meta;
+        let version = meta.version.load(Ordering::Acquire);
+        let current = meta.current.load(Ordering::Acquire);
+        let layout = meta.layout(current);
+
+        HeaderView {
+            version,
+            current,
+            entry_mask: layout.entry_mask.load(Ordering::Relaxed),
+            page_mask: layout.page_mask.load(Ordering::Relaxed),
+            page_write_offset: layout.page_write_offset.load(Ordering::Relaxed),
+            flags: layout.flags.load(Ordering::Relaxed),
+            commits: meta.commits.load(Ordering::Relaxed),
+            epoch: meta.epoch.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Switch to an epoch, entries tagged with any other epoch are no longer valid.
+    pub(crate) fn set_epoch(&mut self, epoch: u64) -> Result<(), ConfigureError> {
+        if !self.head.cache.epochs {
+            return Err(ConfigureError::NoEpochs);
+        }
+
+        self.head.meta.epoch.store(epoch, Ordering::Release);
+        Ok(())
+    }
+
+    /// The commit marker of the file, see [`File::commit_marker`].
+    pub(crate) fn commit_marker(&self) -> u64 {
+        self.head.meta.commits.load(Ordering::Acquire)
+    }
+}
 
 impl WriteHead {
     pub(crate) fn pre_configure_entries(&mut self, num: u64) {
@@ -361,47 +1365,160 @@ impl WriteHead {
         self.cache.page_write_offset = offset;
     }
 
-    pub(crate) fn configure_pages(&mut self) {
+    pub(crate) fn pre_configure_contiguous(&mut self, contiguous: bool) {
+        self.cache.contiguous_entries = contiguous;
+    }
+
+    pub(crate) fn pre_configure_checksum(&mut self, checksum: bool) {
+        self.cache.checksum = checksum;
+    }
+
+    pub(crate) fn pre_configure_epochs(&mut self, epochs: bool) {
+        self.cache.epochs = epochs;
+    }
+
+    pub(crate) fn pre_configure_page_shift(&mut self, page_shift: u32) {
+        self.cache.page_shift = page_shift;
+    }
+
+    /// Lay out the pages and publish the layout, unless the file is too small to hold it.
+    pub(crate) fn configure_pages(&mut self) -> Result<(), ConfigureError> {
+        self.layout_pages()?;
+        self.store_meta();
+        Ok(())
+    }
+
+    /// Split the mapped pages into the sequence ring, the data ring, and the tail.
+    ///
+    /// Fails if the pages can not hold both rings, leaving the pages as they are.
+    fn layout_pages(&mut self) -> Result<(), ConfigureError> {
         assert_eq!(
             core::mem::size_of::<DataPage>(),
             core::mem::size_of::<SequencePage>()
         );
 
-        let sequence: usize = (self.cache.entry_mask + 1)
-            .try_into()
-            .expect("Invalid configured entry mask");
-        let sequence = sequence.next_power_of_two();
+        let page = core::mem::size_of::<DataPage>() as u64;
+        let layout = PageLayout::new(
+            self.cache.entry_mask + 1,
+            self.cache.page_mask + 1,
+            self.cache.checksum,
+            self.cache.epochs,
+            self.cache.page_shift,
+        );
 
-        let data: usize = (self.cache.page_mask + 1)
-            .try_into()
-            .expect("Invalid configured page mask");
-        let data = data.next_power_of_two();
+        // Both byte counts include the head page.
+        let len_of = |pages: usize| (pages as u64).saturating_add(1).saturating_mul(page);
+        let layout = match layout {
+            Some(layout) if layout.data.end <= self.data.len() => layout,
+            other => {
+                return Err(ConfigureError::TooSmall {
+                    needed: other.map_or(u64::MAX, |layout| len_of(layout.data.end)),
+                    have: len_of(self.data.len()),
+                })
+            }
+        };
 
-        let psequence = sequence / SequencePage::DATA_COUNT
-            + usize::from(sequence % SequencePage::DATA_COUNT != 0);
-        let pdata = data / core::mem::size_of::<DataPage>()
-            + usize::from(data % core::mem::size_of::<DataPage>() != 0);
+        self.sequence = &self.sequence[layout.sequence];
+        self.checksums = &self.data[layout.checksums];
+        self.epochs = &self.data[layout.epochs];
+        self.tail = &self.data[layout.data.end..];
+        self.data = &self.data[layout.data];
+        Ok(())
+    }
 
-        self.sequence = &self.sequence[..psequence];
-        let (data, tail) = self.data[psequence..].split_at(pdata);
-        self.data = data;
-        self.tail = tail;
+    /// Publish the cached layout to the header page.
+    ///
+    /// The layout is written to the slot not currently in use and then published by a single
+    /// store. If this is interrupted, the header still describes the previous layout.
+    fn store_meta(&mut self) {
+        let slot = self.stage_meta();
+        self.publish_meta(slot);
+    }
 
-        self.meta
-            .entry_mask
-            .store(self.cache.entry_mask, Ordering::Relaxed);
-        self.meta
-            .page_mask
-            .store(self.cache.page_mask, Ordering::Relaxed);
-        self.meta
+    /// Write the cached layout to the layout slot of the header page that is not in use.
+    ///
+    /// Returns the slot, which must be published for the layout to take effect.
+    pub(crate) fn stage_meta(&self) -> u64 {
+        let slot = (self.meta.current.load(Ordering::Relaxed) & 1) ^ 1;
+        let layout = self.meta.layout(slot);
+        layout.entry_mask.store(self.cache.entry_mask, Ordering::Relaxed);
+        layout.page_mask.store(self.cache.page_mask, Ordering::Relaxed);
+        layout
             .page_write_offset
             .store(self.cache.page_write_offset, Ordering::Relaxed);
+        let mut flags = 0;
+        if self.cache.contiguous_entries {
+            flags |= HeadPage::FLAG_CONTIGUOUS_ENTRIES;
+        }
+        if self.cache.checksum {
+            flags |= HeadPage::FLAG_CHECKSUM;
+        }
+        if self.cache.epochs {
+            flags |= HeadPage::FLAG_EPOCHS;
+        }
+        flags |= u64::from(self.cache.page_shift) << HeadPage::PAGE_SHIFT_BIT;
+        layout.flags.store(flags, Ordering::Relaxed);
+
+        slot
+    }
+
+    /// Switch the header page to a previously staged layout slot.
+    fn publish_meta(&mut self, slot: u64) {
+        self.meta.current.store(slot, Ordering::Release);
+        self.cache.layout = slot;
 
         self.meta
             .version
             .store(ConfigureFile::MAGIC_VERSION, Ordering::Release);
     }
 
+    /// Mark a commit as in flight, before any modification of the rings.
+    fn begin_commit(&self) {
+        let marker = self.meta.commits.load(Ordering::Relaxed);
+        // A marker left odd by an interrupted writer stays odd, instead of counting twice.
+        self.meta.commits.store(marker | 1, Ordering::Relaxed);
+        core::sync::atomic::fence(Ordering::Release);
+    }
+
+    /// Mark the commit as finished, after all modifications of the rings.
+    fn end_commit(&self) {
+        self.meta.end_commit();
+    }
+
+    /// Start an entry of `len` bytes, `None` if the length overflowed.
+    ///
+    /// Frees the space of the entry and marks the commit as in flight, end it with `end_commit`
+    /// whether or not the entry is committed.
+    pub(crate) fn begin_entry(&mut self, len: Option<usize>) -> Result<Entry<'_>, CommitError> {
+        let version = self.meta.version.load(Ordering::Relaxed);
+        if !ConfigureFile::is_known_version(version) || self.sequence.is_empty() {
+            return Err(CommitError::Uninitialized);
+        }
+
+        let too_large = |len: u64| CommitError::DataTooLarge {
+            len,
+            capacity: self.cache.page_mask,
+        };
+
+        let Some(len) = len else {
+            return Err(too_large(u64::MAX));
+        };
+
+        if self.new_write_offset(len).is_none() {
+            return Err(too_large(len as u64));
+        }
+
+        let mut entry = self.entry();
+        entry.skip_to_contiguous(len);
+        let end_ptr = entry
+            .new_write_offset(len)
+            .expect("Checked the length fits the data ring");
+
+        entry.head.begin_commit();
+        entry.invalidate_heads(end_ptr);
+        Ok(entry)
+    }
+
     pub(crate) fn entry(&mut self) -> Entry<'_> {
         let index = self.cache.entry_write_offset;
         let offset = self.cache.page_write_offset;
@@ -413,73 +1530,180 @@ impl WriteHead {
         }
     }
 
+    /// Visit all valid entries, invalidating those that are not inserted with `ordering`.
     pub(crate) fn iter_valid(&self, extend: &mut dyn Collect<Snapshot>, ordering: Ordering) {
+        let mut iter = self.snapshots();
+
+        while let Some((snapshot, seq)) = iter.next_entry() {
+            if !extend.insert_one(snapshot) {
+                seq.length.store(0, ordering);
+            }
+        }
+    }
+
+    pub(crate) fn snapshots(&self) -> SnapshotIter<'static> {
         // Always use the stored one. If we're iterating a pre-loaded file then this is the one
         // stored from the previous run, or zeroed if new. If we're iterating over our current
         // writer then we've previously written it, i.e. the ordering here is always good too, no
         // matter which one is used precisely.
-        let max = self.meta.entry_mask.load(ordering);
-        let seqs = self.sequence.iter().flat_map(|seq| &seq.data);
+        SnapshotIter {
+            sequence: self.sequence,
+            idx: 0,
+            max: self.meta.current().entry_mask.load(Ordering::Acquire),
+            capacity: self.cache.page_mask.wrapping_add(1),
+            epoch: self.meta.epoch.load(Ordering::Acquire),
+            epochs: self.entry_epochs(),
+            checksums: self.entry_checksums(),
+        }
+    }
 
-        for (idx, seq) in seqs.enumerate() {
-            if idx as u64 > max {
-                break;
-            }
+    fn entry_checksums(&self) -> Option<EntryChecksums<'static>> {
+        self.cache.checksum.then(|| EntryChecksums {
+            sums: DataPage::as_slice_of_u64(self.checksums),
+            data: self.data,
+            page_mask: self.cache.page_mask,
+        })
+    }
 
-            let length = seq.length.load(ordering);
+    /// The stored checksum of an entry, if checksums are configured.
+    pub(crate) fn checksum_at(&self, idx: u64) -> Option<u64> {
+        let sums = self.entry_checksums()?;
+        sums.stored(idx & self.cache.entry_mask)
+    }
 
-            if length == 0 {
-                continue;
-            }
+    fn store_checksum(&self, idx: u64, checksum: u64) {
+        let sums = DataPage::as_slice_of_u64(self.checksums);
+        sums[(idx & self.cache.entry_mask) as usize].store(checksum.to_le(), Ordering::Relaxed);
+    }
 
-            if !extend.insert_one(Snapshot {
-                length,
-                offset: seq.offset.load(ordering),
-            }) {
-                seq.length.store(0, ordering);
-            }
-        }
+    fn entry_epochs(&self) -> Option<&'static [AtomicU64]> {
+        self.cache.epochs.then(|| DataPage::as_slice_of_u64(self.epochs))
+    }
+
+    /// The stored epoch of an entry, if epochs are configured.
+    fn epoch_at(&self, idx: u64) -> Option<u64> {
+        let epochs = self.entry_epochs()?;
+        let tag = &epochs[(idx & self.cache.entry_mask) as usize];
+        Some(u64::from_le(tag.load(Ordering::Relaxed)))
+    }
+
+    fn store_epoch(&self, idx: u64, epoch: u64) {
+        let epochs = DataPage::as_slice_of_u64(self.epochs);
+        epochs[(idx & self.cache.entry_mask) as usize].store(epoch.to_le(), Ordering::Relaxed);
+    }
+
+    /// Check that an entry is tagged with the current epoch, always true without epochs.
+    fn is_current_epoch(&self, idx: u64) -> bool {
+        self.epoch_at(idx)
+            .is_none_or(|tag| tag == self.meta.epoch.load(Ordering::Acquire))
     }
 
     pub(crate) fn new_write_offset(&self, n: usize) -> Option<u64> {
         let len = u64::try_from(n);
-        if let Some(len) = len.ok().filter(|&l| l <= self.cache.page_mask) {
-            Some(self.cache.page_write_offset.wrapping_add(len))
-        } else {
-            None
-        }
+        len.ok()
+            .filter(|&l| l <= self.cache.page_mask)
+            .map(|len| self.cache.page_write_offset.wrapping_add(len))
     }
 
-    /// Invalidate all heads so that `n` bytes can be written.
+    /// Invalidate all heads so that data up to the stream offset `end` can be written.
+    ///
+    /// This frees up the entries whose data would be overwritten as well as the oldest entry if
+    /// the sequence ring has no more room for the next entry.
     pub(crate) fn invalidate_heads_to(&mut self, end: u64) {
+        let capacity = self.cache.page_mask.wrapping_add(1);
         let mut entry = self.cache.entry_read_offset;
-        let mut data = self.cache.page_read_offset;
 
-        loop {
-            if data >= end {
-                break;
-            }
+        while entry != self.cache.entry_write_offset {
+            let snapshot = self.entry_at_relaxed(entry);
+            let ring_full = self.cache.entry_write_offset.wrapping_sub(entry) > self.cache.entry_mask;
+            let overwritten = end.wrapping_sub(snapshot.offset) > capacity;
 
-            // The entry write offset is ahead of the entry read offset.
-            if entry == self.cache.entry_write_offset {
-                data = end;
+            if snapshot.length != 0 && !ring_full && !overwritten {
                 break;
             }
 
-            let length = self.invalidate_at(entry);
+            self.invalidate_at(entry);
             entry = entry.wrapping_add(1);
-            data = data.wrapping_add(length);
         }
 
+        // Order the invalidations before the data written next, see `SequenceEntry`.
+        core::sync::atomic::fence(Ordering::Release);
         self.cache.entry_read_offset = entry;
-        self.cache.page_read_offset = data;
+    }
+
+    /// The number of bytes of the data ring from the oldest valid entry to the write offset.
+    pub(crate) fn data_used(&self) -> u64 {
+        let read = self.cache.entry_read_offset;
+        let count = self.cache.entry_write_offset.wrapping_sub(read);
+
+        let oldest = (0..count)
+            .map(|n| self.entry_at_relaxed(read.wrapping_add(n)))
+            .find(|snapshot| snapshot.length != 0);
+
+        oldest.map_or(0, |snapshot| {
+            let used = self.cache.page_write_offset.wrapping_sub(snapshot.offset);
+            used.min(self.cache.page_mask.wrapping_add(1))
+        })
+    }
+
+    /// Recover the position of the entry cursors from the entries stored in the sequence ring.
+    ///
+    /// The newest valid entry is the one closest to the write offset of the data stream, the next
+    /// entry is written right after it. All other slots are considered to hold older entries.
+    pub(crate) fn restore_cursors(&mut self) {
+        let count = self.cache.entry_mask.wrapping_add(1);
+        let newest = self.newest_entry();
+
+        let next = newest.map_or(0, |snapshot| snapshot.entry + 1);
+        self.cache.entry_write_offset = next;
+        self.cache.entry_read_offset = if newest.is_some() { next.wrapping_sub(count) } else { next };
+    }
+
+    /// The stored entry closest to the write offset of the data stream.
+    ///
+    /// This considers every slot holding a length, regardless of its epoch or checksum.
+    fn newest_entry(&self) -> Option<Snapshot> {
+        let write_offset = self.cache.page_write_offset;
+        let count = self.cache.entry_mask.wrapping_add(1);
+        let mut newest: Option<(u64, Snapshot)> = None;
+
+        for slot in 0..count {
+            let snapshot = self.entry_at_relaxed(slot);
+
+            if snapshot.length == 0 {
+                continue;
+            }
+
+            let age = write_offset.wrapping_sub(snapshot.offset);
+            if newest.is_none_or(|(newest_age, _)| age < newest_age) {
+                newest = Some((age, snapshot));
+            }
+        }
+
+        newest.map(|(_, snapshot)| snapshot)
     }
 
     pub(crate) fn copy_from_slice(&mut self, data: &[u8]) -> u64 {
         let mut n = self.cache.page_write_offset;
 
-        for (&b, idx) in data.iter().zip(n..) {
-            self.write_at(idx, b);
+        // Bytes up to the next word boundary.
+        let head = (n.wrapping_neg() % 8).min(data.len() as u64) as usize;
+        let (unaligned, data) = data.split_at(head);
+
+        for &b in unaligned {
+            self.write_at(n, b);
+            n = n.wrapping_add(1);
+        }
+
+        // The ring is a multiple of a page long, aligned words never wrap in the middle.
+        let mut words = data.chunks_exact(8);
+        for word in &mut words {
+            self.write_word_at(n, u64::from_le_bytes(word.try_into().unwrap()));
+            n = n.wrapping_add(8);
+        }
+
+        for &b in words.remainder() {
+            self.write_at(n, b);
             n = n.wrapping_add(1);
         }
 
@@ -488,11 +1712,68 @@ impl WriteHead {
         count
     }
 
-    pub(crate) fn read(&self, snapshot: &Snapshot, into: &mut [u8]) {
-        for (b, offset) in into.iter_mut().zip(0..snapshot.length) {
-            let idx = snapshot.offset.wrapping_add(offset);
-            *b = self.read_at(idx);
+    /// Read the data of a snapshot, returns whether it matches its checksum.
+    ///
+    /// A snapshot without an entry, or a file without checksums, always matches.
+    pub(crate) fn read(&self, snapshot: &Snapshot, into: &mut [u8]) -> bool {
+        // The snapshot was loaded before, order the data loads after it. See `SequenceEntry`.
+        core::sync::atomic::fence(Ordering::Acquire);
+
+        let len = into.len().min(snapshot.length.try_into().unwrap_or(usize::MAX));
+        let into_data = &mut into[..len];
+        let mut n = snapshot.offset;
+
+        // Bytes up to the next word boundary.
+        let head = (n.wrapping_neg() % 8).min(len as u64) as usize;
+        let (unaligned, rest) = into_data.split_at_mut(head);
+
+        for b in unaligned {
+            *b = self.read_at(n);
+            n = n.wrapping_add(1);
+        }
+
+        // As in `copy_from_slice`, aligned words never wrap in the middle.
+        let mut words = rest.chunks_exact_mut(8);
+        for word in &mut words {
+            word.copy_from_slice(&self.read_word_at(n).to_le_bytes());
+            n = n.wrapping_add(8);
+        }
+
+        for b in words.into_remainder() {
+            *b = self.read_at(n);
+            n = n.wrapping_add(1);
+        }
+
+        match self.entry_checksums() {
+            Some(sums) if snapshot.entry != Snapshot::NO_ENTRY => {
+                sums.stored(snapshot.entry) == Some(u64::from(crc32c(into.iter().copied())))
+            }
+            _ => true,
+        }
+    }
+
+    /// Read the data of a snapshot, then confirm its entry still holds it.
+    ///
+    /// The entry is loaded again after the data was copied. A writer invalidates the entry before
+    /// it overwrites the data, so an unchanged entry proves the copy was not torn.
+    pub(crate) fn read_consistent(&self, snapshot: &Snapshot, into: &mut [u8]) -> Result<(), ReadError> {
+        if snapshot.length == 0 || snapshot.entry > self.cache.entry_mask {
+            return Err(ReadError::Stale);
+        }
+
+        let intact = self.read(snapshot, into);
+        // Order the data loads before, see `SequenceEntry`.
+        core::sync::atomic::fence(Ordering::Acquire);
+
+        if self.entry_at_relaxed(snapshot.entry) != *snapshot {
+            return Err(ReadError::Stale);
         }
+
+        if !intact {
+            return Err(ReadError::ChecksumMismatch);
+        }
+
+        Ok(())
     }
 
     fn get_entry_atomic(&self, idx: u64) -> &SequenceEntry {
@@ -522,38 +1803,41 @@ impl WriteHead {
         Snapshot {
             offset: entry.offset.load(Ordering::Relaxed),
             length: entry.length.load(Ordering::Relaxed),
+            entry: idx & self.cache.entry_mask,
         }
     }
 
-    fn idx_at(&self, idx: u64) -> (usize, usize, u32) {
-        let idx = idx & self.cache.page_mask;
-
-        let offset = idx % 8;
-        let idx = idx / 8;
-        let shift = 8 * offset;
-
-        let data_idx = idx as usize % DataPage::DATA_COUNT;
-        let page_idx = idx as usize / DataPage::DATA_COUNT;
-        (page_idx, data_idx, shift as u32)
-    }
-
-    fn write_at(&self, idx: u64, byte: u8) {
-        let (page_idx, data_idx, shift) = self.idx_at(idx);
+    /// Write a single byte, leaving the other bytes of its word as they are.
+    ///
+    /// The word is updated with a single atomic exchange, so a concurrent write to another byte
+    /// of the same word is never lost.
+    pub(crate) fn write_at(&self, idx: u64, byte: u8) {
+        let (page_idx, data_idx, shift) = DataPage::idx_at(self.cache.page_mask, idx);
         let word = &self.data[page_idx].data[data_idx];
         let mask = 0xffu64 << shift;
+        let bits = u64::from(byte) << shift;
 
-        let old = word.load(Ordering::Relaxed) & !mask;
-        let new = old | (u64::from(byte) << shift);
-        word.store(new, Ordering::Relaxed);
+        let _ = word.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| {
+            Some(((u64::from_le(old) & !mask) | bits).to_le())
+        });
     }
 
-    fn read_at(&self, idx: u64) -> u8 {
-        let (page_idx, data_idx, shift) = self.idx_at(idx);
+    /// Write a whole word, `idx` must be a multiple of 8.
+    fn write_word_at(&self, idx: u64, word: u64) {
+        debug_assert_eq!(idx % 8, 0);
+        let (page_idx, data_idx, _) = DataPage::idx_at(self.cache.page_mask, idx);
+        self.data[page_idx].data[data_idx].store(word.to_le(), Ordering::Relaxed);
+    }
 
-        let word = &self.data[page_idx].data[data_idx];
-        let old = word.load(Ordering::Relaxed);
+    /// Read a whole word, `idx` must be a multiple of 8.
+    pub(crate) fn read_word_at(&self, idx: u64) -> u64 {
+        debug_assert_eq!(idx % 8, 0);
+        let (page_idx, data_idx, _) = DataPage::idx_at(self.cache.page_mask, idx);
+        u64::from_le(self.data[page_idx].data[data_idx].load(Ordering::Relaxed))
+    }
 
-        ((old >> shift) & 0xff) as u8
+    fn read_at(&self, idx: u64) -> u8 {
+        DataPage::byte_at(self.data, self.cache.page_mask, idx)
     }
 }
 
@@ -561,24 +1845,40 @@ impl Entry<'_> {
     /// Consume the entry, putting it into the sequence buffer.
     pub(crate) fn commit(self) -> u64 {
         let end = self.head.cache.page_write_offset;
-        self.head
-            .meta
-            .page_write_offset
-            .store(end, Ordering::Relaxed);
 
         debug_assert!(
             end.wrapping_sub(self.offset) >= self.length,
             "Failed to reserve enough space in the data section for the entry, risking corrupted data with following writes"
         );
 
+        if self.head.cache.checksum {
+            let data = (0..self.length).map(|n| self.head.read_at(self.offset.wrapping_add(n)));
+            self.head.store_checksum(self.index, u64::from(crc32c(data)));
+        }
+
+        if self.head.cache.epochs {
+            let epoch = self.head.meta.epoch.load(Ordering::Relaxed);
+            self.head.store_epoch(self.index, epoch);
+        }
+
         self.head.insert_at(
             self.index,
             Snapshot {
                 length: self.length,
                 offset: self.offset,
+                entry: self.index & self.head.cache.entry_mask,
             },
         );
 
+        // After the entry, such that the newest entry ends at the stored write offset unless the
+        // commit was interrupted. See `FileDiscovery::last_entry_suspect`.
+        self.head
+            .meta
+            .layout(self.head.cache.layout)
+            .page_write_offset
+            .store(end, Ordering::Relaxed);
+
+        self.head.cache.entry_write_offset = self.index.wrapping_add(1);
         self.index
     }
 
@@ -586,6 +1886,31 @@ impl Entry<'_> {
         self.head.new_write_offset(n)
     }
 
+    /// Move the entry to the start of the data ring, if `n` bytes would straddle its end.
+    ///
+    /// Only applies when configured for contiguous entries. The skipped bytes are accounted for in
+    /// the write offset, i.e. they count as written when invalidating older entries.
+    pub(crate) fn skip_to_contiguous(&mut self, n: usize) {
+        let cache = &mut self.head.cache;
+
+        if !cache.contiguous_entries {
+            return;
+        }
+
+        let Some(len) = u64::try_from(n).ok().filter(|&l| l <= cache.page_mask) else {
+            return;
+        };
+
+        let start = cache.page_write_offset & cache.page_mask;
+        if start.checked_add(len).is_some_and(|end| end <= cache.page_mask + 1) {
+            return;
+        }
+
+        let padding = cache.page_mask + 1 - start;
+        cache.page_write_offset = cache.page_write_offset.wrapping_add(padding);
+        self.offset = cache.page_write_offset;
+    }
+
     pub(crate) fn invalidate_heads(&mut self, end: u64) {
         self.head.invalidate_heads_to(end);
     }
@@ -593,6 +1918,13 @@ impl Entry<'_> {
     pub(crate) fn copy_from_slice(&mut self, data: &[u8]) {
         self.length += self.head.copy_from_slice(data);
     }
+
+    /// Account for `len` bytes of data without writing them.
+    pub(crate) fn reserve(&mut self, len: u64) {
+        let cache = &mut self.head.cache;
+        cache.page_write_offset = cache.page_write_offset.wrapping_add(len);
+        self.length += len;
+    }
 }
 
 impl<'lt> PreparedTransaction<'lt> {
@@ -614,6 +1946,117 @@ impl<'lt> PreparedTransaction<'lt> {
     pub fn tail(&self) -> &'lt [AtomicU64] {
         DataPage::as_slice_of_u64(self.tail)
     }
+
+    /// Read back the start of the staged data, including modifications by [`Self::replace`].
+    ///
+    /// # Panics
+    ///
+    /// If `buf` is longer than the data of the entry.
+    pub fn current(&self, buf: &mut [u8]) {
+        assert!(
+            buf.len() as u64 <= self.length,
+            "{} > {}",
+            buf.len(),
+            self.length
+        );
+
+        self.head.read(&Snapshot::new(self.offset, buf.len() as u64), buf);
+    }
+
+    /// Discard the entry instead of committing it.
+    ///
+    /// The commit fails with [`CommitError::Rejected`], even if the function passed to
+    /// [`Writer::commit_with`] returns a value afterwards. Modifications of the tail are kept.
+    pub fn abort(self) {
+        self.aborted.set(true);
+    }
+}
+
+impl Reservation<'_> {
+    fn entry(&self) -> &Entry<'_> {
+        self.entry.as_ref().expect("Only taken when committing")
+    }
+
+    /// The number of reserved bytes.
+    pub fn len(&self) -> usize {
+        self.entry().length as usize
+    }
+
+    /// Whether the reservation is for an empty entry.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The offset into the entry of the first byte held by [`Self::words`].
+    ///
+    /// This is less than eight, unless the entry holds no whole word at all.
+    pub fn word_offset(&self) -> usize {
+        let entry = self.entry();
+        let first = entry.offset.wrapping_add(7) & !7;
+        (first.wrapping_sub(entry.offset) as usize).min(self.len())
+    }
+
+    /// The whole words of the data ring within the reservation, in order.
+    ///
+    /// The second slice is only non-empty if the reservation wraps around the end of the data
+    /// ring. Each word holds eight consecutive bytes of the entry, the first starts at
+    /// [`Self::word_offset`]. Words are little-endian independent of the host, i.e. store a word
+    /// with `u64::from_le_bytes(bytes).to_le()`. Words are written with `Relaxed` ordering, the
+    /// commit publishes them.
+    pub fn words(&self) -> (&[AtomicU64], &[AtomicU64]) {
+        let entry = self.entry();
+        let cache = &entry.head.cache;
+
+        let first = entry.offset.wrapping_add(self.word_offset() as u64);
+        let count = (self.len() - self.word_offset()) / 8;
+        let ring_words = ((cache.page_mask + 1) / 8) as usize;
+
+        let ring = &DataPage::as_slice_of_u64(entry.head.data)[..ring_words];
+        if count == 0 {
+            return (&[], &[]);
+        }
+
+        let start = ((first & cache.page_mask) / 8) as usize;
+        let until_end = count.min(ring_words - start);
+        (&ring[start..start + until_end], &ring[..count - until_end])
+    }
+
+    /// Write bytes into the entry, starting at `at` bytes into it.
+    ///
+    /// # Panics
+    ///
+    /// If the data does not fit into the reservation at that offset.
+    pub fn write(&mut self, at: usize, data: &[u8]) {
+        let end = at.checked_add(data.len());
+        assert!(
+            end.is_some_and(|end| end <= self.len()),
+            "{at} + {} > {}",
+            data.len(),
+            self.len()
+        );
+
+        let entry = self.entry();
+        for (&b, n) in data.iter().zip(at as u64..) {
+            entry.head.write_at(entry.offset.wrapping_add(n), b);
+        }
+    }
+
+    /// Commit the entry, after all its bytes have been written.
+    pub fn commit(mut self) -> super::SnapshotIndex {
+        let entry = self.entry.take().expect("Only taken when committing");
+        let meta = entry.head.meta;
+        let index = entry.commit();
+        meta.end_commit();
+        super::SnapshotIndex { entry: index }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            entry.head.end_commit();
+        }
+    }
 }
 
 pub(crate) struct HeadCache {
@@ -622,7 +2065,13 @@ pub(crate) struct HeadCache {
     entry_write_offset: u64,
     page_mask: u64,
     page_write_offset: u64,
-    page_read_offset: u64,
+    contiguous_entries: bool,
+    checksum: bool,
+    epochs: bool,
+    /// The power of two by which pages exceed 4096 bytes.
+    page_shift: u32,
+    /// The layout slot of the header page that this head published.
+    layout: u64,
 }
 
 impl HeadCache {
@@ -633,54 +2082,294 @@ impl HeadCache {
             entry_write_offset: 0,
             page_mask: 0,
             page_write_offset: 0,
-            page_read_offset: 0,
+            contiguous_entries: false,
+            checksum: false,
+            epochs: false,
+            page_shift: 0,
+            layout: 0,
         }
     }
 }
 
 #[derive(Default)]
+#[repr(C)]
 pub(crate) struct HeadPage {
     /// Magic 8-byte sequence, denoting the layout of this file and identifying it as shm-snapshot.
-    version: AtomicU64,
+    version: LeU64,
+    /// The layout in use while `current` is `0`.
+    layout: HeadLayout,
+    /// Selects the layout slot in use, `0` for `layout` and `1` for `shadow`.
+    ///
+    /// A new layout is written to the slot not in use and published by switching this value, so
+    /// that an interrupted configuration leaves the previous layout intact.
+    current: LeU64,
+    /// The layout in use while `current` is `1`.
+    shadow: HeadLayout,
+    /// Counts the starts and ends of commits, odd while a commit is in flight.
+    ///
+    /// A writer makes this odd before it modifies the rings for a new entry, and even again once
+    /// the entry is committed or abandoned. Files written by prior versions leave this zero.
+    commits: LeU64,
+    /// The application-defined epoch, see [`Writer::set_epoch`].
+    epoch: LeU64,
+}
+
+#[derive(Default)]
+#[repr(C)]
+pub(crate) struct HeadLayout {
     /// The mask to translate stream index to a specific descriptor offset.
-    entry_mask: AtomicU64,
+    entry_mask: LeU64,
     /// The mask to translate stream offset to a data page offset.
-    page_mask: AtomicU64,
+    page_mask: LeU64,
     /// The stream offset of the next byte to write.
-    page_write_offset: AtomicU64,
+    page_write_offset: LeU64,
+    /// Layout options, a bit set of the `FLAG_*` constants.
+    flags: LeU64,
 }
 
 impl HeadPage {
     const PAGE_SZ: usize = 4096;
+    const FLAG_CONTIGUOUS_ENTRIES: u64 = 1;
+    const FLAG_CHECKSUM: u64 = 2;
+    const FLAG_EPOCHS: u64 = 4;
+    /// The lowest bit of the page shift in the layout flags, see `HeaderView::flags`.
+    const PAGE_SHIFT_BIT: u32 = 8;
+
+    fn layout(&self, slot: u64) -> &HeadLayout {
+        if slot & 1 == 0 {
+            &self.layout
+        } else {
+            &self.shadow
+        }
+    }
+
+    fn current(&self) -> &HeadLayout {
+        self.layout(self.current.load(Ordering::Acquire))
+    }
+
+    /// Mark the commit as finished, see `WriteHead::end_commit`.
+    fn end_commit(&self) {
+        let marker = self.commits.load(Ordering::Relaxed);
+        self.commits
+            .store((marker | 1).wrapping_add(1), Ordering::Release);
+    }
+}
+
+impl HeadLayout {
+    const fn new() -> Self {
+        HeadLayout {
+            entry_mask: LeU64::new(0),
+            page_mask: LeU64::new(0),
+            page_write_offset: LeU64::new(0),
+            flags: LeU64::new(0),
+        }
+    }
 }
 
 pub(crate) struct SequencePage {
     data: [SequenceEntry; Self::DATA_COUNT],
 }
 
+/// A descriptor of the sequence ring.
+///
+/// A writer stores the data of an entry and its checksum with `Relaxed` ordering, then publishes
+/// the entry by storing `offset` and finally `length` with `Release` ordering. A reader loads
+/// `length` with `Acquire` ordering, or follows its loads with an `Acquire` fence, before it loads
+/// `offset` and the data. A non-zero length then guarantees the data of that commit is visible.
+///
+/// Before data is overwritten the writer stores a zero length. This is not ordered before the
+/// new data for a concurrent reader, which must load the descriptor again after copying the data
+/// to confirm the copy, see `FileDiscovery::is_current`.
+///
+/// Both fields are little-endian, see `LeU64`.
 struct SequenceEntry {
-    offset: AtomicU64,
-    length: AtomicU64,
+    offset: LeU64,
+    length: LeU64,
 }
 
 impl Default for SequencePage {
     fn default() -> Self {
         SequencePage {
             data: [0; Self::DATA_COUNT].map(|_i| SequenceEntry {
-                offset: AtomicU64::new(0),
-                length: AtomicU64::new(0),
+                offset: LeU64::new(0),
+                length: LeU64::new(0),
             }),
         }
     }
 }
 
+impl Reader<'_> {
+    fn new(head: WriteHead, snapshot: Snapshot) -> Self {
+        Reader {
+            head,
+            snapshot,
+            position: 0,
+            crc: 0,
+            file: core::marker::PhantomData,
+        }
+    }
+
+    /// Copy the next bytes of the snapshot, returning their number.
+    pub(crate) fn read_next(&mut self, into: &mut [u8]) -> usize {
+        let remaining = self.snapshot.length - self.position;
+        let len = into.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let into = &mut into[..len];
+
+        // A part of the snapshot without an entry, whose checksum is confirmed at the end.
+        let part = Snapshot::new(self.snapshot.offset.wrapping_add(self.position), len as u64);
+        self.head.read(&part, into);
+
+        self.crc = crate::crc::crc32c_extend(self.crc, into.iter().copied());
+        self.position += len as u64;
+        len
+    }
+
+    /// Check all bytes read against the checksum of the entry, after the end of the snapshot.
+    ///
+    /// A snapshot without an entry, or a file without checksums, always matches.
+    pub(crate) fn matches(&self) -> bool {
+        if self.snapshot.entry == Snapshot::NO_ENTRY {
+            return true;
+        }
+
+        match self.head.checksum_at(self.snapshot.entry) {
+            Some(sum) => sum == u64::from(self.crc),
+            None => true,
+        }
+    }
+}
+
+impl<'lt> SnapshotIter<'lt> {
+    fn next_entry(&mut self) -> Option<(Snapshot, &'lt SequenceEntry)> {
+        while self.idx <= self.max {
+            let idx = self.idx;
+            let page = usize::try_from(idx).ok()? / SequencePage::DATA_COUNT;
+            let seq = &self.sequence.get(page)?.data[idx as usize % SequencePage::DATA_COUNT];
+            self.idx += 1;
+
+            // Pairs with the release in `insert_at`, the data of the commit is visible after this.
+            let length = seq.length.load(Ordering::Acquire);
+
+            // A corrupt or torn length, no commit holds more data than the ring.
+            if length == 0 || length > self.capacity {
+                continue;
+            }
+
+            let offset = seq.offset.load(Ordering::Relaxed);
+            // Entries committed in another epoch than the current one.
+            let tag = self
+                .epochs
+                .and_then(|epochs| epochs.get(usize::try_from(idx).ok()?))
+                .map(|tag| u64::from_le(tag.load(Ordering::Relaxed)));
+            if tag.is_some_and(|tag| tag != self.epoch) {
+                continue;
+            }
+
+            let snapshot = Snapshot {
+                length,
+                offset,
+                entry: idx,
+            };
+
+            // Torn data, for instance in a copy of the file racing a commit.
+            if self.checksums.is_some_and(|sums| !sums.matches(&snapshot)) {
+                continue;
+            }
+
+            return Some((snapshot, seq));
+        }
+
+        None
+    }
+}
+
+impl Iterator for SnapshotIter<'_> {
+    type Item = Snapshot;
+
+    fn next(&mut self) -> Option<Snapshot> {
+        self.next_entry().map(|(snapshot, _)| snapshot)
+    }
+}
+
+impl EntryChecksums<'_> {
+    fn stored(&self, entry: u64) -> Option<u64> {
+        let sum = self.sums.get(usize::try_from(entry).ok()?)?;
+        Some(u64::from_le(sum.load(Ordering::Relaxed)))
+    }
+
+    /// Check the data of a snapshot against the checksum of its entry.
+    fn matches(&self, snapshot: &Snapshot) -> bool {
+        let data = (0..snapshot.length).map(|n| {
+            DataPage::byte_at(self.data, self.page_mask, snapshot.offset.wrapping_add(n))
+        });
+
+        self.stored(snapshot.entry) == Some(u64::from(crc32c(data)))
+    }
+}
+
 impl SequencePage {
     // FIXME: I currently don't target 32-bit atomic targets. But if then this should depend on
     // such a target choice. The code written should then also get another implementation, and
     // `Writer` only access this by indirection.
     const DATA_COUNT: usize = 4096 / 16;
+
+    /// The number of pages holding a sequence ring of `entries`.
+    fn pages_for(entries: u64) -> Option<usize> {
+        let entries = usize::try_from(entries).ok()?;
+        Some(entries.div_ceil(Self::DATA_COUNT))
+    }
+
+    /// The number of pages holding a sequence ring of `entries` and, if enabled, its checksums
+    /// and epochs.
+    fn ring_pages_for(entries: u64, checksum: bool, epochs: bool) -> Option<usize> {
+        let per_entry = usize::try_from(entries).ok()?.div_ceil(DataPage::DATA_COUNT);
+        let checksums = if checksum { per_entry } else { 0 };
+        let epochs = if epochs { per_entry } else { 0 };
+
+        Self::pages_for(entries)?
+            .checked_add(checksums)?
+            .checked_add(epochs)
+    }
 }
 
+impl PageLayout {
+    /// The placement of the rings of `entries` and `data` bytes, `None` if it overflows.
+    fn new(entries: u64, data: u64, checksum: bool, epochs: bool, page_shift: u32) -> Option<Self> {
+        if page_shift > ConfigureFile::MAX_PAGE_SHIFT {
+            return None;
+        }
+
+        let block = core::mem::size_of::<DataPage>() as u64;
+        let per_page = 1usize << page_shift;
+
+        // The remainder of the head page precedes the sequence ring.
+        let start = per_page - 1;
+        let psequence = SequencePage::pages_for(entries)?;
+        let pchecksum = SequencePage::ring_pages_for(entries, checksum, false)?;
+        let pring = SequencePage::ring_pages_for(entries, checksum, epochs)?;
+        let pdata = usize::try_from(data.div_ceil(block)).ok()?;
+
+        let data_start = start.checked_add(pring.checked_next_multiple_of(per_page)?)?;
+        let data_end = data_start.checked_add(pdata.checked_next_multiple_of(per_page)?)?;
+
+        Some(PageLayout {
+            sequence: start..start + psequence,
+            checksums: start + psequence..start + pchecksum,
+            epochs: start + pchecksum..start + pring,
+            data: data_start..data_end,
+        })
+    }
+
+    fn of(cfg: &ConfigureFile) -> Option<Self> {
+        Self::new(cfg.entries, cfg.data, cfg.checksum, cfg.epochs, cfg.page_shift()?)
+    }
+}
+
+/// A page of the data ring.
+///
+/// Byte `idx` of the ring is stored in the bits `8 * (idx % 8)..` of its word, and words are
+/// little-endian independent of the host. The bytes of the ring are thus in order in the file.
+/// Words are converted with `to_le` and `from_le` on each access.
 pub struct DataPage {
     pub data: [AtomicU64; Self::DATA_COUNT],
 }
@@ -689,6 +2378,28 @@ impl DataPage {
     // One AtomicU64 per entry dividing the page.
     const DATA_COUNT: usize = 4096 / 8;
 
+    /// The page, word and bit shift of the byte at stream offset `idx` in a ring of `mask + 1`.
+    fn idx_at(mask: u64, idx: u64) -> (usize, usize, u32) {
+        let idx = idx & mask;
+
+        let offset = idx % 8;
+        let idx = idx / 8;
+        let shift = 8 * offset;
+
+        let data_idx = idx as usize % Self::DATA_COUNT;
+        let page_idx = idx as usize / Self::DATA_COUNT;
+        (page_idx, data_idx, shift as u32)
+    }
+
+    fn byte_at(this: &[DataPage], mask: u64, idx: u64) -> u8 {
+        let (page_idx, data_idx, shift) = Self::idx_at(mask, idx);
+
+        let word = &this[page_idx].data[data_idx];
+        let old = u64::from_le(word.load(Ordering::Relaxed));
+
+        ((old >> shift) & 0xff) as u8
+    }
+
     pub fn as_slice_of_u64(this: &[DataPage]) -> &[AtomicU64] {
         let count = Self::DATA_COUNT * this.len();
         unsafe { &*core::ptr::slice_from_raw_parts(this.as_ptr() as *const AtomicU64, count) }
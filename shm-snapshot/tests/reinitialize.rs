@@ -1,5 +1,5 @@
 #![cfg(target_family = "unix")]
-use shm_snapshot::{ConfigureFile, File};
+use shm_snapshot::{CommitError, ConfigureError, ConfigureFile, File, GrowError, Mapping, ReadError, RecoverError, Snapshot};
 use memfile::CreateOptions;
 
 #[test]
@@ -9,34 +9,31 @@ fn after_no_writes() {
     file.set_len(0x1_0000_0000).unwrap();
     let _restore_from = file.try_clone().unwrap();
 
-    let mut file = File::new(file).unwrap();
+    let file = File::new(file).unwrap();
     let mut cfg = ConfigureFile::default();
 
-    file.discover(&mut cfg);
+    file.recover(&mut cfg);
     cfg.or_insert_with(|cfg| {
         cfg.entries = 0x80;
         cfg.data = 0x100;
     });
 
-    let mut writer = file.configure(&cfg);
+    let mut writer = file.configure(&cfg).unwrap();
     const GREETING: &[u8] = b"Hello, world";
-    writer.write(GREETING).unwrap();
+    writer.commit(GREETING).unwrap();
 
     drop(writer);
 
     let file = _restore_from;
-    let mut file = File::new(file).unwrap();
+    let file = File::new(file).unwrap();
     let mut cfg = ConfigureFile::default();
-    file.discover(&mut cfg);
-    cfg.or_insert_with(|cfg| {
-        panic!("Failed to restore configuration {cfg:?}");
-    });
-
     let mut valid_priors = vec![];
-    file.valid(&mut valid_priors);
+    file.recover(&mut cfg)
+        .expect("Failed to restore configuration")
+        .valid(&mut valid_priors);
     assert_eq!(valid_priors.len(), 1, "{:?}", &valid_priors);
 
-    let _writer = file.configure(&cfg);
+    let _writer = file.configure(&cfg).unwrap();
 }
 
 
@@ -47,49 +44,2183 @@ fn commit_not() {
     file.set_len(0x1_0000_0000).unwrap();
     let _restore_from = file.try_clone().unwrap();
 
-    let mut file = File::new(file).unwrap();
+    let file = File::new(file).unwrap();
     let mut cfg = ConfigureFile::default();
 
-    file.discover(&mut cfg);
+    file.recover(&mut cfg);
     cfg.or_insert_with(|cfg| {
         cfg.entries = 0x80;
         cfg.data = 0x100;
     });
 
-    let mut writer = file.configure(&cfg);
+    let mut writer = file.configure(&cfg).unwrap();
     const GREETING: &[u8] = b"Hello, world";
-    writer.write_with(GREETING, |tx| {
-        assert!(tx.tail().len() > 0);
-        false
+    writer.commit_with(GREETING, |tx| {
+        assert!(!tx.tail().is_empty());
+        None::<()>
     }).unwrap_err();
 
     drop(writer);
 
     let file = _restore_from.try_clone().unwrap();
-    let mut file = File::new(file).unwrap();
+    let file = File::new(file).unwrap();
     let mut cfg = ConfigureFile::default();
-    file.discover(&mut cfg);
-    cfg.or_insert_with(|cfg| {
-        panic!("Failed to restore configuration {cfg:?}");
-    });
-
     let mut valid_priors = vec![];
-    file.valid(&mut valid_priors);
+    file.recover(&mut cfg)
+        .expect("Failed to restore configuration")
+        .valid(&mut valid_priors);
     assert_eq!(valid_priors.len(), 0, "{:?}", &valid_priors);
-    let mut writer = file.configure(&cfg);
+    let mut writer = file.configure(&cfg).unwrap();
 
-    writer.write_with(GREETING, |tx| {
-        assert!(tx.tail().len() > 0);
-        true
+    writer.commit_with(GREETING, |tx| {
+        assert!(!tx.tail().is_empty());
+        Some(())
     }).unwrap();
 
     drop(writer);
 
     let file = _restore_from;
-    let mut file = File::new(file).unwrap();
+    let file = File::new(file).unwrap();
     let mut cfg = ConfigureFile::default();
-    file.discover(&mut cfg);
+    file.recover(&mut cfg);
     cfg.or_insert_with(|cfg| {
         panic!("Failed to restore configuration {cfg:?}");
     });
 }
+
+#[test]
+fn filter_keeps_entries() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x1_0000_0000).unwrap();
+    let _restore_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x80;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    const GREETING: &[u8] = b"Hello, world";
+    writer.commit(GREETING).unwrap();
+
+    drop(writer);
+
+    let file = File::new(_restore_from).unwrap();
+    let mut cfg = ConfigureFile::default();
+    let recovery = file.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+
+    let mut none = vec![];
+    recovery.filter(|_| false, &mut none);
+    assert!(none.is_empty(), "{:?}", &none);
+
+    let mut greeting = vec![];
+    recovery.filter(|snapshot| snapshot.length == GREETING.len() as u64, &mut greeting);
+    assert_eq!(greeting.len(), 1, "{:?}", &greeting);
+
+    // Filtering must not have invalidated the entry that was skipped before.
+    let mut valid = vec![];
+    recovery.valid(&mut valid);
+    assert_eq!(valid, greeting);
+}
+
+#[test]
+fn grow_entries_keeps_end_of_tail() {
+    use core::sync::atomic::Ordering;
+    use std::os::unix::fs::FileExt;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let _restore_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let tail_len = writer.tail_len();
+    let last = writer.tail().len() - 1;
+    writer.tail()[last].store(0x5eed, Ordering::Relaxed);
+
+    // Three more pages of sequence entries, the file grows by them.
+    writer.grow_entries(0x400).unwrap();
+    assert_eq!(writer.tail_len(), tail_len);
+    assert_eq!(writer.tail()[last].load(Ordering::Relaxed), 0x5eed);
+    drop(writer);
+
+    assert_eq!(_restore_from.metadata().unwrap().len(), 0x10_0000 + 0x3000);
+    let mut word = [0; 8];
+    _restore_from.read_exact_at(&mut word, 0x10_0000 + 0x3000 - 8).unwrap();
+    assert_eq!(u64::from_le_bytes(word), 0x5eed);
+}
+
+#[test]
+fn grow_entries_keeps_snapshots() {
+    use core::sync::atomic::Ordering;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let _restore_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.tail()[0].store(0xdead_beef, Ordering::Relaxed);
+
+    for idx in 0..6u8 {
+        writer.commit(&[idx; 16]).unwrap();
+    }
+
+    // The sequence ring only holds the four most recent entries.
+    let mut before = vec![];
+    writer.valid(&mut before);
+    assert_eq!(before.len(), 4, "{:?}", &before);
+
+    // Requires more than a single page of sequence entries, i.e. moves the data.
+    writer.grow_entries(0x200).unwrap();
+
+    let mut after = vec![];
+    writer.valid(&mut after);
+    // Entries move to other slots of the larger ring, the data they describe stays the same.
+    let data_of = |snapshots: &mut Vec<shm_snapshot::Snapshot>| {
+        snapshots.sort_by_key(|snapshot| snapshot.offset);
+        snapshots.iter().map(|s| (s.offset, s.length)).collect::<Vec<_>>()
+    };
+    assert_eq!(data_of(&mut before), data_of(&mut after));
+
+    for (snapshot, idx) in after.iter().zip(2u8..) {
+        let mut buffer = [0; 16];
+        writer.read(snapshot, &mut buffer).unwrap();
+        assert_eq!(buffer, [idx; 16]);
+    }
+
+    assert_eq!(writer.tail()[0].load(Ordering::Relaxed), 0xdead_beef);
+
+    for idx in 6..10u8 {
+        writer.commit(&[idx; 16]).unwrap();
+    }
+
+    drop(writer);
+
+    let file = File::new(_restore_from).unwrap();
+    let mut cfg = ConfigureFile::default();
+    let mut valid = vec![];
+    file.recover(&mut cfg)
+        .expect("Failed to restore configuration")
+        .valid(&mut valid);
+
+    assert_eq!(cfg.entries, 0x200);
+    assert_eq!(valid.len(), 8, "{:?}", &valid);
+}
+
+#[test]
+fn valid_in_commit_order() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let _restore_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    // Wraps around the sequence ring, the raw slot order no longer matches the commit order.
+    for idx in 0..6u8 {
+        writer.commit(&[idx; 16]).unwrap();
+    }
+
+    drop(writer);
+
+    let file = File::new(_restore_from).unwrap();
+    let mut cfg = ConfigureFile::default();
+    let recovery = file.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+
+    let mut ordered = vec![];
+    recovery.valid_ordered(&mut ordered);
+    assert_eq!(ordered.len(), 4, "{:?}", &ordered);
+
+    for (snapshot, idx) in ordered.iter().zip(2u8..) {
+        let mut buffer = [0; 16];
+        recovery.read(snapshot, &mut buffer).unwrap();
+        assert_eq!(buffer, [idx; 16]);
+    }
+}
+
+#[test]
+fn configure_min_entry_len() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    assert_eq!(cfg.max_entry_len(), 0xff);
+    cfg.min_entry_len = 0x100;
+
+    let err = file.configure(&cfg).expect_err("layout can not hold the entries");
+    assert_eq!(err, ConfigureError::EntryTooLarge { required: 0x100, max_entry_len: 0xff });
+}
+
+#[test]
+fn commit_chunks_concatenates() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let chunked = writer.commit_chunks(&[b"Hello", b", ", b"", b"world"]).unwrap();
+    let chunked = writer.snapshot_at(chunked);
+    let whole = writer.commit(b"Hello, world").unwrap();
+    let whole = writer.snapshot_at(whole);
+
+    assert_eq!(chunked.length, whole.length);
+
+    let mut chunked_buffer = [0; 12];
+    let mut whole_buffer = [0; 12];
+    writer.read(&chunked, &mut chunked_buffer).unwrap();
+    writer.read(&whole, &mut whole_buffer).unwrap();
+    assert_eq!(&chunked_buffer, b"Hello, world");
+    assert_eq!(chunked_buffer, whole_buffer);
+}
+
+#[test]
+fn contiguous_entries_do_not_wrap() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let _restore_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+        cfg.contiguous_entries = true;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let first = writer.commit(&[1; 0x40]).unwrap();
+    assert_eq!(writer.snapshot_at(first).offset, 0);
+    let second = writer.commit(&[2; 0x90]).unwrap();
+    assert_eq!(writer.snapshot_at(second).offset, 0x40);
+
+    // Would straddle the end of the ring, starts the next round of the ring instead.
+    let third = writer.commit(&[3; 0x40]).unwrap();
+    let third = writer.snapshot_at(third);
+    assert_eq!(third.offset, 0x100);
+
+    // The padding counts as written, the first entry is overwritten but not the second.
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert_eq!(valid.len(), 2, "{:?}", &valid);
+    assert!(valid.contains(&third));
+
+    drop(writer);
+
+    let file = File::new(_restore_from).unwrap();
+    let mut cfg = ConfigureFile::default();
+    let recovery = file.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+    assert!(cfg.contiguous_entries);
+
+    let mut buffer = [0; 0x40];
+    recovery.read(&third, &mut buffer).unwrap();
+    assert_eq!(buffer, [3; 0x40]);
+}
+
+#[test]
+fn finalize_then_recover() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    const GREETING: &[u8] = b"Hello, world";
+    writer.commit(GREETING).unwrap();
+
+    let file = writer.finalize().unwrap();
+    let mut cfg = ConfigureFile::default();
+    let recovery = file.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+
+    let mut valid = vec![];
+    recovery.valid(&mut valid);
+    assert_eq!(valid.len(), 1, "{:?}", &valid);
+
+    let mut buffer = [0; GREETING.len()];
+    recovery.read(&valid[0], &mut buffer).unwrap();
+    assert_eq!(&buffer, GREETING);
+}
+
+#[test]
+fn zero_uninitialized_clears_stale_entries() {
+    use std::os::unix::fs::FileExt;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    // Dirty the sequence ring, which follows the head page, but leave the head uninitialized.
+    file.write_all_at(&[0xff; 0x100], 0x1000).unwrap();
+    let dirty = file.try_clone().unwrap();
+
+    let mut file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    assert!(file.recover(&mut cfg).is_none());
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    assert!(file.zero_uninitialized(&cfg));
+    let mut writer = file.configure(&cfg).unwrap();
+
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert!(valid.is_empty(), "{:?}", &valid);
+
+    writer.commit(b"Hello, world").unwrap();
+    drop(writer);
+
+    // An initialized file is left alone.
+    let mut file = File::new(dirty).unwrap();
+    let mut cfg = ConfigureFile::default();
+    file.recover(&mut cfg).expect("Failed to restore configuration");
+    assert!(!file.zero_uninitialized(&cfg));
+
+    let writer = file.configure(&cfg).unwrap();
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert_eq!(valid.len(), 1, "{:?}", &valid);
+}
+
+#[test]
+fn recover_prior_format_version() {
+    use std::os::unix::fs::FileExt;
+
+    const PRIOR_VERSION: u64 = 0x96c2_a6f4b68519b3;
+    const GREETING: &[u8] = b"Hello, world";
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    // Head page: version, entry mask, data mask, write offset. Then one page of entries, and the
+    // data ring holding a single entry.
+    let head: Vec<u8> = [PRIOR_VERSION, 0x3, 0xff, GREETING.len() as u64]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+    let entry: Vec<u8> = [0, GREETING.len() as u64]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+    file.write_all_at(&head, 0).unwrap();
+    file.write_all_at(&entry, 0x1000).unwrap();
+    file.write_all_at(GREETING, 0x2000).unwrap();
+    let _restore_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+    let recovery = file.recover(&mut cfg)
+        .expect("Failed to restore configuration of prior version");
+    assert_eq!(cfg.entries, 0x4);
+    assert_eq!(cfg.data, 0x100);
+
+    let mut valid = vec![];
+    recovery.valid(&mut valid);
+    assert_eq!(valid.len(), 1, "{:?}", &valid);
+
+    let mut buffer = [0; GREETING.len()];
+    recovery.read(&valid[0], &mut buffer).unwrap();
+    assert_eq!(&buffer, GREETING);
+
+    // Configuring migrates the file to the current version.
+    let _writer = file.configure(&cfg).unwrap();
+    let mut version = [0; 8];
+    _restore_from.read_exact_at(&mut version, 0).unwrap();
+    assert_ne!(u64::from_le_bytes(version), PRIOR_VERSION);
+
+    let file = File::new(_restore_from).unwrap();
+    let mut cfg = ConfigureFile::default();
+    let mut valid = vec![];
+    file.recover(&mut cfg)
+        .expect("Failed to restore configuration")
+        .valid(&mut valid);
+    assert_eq!(valid.len(), 1, "{:?}", &valid);
+}
+
+#[test]
+fn recover_little_endian_fixture() {
+    use std::os::unix::fs::FileExt;
+
+    const GREETING: &[u8] = b"Hello, world";
+
+    // Head page: version, entry mask, data mask, write offset, flags, then the layout selector.
+    // All fields are little-endian independent of the host writing or reading the file.
+    const HEAD: [u8; 48] = [
+        0xb5, 0xc6, 0x71, 0x2a, 0x3e, 0x9d, b'L', b'E',
+        0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    // The first entry of the sequence ring: offset, length.
+    const ENTRY: [u8; 16] = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    file.write_all_at(&HEAD, 0).unwrap();
+    file.write_all_at(&ENTRY, 0x1000).unwrap();
+    file.write_all_at(GREETING, 0x2000).unwrap();
+    let _restore_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+    let recovery = file.try_recover(&mut cfg)
+        .expect("Failed to restore configuration of fixture");
+    assert_eq!(cfg.entries, 0x4);
+    assert_eq!(cfg.data, 0x100);
+    assert_eq!(cfg.initial_offset, GREETING.len() as u64);
+
+    let mut valid = vec![];
+    recovery.valid(&mut valid);
+    assert_eq!(valid.len(), 1, "{:?}", &valid);
+
+    let mut buffer = [0; GREETING.len()];
+    recovery.read(&valid[0], &mut buffer).unwrap();
+    assert_eq!(&buffer, GREETING);
+
+    // Writing produces the same byte order, also for the data written in whole words.
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"!").unwrap();
+    writer.commit(b"0123456789abcdef").unwrap();
+    drop(writer);
+
+    let mut data = [0; 29];
+    _restore_from.read_exact_at(&mut data, 0x2000).unwrap();
+    assert_eq!(&data, b"Hello, world!0123456789abcdef");
+
+    let mut version = [0; 8];
+    _restore_from.read_exact_at(&mut version, 0).unwrap();
+    assert_eq!(version, HEAD[..8]);
+
+    let mut entry = [0; 16];
+    _restore_from.read_exact_at(&mut entry, 0x1010).unwrap();
+    assert_eq!(entry, [
+        0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ]);
+}
+
+#[test]
+fn recover_interrupted_commit() {
+    use std::os::unix::fs::FileExt;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let crashed = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"Hello").unwrap();
+    let last = writer.commit(b"world").unwrap();
+    let last = writer.snapshot_at(last);
+    drop(writer);
+
+    let recover = || {
+        let file = File::new(crashed.try_clone().unwrap()).unwrap();
+        let mut cfg = ConfigureFile::default();
+        file.try_recover(&mut cfg).map(|discovery| discovery.last_entry_suspect()).unwrap()
+    };
+
+    assert!(!recover());
+
+    // The slot of the last entry holds the length of a previous entry, as if the writer stopped
+    // between storing its offset and its length.
+    let slot = 0x1000 + 16 * last.entry;
+    crashed.write_all_at(&7u64.to_le_bytes(), slot + 8).unwrap();
+    assert!(recover());
+
+    crashed.write_all_at(&last.length.to_le_bytes(), slot + 8).unwrap();
+    assert!(!recover());
+
+    // The data of the next commit was written, but neither its entry nor the write offset.
+    let write_offset = last.offset + last.length;
+    crashed.write_all_at(b"again", 0x2000 + write_offset).unwrap();
+    assert!(!recover());
+
+    // The entry of the last commit was stored, but the write offset in the header page was not.
+    let mut current = [0; 8];
+    crashed.read_exact_at(&mut current, 40).unwrap();
+    let layout = if u64::from_le_bytes(current) & 1 == 0 { 8 } else { 48 };
+    crashed.write_all_at(&last.offset.to_le_bytes(), layout + 16).unwrap();
+    assert!(recover());
+}
+
+#[test]
+fn last_entry_suspect_of_invalid_entries() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+        cfg.epochs = true;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"first").unwrap();
+    writer.set_epoch(1).unwrap();
+    writer.commit(b"second").unwrap();
+
+    // The newest entry is of another epoch, it still ends at the write offset.
+    writer.set_epoch(0).unwrap();
+    let mut cfg = ConfigureFile::default();
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+    assert_eq!(recovery.iter().count(), 1);
+    assert!(!recovery.last_entry_suspect());
+
+    // Retaining drops the newest entry from the file, the previous one does not end at the write
+    // offset.
+    writer.set_epoch(1).unwrap();
+    let keep: Vec<Snapshot> = vec![];
+    recovery.retain(&keep);
+    assert_eq!(recovery.iter().count(), 0);
+    assert!(recovery.last_entry_suspect());
+}
+
+#[test]
+fn configure_unknown_format_version() {
+    use std::os::unix::fs::FileExt;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    file.write_all_at(&0x1234_5678u64.to_le_bytes(), 0).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    assert!(file.recover(&mut cfg).is_none());
+    let err = file.try_recover(&mut cfg).err();
+    assert_eq!(err, Some(RecoverError::FormatVersionMismatch { found: 0x1234_5678 }));
+
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let err = file.configure(&cfg).expect_err("must not overwrite unknown data");
+    assert_eq!(err, ConfigureError::FormatVersionMismatch { found: 0x1234_5678 });
+}
+
+#[test]
+fn configure_for_file() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| *cfg = ConfigureFile::for_file(0x10_0000));
+    assert_eq!(cfg.data, 0x8_0000);
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(&[0x42; 0x4_0000]).unwrap();
+
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert_eq!(valid.len(), 1, "{:?}", &valid);
+}
+
+#[test]
+fn configure_interrupted_keeps_layout() {
+    use std::os::unix::fs::FileExt;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let _restore_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"Hello, world").unwrap();
+    drop(writer);
+
+    // Inject a torn layout into the slot not in use, as left by a crash during configuration.
+    // The head page holds the version, a first layout slot, the slot selector, the second slot.
+    let mut current = [0; 8];
+    _restore_from.read_exact_at(&mut current, 40).unwrap();
+    let unused = if u64::from_le_bytes(current) & 1 == 0 { 48 } else { 8 };
+    _restore_from.write_all_at(&0xffffu64.to_le_bytes(), unused).unwrap();
+
+    let file = File::new(_restore_from).unwrap();
+    let mut cfg = ConfigureFile::default();
+    let mut valid = vec![];
+    file.recover(&mut cfg)
+        .expect("Failed to restore configuration")
+        .valid(&mut valid);
+
+    assert_eq!(cfg.entries, 0x4);
+    assert_eq!(cfg.data, 0x100);
+    assert_eq!(valid.len(), 1, "{:?}", &valid);
+}
+
+#[test]
+fn configure_tail_reserve() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let second = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x8_0000;
+    });
+
+    // The head page, one page of entries and the data ring leave 126 pages.
+    cfg.tail_reserve = 0x7_e001;
+    let err = file.configure(&cfg).expect_err("tail can not hold the reserve");
+    assert_eq!(err, ConfigureError::TailTooSmall { required: 0x7_e001, available: 0x7_e000 });
+
+    let file = File::new(second).unwrap();
+    cfg.tail_reserve = 0x7_e000;
+    let writer = file.configure(&cfg).unwrap();
+    assert_eq!(writer.tail().len() * 8, 0x7_e000);
+}
+
+#[test]
+fn read_all_entries() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let _restore_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    assert_eq!(file.read_all().err(), Some(RecoverError::Uninitialized));
+
+    let mut cfg = ConfigureFile::default();
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"Hello").unwrap();
+    writer.commit(b"world").unwrap();
+    drop(writer);
+
+    let file = File::new(_restore_from).unwrap();
+    let entries = file.read_all().unwrap();
+    let data: Vec<_> = entries.iter().map(|(_, data)| data.as_slice()).collect();
+    assert_eq!(data, [b"Hello", b"world"]);
+}
+
+#[test]
+fn snapshot_is_current() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let idx = writer.commit(b"Hello, world").unwrap();
+    let committed = writer.snapshot_at(idx);
+
+    let mut cfg = ConfigureFile::default();
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+
+    let mut valid = vec![];
+    recovery.valid(&mut valid);
+    assert_eq!(valid, [committed]);
+    assert!(recovery.is_current(&committed));
+    assert!(!recovery.is_current(&shm_snapshot::Snapshot::new(committed.offset, committed.length)));
+
+    // Wrap around the sequence ring, which replaces the entry.
+    for _ in 0..4 {
+        writer.commit(b"Hello, world").unwrap();
+    }
+
+    assert!(!recovery.is_current(&committed));
+}
+
+#[test]
+fn commit_marker_in_flight() {
+    use std::sync::mpsc;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let before = reader.commit_marker();
+    assert!(!reader.is_commit_in_flight());
+
+    let (reserved_tx, reserved_rx) = mpsc::channel();
+    let (scanned_tx, scanned_rx) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            writer.commit_with(b"Hello, world", |_| {
+                reserved_tx.send(()).unwrap();
+                scanned_rx.recv().ok()
+            }).unwrap();
+        });
+
+        reserved_rx.recv().unwrap();
+        assert!(reader.is_commit_in_flight());
+        assert_eq!(reader.wait_for_commit(std::time::Duration::ZERO), None);
+        scanned_tx.send(()).unwrap();
+
+        let marker = reader.wait_for_commit(std::time::Duration::from_secs(10))
+            .expect("the commit to finish");
+        assert_eq!(marker, before + 2);
+    });
+
+    assert!(!reader.is_commit_in_flight());
+}
+
+#[test]
+fn header_of_configured_file() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    assert_eq!(file.header().version, 0);
+
+    let mut cfg = ConfigureFile::default();
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"Hello, world").unwrap();
+
+    let header = reader.header();
+    assert_ne!(header.version, 0);
+    assert_eq!(header.entry_mask, 0x3);
+    assert_eq!(header.page_mask, 0xff);
+    assert_eq!(header.page_write_offset, 12);
+    assert_eq!(header.commits, 2);
+}
+
+#[test]
+fn latest_by_embedded_sequence() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    // Wrap around both rings, only the most recent entries stay valid.
+    for seq in 0u64..10 {
+        let mut data = [0; 0x30];
+        data[..8].copy_from_slice(&seq.to_be_bytes());
+        writer.commit(&data).unwrap();
+    }
+
+    let mut cfg = ConfigureFile::default();
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+
+    let latest = recovery.latest_by(|_, data| {
+        u64::from_be_bytes(data[..8].try_into().unwrap())
+    }).unwrap();
+    assert_eq!(recovery.latest_by(|snapshot, _| snapshot.offset), Some(latest));
+
+    let mut data = [0; 0x30];
+    recovery.read(&latest, &mut data).unwrap();
+    assert_eq!(data[..8], 9u64.to_be_bytes());
+}
+
+#[test]
+fn readonly_reads_entries() {
+    use std::os::unix::io::AsRawFd;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    // Reopen the memory file without write access.
+    let readonly = std::fs::File::open(format!("/proc/self/fd/{}", file.as_raw_fd()))
+        .expect("to reopen the memory file");
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"Hello, world").unwrap();
+
+    let reader = File::new_readonly(readonly).unwrap();
+    let entries = reader.read_all().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].1, b"Hello, world");
+
+    let mut cfg = ConfigureFile::default();
+    reader.recover(&mut cfg).expect("Failed to restore configuration");
+    assert_eq!(reader.configure(&cfg).err(), Some(ConfigureError::ReadOnly));
+}
+
+#[test]
+fn reader_reuses_buffer() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"Hello, world").unwrap();
+    writer.commit(b"Hello").unwrap();
+    writer.commit(b"world").unwrap();
+
+    let mut cfg = ConfigureFile::default();
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+
+    let mut snapshots = vec![];
+    recovery.valid_ordered(&mut snapshots);
+
+    let mut reader = recovery.reader();
+    let first = reader.read(&snapshots[0]).unwrap();
+    assert_eq!(first, b"Hello, world");
+    let allocation = first.as_ptr();
+
+    for (snapshot, expected) in snapshots[1..].iter().zip([b"Hello", b"world"]) {
+        let data = reader.read(snapshot).unwrap();
+        assert_eq!(data, expected);
+        assert_eq!(data.as_ptr(), allocation);
+    }
+
+    // Lengths beyond the data ring fail before the buffer grows.
+    for length in [0x101, u64::MAX] {
+        let snapshot = Snapshot::new(0, length);
+        assert_eq!(reader.read(&snapshot), Err(ReadError::ExceedsCapacity { length }));
+    }
+}
+
+#[test]
+fn try_recover_explains_failure() {
+    for len in [0, 0x1000, 0x2000] {
+        let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+            .expect("to create a memory file");
+        file.set_len(len).unwrap();
+
+        let file = File::new(file).unwrap();
+        let mut cfg = ConfigureFile::default();
+        assert_eq!(file.try_recover(&mut cfg).err(), Some(RecoverError::TooSmall), "{len}");
+        assert!(file.recover(&mut cfg).is_none());
+    }
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+    assert_eq!(file.try_recover(&mut cfg).err(), Some(RecoverError::Uninitialized));
+
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let _writer = file.configure(&cfg).unwrap();
+    let mut cfg = ConfigureFile::default();
+    assert!(reader.try_recover(&mut cfg).is_ok());
+}
+
+#[test]
+fn epoch_invalidates_prior_commits() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let _restore_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+        cfg.epochs = true;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"old format").unwrap();
+    writer.commit(b"old format").unwrap();
+    drop(writer);
+
+    // The upgraded application finds entries of another epoch.
+    let file = File::new(_restore_from.try_clone().unwrap()).unwrap();
+    assert_eq!(file.header().epoch, 0);
+    assert_eq!(file.read_all().unwrap().len(), 2);
+
+    let mut cfg = ConfigureFile::default();
+    file.recover(&mut cfg).expect("Failed to restore configuration");
+    assert!(cfg.epochs);
+    let mut writer = file.configure(&cfg).unwrap();
+
+    // Setting the current epoch again keeps its entries.
+    writer.set_epoch(0).unwrap();
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert_eq!(valid.len(), 2);
+
+    writer.set_epoch(1).unwrap();
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert!(valid.is_empty());
+
+    writer.commit(b"new format").unwrap();
+
+    // Growing the ring keeps the epochs of the entries.
+    writer.grow_entries(0x200).unwrap();
+    assert_eq!(writer.iter().count(), 1);
+    drop(writer);
+
+    let file = File::new(_restore_from.try_clone().unwrap()).unwrap();
+    assert_eq!(file.header().epoch, 1);
+    let entries = file.read_all().unwrap();
+    let data: Vec<_> = entries.iter().map(|(_, data)| data.as_slice()).collect();
+    assert_eq!(data, [b"new format"]);
+
+    // Another epoch without any commit in between still invalidates the entries.
+    let mut cfg = ConfigureFile::default();
+    let recovery = file.recover(&mut cfg).expect("Failed to restore configuration");
+    let mut committed = vec![];
+    recovery.valid(&mut committed);
+    assert!(recovery.is_current(&committed[0]));
+
+    let reader = File::new(_restore_from).unwrap();
+    let mut writer = reader.configure(&cfg).unwrap();
+    writer.set_epoch(2).unwrap();
+    assert!(!recovery.is_current(&committed[0]));
+    assert_eq!(writer.iter().count(), 0);
+}
+
+#[test]
+fn epoch_requires_tags() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"untagged").unwrap();
+    assert_eq!(writer.set_epoch(1), Err(ConfigureError::NoEpochs));
+    assert_eq!(writer.iter().count(), 1);
+}
+
+#[test]
+fn iter_matches_valid() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    for data in [&b"a"[..], b"bc", b"def", b"ghij", b"klmno"] {
+        writer.commit(data).unwrap();
+    }
+
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert_eq!(writer.iter().collect::<Vec<_>>(), valid);
+
+    let mut cfg = ConfigureFile::default();
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+
+    let mut valid = vec![];
+    recovery.valid(&mut valid);
+    assert_eq!(valid.len(), 4);
+
+    let mut iter = recovery.iter();
+    let first = iter.next().unwrap();
+    let restart = iter.clone();
+    assert_eq!(first, valid[0]);
+    assert!(iter.eq(restart));
+    assert!(recovery.iter().all(|snapshot| snapshot.length > 0));
+    assert_eq!(recovery.iter().collect::<Vec<_>>(), valid);
+}
+
+#[test]
+fn read_reports_length() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let idx = writer.commit(b"Hello").unwrap();
+    let snapshot = writer.snapshot_at(idx);
+
+    let mut buffer = [0xff; 8];
+    assert_eq!(writer.read(&snapshot, &mut buffer), Ok(5));
+    assert_eq!(&buffer, b"Hello\xff\xff\xff");
+
+    let mut short = [0; 4];
+    assert_eq!(writer.read(&snapshot, &mut short), Err(ReadError::BufferTooSmall { length: 5 }));
+    assert_eq!(short, [0; 4]);
+
+    assert_eq!(writer.read_exact(&snapshot, &mut buffer), Err(ReadError::BufferTooLarge { length: 5 }));
+    let mut exact = [0; 5];
+    assert_eq!(writer.read_exact(&snapshot, &mut exact), Ok(()));
+    assert_eq!(&exact, b"Hello");
+}
+
+#[test]
+fn checksum_skips_torn_entries() {
+    use std::os::unix::fs::FileExt;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let corrupt = file.try_clone().unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+        cfg.checksum = true;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let first = writer.commit(b"Hello").unwrap();
+    let first = writer.snapshot_at(first);
+    writer.commit(b"world").unwrap();
+
+    // Head page, one page of entries, one page of checksums, then the data ring.
+    corrupt.write_all_at(b"J", 0x3000).unwrap();
+
+    let mut buffer = [0; 5];
+    assert_eq!(writer.read(&first, &mut buffer), Err(ReadError::ChecksumMismatch));
+    assert_eq!(&buffer, b"Jello");
+
+    let mut cfg = ConfigureFile::default();
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+    assert!(cfg.checksum);
+
+    let mut valid = vec![];
+    recovery.valid(&mut valid);
+    assert_eq!(valid.len(), 1, "{:?}", &valid);
+    recovery.read(&valid[0], &mut buffer).unwrap();
+    assert_eq!(&buffer, b"world");
+
+    // Growing the ring keeps the checksums of the entries.
+    writer.grow_entries(0x200).unwrap();
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert_eq!(valid.len(), 1, "{:?}", &valid);
+    writer.read(&valid[0], &mut buffer).unwrap();
+    assert_eq!(&buffer, b"world");
+}
+
+#[test]
+fn stream_round_trips_json() {
+    use std::io::Read;
+    use std::os::unix::fs::FileExt;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let corrupt = file.try_clone().unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+        cfg.checksum = true;
+    });
+
+    let value = serde_json::json!({ "name": "shm-snapshot", "values": [1, 2, 3] });
+    let mut writer = file.configure(&cfg).unwrap();
+    // Start off a word boundary.
+    writer.commit(b"abc").unwrap();
+    let idx = writer.commit(&serde_json::to_vec(&value).unwrap()).unwrap();
+    let snapshot = writer.snapshot_at(idx);
+
+    let read: serde_json::Value = serde_json::from_reader(writer.stream(snapshot)).unwrap();
+    assert_eq!(read, value);
+
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+    let mut stream = recovery.stream(snapshot);
+    let mut data = vec![];
+    let mut chunk = [0; 3];
+    loop {
+        match stream.read(&mut chunk).unwrap() {
+            0 => break,
+            len => data.extend_from_slice(&chunk[..len]),
+        }
+    }
+
+    assert_eq!(data, serde_json::to_vec(&value).unwrap());
+    assert_eq!(stream.read(&mut chunk).unwrap(), 0);
+
+    // Head page, one page of entries, one page of checksums, then the data ring.
+    corrupt.write_all_at(b"[", 0x3000 + snapshot.offset).unwrap();
+    let err = recovery.stream(snapshot).read_to_end(&mut vec![]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn checksum_mismatch_is_skipped() {
+    use std::os::unix::fs::FileExt;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let corrupt = file.try_clone().unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+        cfg.checksum = true;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let first = writer.commit(b"Hello").unwrap();
+    let first = writer.snapshot_at(first);
+    writer.commit(b"world").unwrap();
+
+    // Head page, one page of entries, one page of checksums, then the data ring.
+    corrupt.write_all_at(b"J", 0x3000).unwrap();
+
+    let entries = reader.read_all().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].1, b"world");
+
+    let mut cfg = ConfigureFile::default();
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+    assert_eq!(recovery.reader().read(&first), Err(ReadError::ChecksumMismatch));
+
+    // The corrupted entry would have the greatest key.
+    let latest = recovery.latest_by(|snapshot, _| std::cmp::Reverse(snapshot.offset));
+    assert_eq!(latest, Some(entries[0].0));
+}
+
+#[test]
+fn corrupt_length_is_skipped() {
+    use std::os::unix::fs::FileExt;
+
+    for checksum in [false, true] {
+        let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+            .expect("to create a memory file");
+        file.set_len(0x10_0000).unwrap();
+        let corrupt = file.try_clone().unwrap();
+        let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+        let file = File::new(file).unwrap();
+        let mut cfg = ConfigureFile::default();
+
+        file.recover(&mut cfg);
+        cfg.or_insert_with(|cfg| {
+            cfg.entries = 0x4;
+            cfg.data = 0x100;
+            cfg.checksum = checksum;
+        });
+
+        let mut writer = file.configure(&cfg).unwrap();
+        writer.commit(b"Hello").unwrap();
+        let last = writer.commit(b"world").unwrap();
+        let last = writer.snapshot_at(last);
+        drop(writer);
+
+        // A length far beyond the data ring, neither allocated nor checksummed.
+        let slot = 0x1000 + 16 * last.entry;
+        corrupt.write_all_at(&u64::MAX.to_le_bytes(), slot + 8).unwrap();
+        let entries = reader.read_all().unwrap();
+        let data: Vec<_> = entries.iter().map(|(_, data)| data.as_slice()).collect();
+        assert_eq!(data, [b"Hello"]);
+
+        // One more byte than the data ring holds.
+        corrupt.write_all_at(&0x101u64.to_le_bytes(), slot + 8).unwrap();
+        let mut cfg = ConfigureFile::default();
+        let recovery = reader.recover(&mut cfg)
+            .expect("Failed to restore configuration");
+        assert_eq!(recovery.iter().count(), 1);
+    }
+}
+
+#[test]
+fn concurrent_reader_sees_whole_commits() {
+    const COMMITS: u64 = 20_000;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    // Every commit is a run of the same non-zero byte, its length varies.
+    let data = |n: u64| vec![(n % 255) as u8 + 1; 16 + (n % 32) as usize];
+    writer.commit(&data(0)).unwrap();
+
+    let mut cfg = ConfigureFile::default();
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            for n in 1..COMMITS {
+                writer.commit(&data(n)).unwrap();
+            }
+        });
+
+        let mut reader = recovery.reader();
+        let mut confirmed = 0;
+        while confirmed < 1000 {
+            for snapshot in recovery.iter() {
+                let copy = reader.read(&snapshot).unwrap().to_vec();
+
+                // The entry may have been overwritten while copying, such a copy is discarded.
+                if !recovery.is_current(&snapshot) {
+                    continue;
+                }
+
+                assert!(copy.iter().all(|&b| b == copy[0] && b != 0), "{snapshot:?}: {copy:?}");
+                confirmed += 1;
+            }
+        }
+    });
+}
+
+#[test]
+fn read_consistent_discards_overwritten() {
+    const COMMITS: u64 = 20_000;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let data = |n: u64| vec![(n % 255) as u8 + 1; 16 + (n % 32) as usize];
+    writer.commit(&data(0)).unwrap();
+
+    let mut buffer = [0; 0x100];
+    let detached = Snapshot::new(0, 16);
+    assert_eq!(writer.read_consistent(&detached, &mut buffer), Err(ReadError::Stale));
+
+    let mut cfg = ConfigureFile::default();
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            for n in 1..COMMITS {
+                writer.commit(&data(n)).unwrap();
+            }
+        });
+
+        let mut confirmed = 0;
+        while confirmed < 1000 {
+            for snapshot in recovery.iter() {
+                match recovery.read_consistent(&snapshot, &mut buffer) {
+                    Ok(len) => {
+                        let copy = &buffer[..len];
+                        assert!(copy.iter().all(|&b| b == copy[0] && b != 0), "{snapshot:?}: {copy:?}");
+                        confirmed += 1;
+                    }
+                    Err(ReadError::Stale) => {}
+                    Err(err) => panic!("{snapshot:?}: {err}"),
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn commit_unaligned_lengths_wrap() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let mut buffer = [0; 64];
+
+    // Lengths are coprime to the word size, offsets cycle through every alignment and wrap the
+    // data ring many times.
+    for n in 0..0x1000u32 {
+        let len = 1 + (n % 37) as usize;
+        let data: Vec<u8> = (0..len).map(|i| (n as usize + i) as u8).collect();
+
+        let idx = writer.commit(&data).unwrap();
+        let snapshot = writer.snapshot_at(idx);
+        assert_eq!(writer.read(&snapshot, &mut buffer), Ok(len));
+        assert_eq!(&buffer[..len], &data[..], "{n}: {snapshot:?}");
+    }
+}
+
+#[test]
+fn recover_one_page_file() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x1000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+    assert!(matches!(file.try_recover(&mut cfg), Err(RecoverError::TooSmall)));
+    assert!(!cfg.is_initialized());
+}
+
+#[test]
+fn recover_head_without_entries() {
+    use std::os::unix::fs::FileExt;
+
+    const PRIOR_VERSION: u64 = 0x96c2_a6f4b68519b3;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    // An entry mask of all ones describes a ring of zero entries.
+    let head: Vec<u8> = [PRIOR_VERSION, u64::MAX, 0xff, 0]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+    file.write_all_at(&head, 0).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+    assert!(matches!(file.try_recover(&mut cfg), Err(RecoverError::Uninitialized)));
+    assert!(!cfg.is_initialized());
+}
+
+#[test]
+fn configure_errors_instead_of_panics() {
+    let memory_file = || {
+        let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+            .expect("to create a memory file");
+        file.set_len(0x10_0000).unwrap();
+        File::new(file).unwrap()
+    };
+
+    let mut cfg = ConfigureFile::default();
+    cfg.entries = 0x4;
+    cfg.data = 0x100;
+    let err = memory_file().configure(&cfg).err();
+    assert_eq!(err, Some(ConfigureError::Uninitialized));
+
+    cfg.or_insert_with(|_| {});
+    cfg.entries = 0x5;
+    let err = memory_file().configure(&cfg).err();
+    assert_eq!(err, Some(ConfigureError::NotPowerOfTwo));
+
+    // The head page, one page of entries and 256 pages of data.
+    cfg.entries = 0x4;
+    cfg.data = 0x10_0000;
+    let err = memory_file().configure(&cfg).err();
+    assert_eq!(err, Some(ConfigureError::TooSmall { needed: 0x10_2000, have: 0x10_0000 }));
+}
+
+#[test]
+fn recover_sequence_ring_larger_than_file() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let small = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        // Sixteen pages of entries.
+        cfg.entries = 0x2000;
+        cfg.data = 0x1000;
+    });
+
+    drop(file.configure(&cfg).unwrap());
+
+    // The head page and a couple of pages, the stored sequence ring no longer fits.
+    small.set_len(0x3000).unwrap();
+    let file = File::new(small).unwrap();
+    let mut cfg = ConfigureFile::default();
+    assert!(file.recover(&mut cfg).is_none());
+    assert!(!cfg.is_initialized());
+}
+
+#[test]
+fn recover_page_size_larger_than_file() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let small = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+        cfg.page_size = 0x4000;
+    });
+
+    drop(file.configure(&cfg).unwrap());
+
+    // A page of data follows the sequence ring, but not a whole page of 16 KiB.
+    small.set_len(0x9000).unwrap();
+    let file = File::new(small).unwrap();
+    let mut cfg = ConfigureFile::default();
+    assert_eq!(file.try_recover(&mut cfg).err(), Some(RecoverError::TooSmall));
+    assert_eq!(cfg.page_size, 0x4000);
+    let err = file.configure(&cfg).err();
+    assert_eq!(err, Some(ConfigureError::TooSmall { needed: 0xc000, have: 0x9000 }));
+}
+
+#[test]
+fn commit_errors() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let unconfigured = File::new(file.try_clone().unwrap()).unwrap();
+
+    let mut writer = unconfigured.into_writer_unguarded();
+    assert_eq!(writer.commit(b"Hello").unwrap_err(), CommitError::Uninitialized);
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let err = writer.commit(&[0; 0x100]).unwrap_err();
+    assert_eq!(err, CommitError::DataTooLarge { len: 0x100, capacity: 0xff });
+
+    let err = writer.commit_with(b"Hello", |_| None::<()>).unwrap_err();
+    assert_eq!(err, CommitError::Rejected);
+
+    writer.commit(&[0; 0xff]).unwrap();
+}
+
+#[test]
+fn writer_capacities() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    assert_eq!(writer.entry_capacity(), 0x4);
+    assert_eq!(writer.data_capacity(), 0x100);
+    assert_eq!(writer.data_used(), 0);
+    // The head page, one page of entries and one of data.
+    assert_eq!(writer.tail_len(), 0x10_0000 - 0x3000);
+
+    writer.commit(&[1; 0x40]).unwrap();
+    writer.commit(&[2; 0x20]).unwrap();
+    assert_eq!(writer.data_used(), 0x60);
+
+    // Overwrites the data of the first entry, which is invalidated.
+    writer.commit(&[3; 0xc0]).unwrap();
+    assert_eq!(writer.data_used(), 0xe0);
+
+    let len = writer.data_capacity() - 1;
+    writer.commit(&vec![4; len as usize]).unwrap();
+    assert_eq!(writer.data_used(), len);
+}
+
+#[test]
+fn writer_stats() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let stats = writer.stats();
+    assert_eq!(stats.entries_valid, 0);
+    assert_eq!(stats.write_offset, 0);
+    assert_ne!(stats.layout_version, 0);
+
+    writer.commit(&[1; 0x40]).unwrap();
+    writer.commit(&[2; 0x20]).unwrap();
+    // Overwrites the data of the first entry, which is invalidated.
+    writer.commit(&[3; 0xc0]).unwrap();
+
+    let stats = writer.stats();
+    assert_eq!(stats.entry_capacity, 0x4);
+    assert_eq!(stats.data_capacity, 0x100);
+    assert_eq!(stats.data_used, 0xe0);
+    assert_eq!(stats.entries_valid, 2);
+    assert_eq!(stats.write_offset, 0x120);
+
+    let debug = format!("{writer:?}");
+    assert!(debug.starts_with("Writer {"), "{debug}");
+    assert!(debug.contains("entries_valid: 2"), "{debug}");
+    assert!(debug.contains(&format!("layout_version: {:#x}", stats.layout_version)), "{debug}");
+}
+
+#[test]
+fn retain_resolved_commits() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let reader = File::new(file.try_clone().unwrap()).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let first = writer.commit(b"first").unwrap();
+    writer.commit(b"dropped").unwrap();
+    let third = writer.commit(b"third").unwrap();
+
+    let keep: Vec<Snapshot> = vec![writer.snapshot_at(first), writer.snapshot_at(third)];
+
+    let mut cfg = ConfigureFile::default();
+    let recovery = reader.recover(&mut cfg)
+        .expect("Failed to restore configuration");
+    recovery.retain(&keep);
+
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert_eq!(valid, keep);
+}
+
+#[test]
+fn transaction_current_and_abort() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    // Start off a word boundary.
+    writer.commit(b"abc").unwrap();
+
+    let (idx, staged) = writer.commit_with(b"Hello, world", |mut tx| {
+        tx.replace(b"Jello");
+        let mut staged = [0; 12];
+        tx.current(&mut staged);
+        Some(staged)
+    }).unwrap();
+
+    assert_eq!(&staged, b"Jello, world");
+    let snapshot = writer.snapshot_at(idx);
+    let mut buffer = [0; 12];
+    writer.read_exact(&snapshot, &mut buffer).unwrap();
+    assert_eq!(&buffer, b"Jello, world");
+
+    let err = writer.commit_with(b"discarded", |tx| {
+        tx.abort();
+        Some(())
+    }).unwrap_err();
+    assert_eq!(err, CommitError::Rejected);
+
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert_eq!(valid.len(), 2, "{valid:?}");
+}
+
+#[test]
+fn commit_vectored_concatenates() {
+    use std::io::IoSlice;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    let header = 12u32.to_be_bytes();
+    let idx = writer
+        .commit_vectored(&[IoSlice::new(&header), IoSlice::new(b"Hello, world")])
+        .unwrap();
+
+    let snapshot = writer.snapshot_at(idx);
+    let mut buffer = [0; 16];
+    writer.read_exact(&snapshot, &mut buffer).unwrap();
+    assert_eq!(&buffer[..4], &header);
+    assert_eq!(&buffer[4..], b"Hello, world");
+
+    // The combined length is checked, not that of each buffer.
+    let half = [0; 0x80];
+    let err = writer
+        .commit_vectored(&[IoSlice::new(&half), IoSlice::new(&half)])
+        .unwrap_err();
+    assert_eq!(err, CommitError::DataTooLarge { len: 0x100, capacity: 0xff });
+}
+
+#[test]
+fn reserve_fills_in_place() {
+    use std::sync::atomic::Ordering;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+        cfg.checksum = true;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+
+    // Lengths coprime to the word size, reservations start at every alignment and wrap.
+    for n in 0..0x200u32 {
+        let len = 1 + (n % 61) as usize;
+        let data: Vec<u8> = (0..len).map(|i| (n as usize * 7 + i) as u8).collect();
+
+        let mut reservation = writer.reserve(len).unwrap();
+        assert_eq!(reservation.len(), len);
+
+        let head = reservation.word_offset();
+        let (first, second) = reservation.words();
+        let words = first.len() + second.len();
+
+        for (word, bytes) in first.iter().chain(second).zip(data[head..].chunks_exact(8)) {
+            word.store(u64::from_le_bytes(bytes.try_into().unwrap()).to_le(), Ordering::Relaxed);
+        }
+
+        let tail = head + 8 * words;
+        reservation.write(0, &data[..head]);
+        reservation.write(tail, &data[tail..]);
+        let idx = reservation.commit();
+
+        let snapshot = writer.snapshot_at(idx);
+        let mut buffer = vec![0; len];
+        writer.read_exact(&snapshot, &mut buffer).unwrap();
+        assert_eq!(buffer, data, "{n}: {snapshot:?}");
+    }
+
+    let dropped = writer.reserve(0x10).unwrap();
+    drop(dropped);
+
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    assert!(valid.iter().all(|snapshot| snapshot.length != 0x10), "{valid:?}");
+    assert_eq!(writer.reserve(0x100).err(), Some(CommitError::DataTooLarge { len: 0x100, capacity: 0xff }));
+}
+
+#[test]
+#[cfg(feature = "bytemuck")]
+fn tail_as_plain_data() {
+    use std::sync::atomic::Ordering;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let writer = file.configure(&cfg).unwrap();
+    writer.tail()[0].store(u64::from_ne_bytes(*b"abcdefgh"), Ordering::Relaxed);
+    writer.tail()[1].store(42, Ordering::Relaxed);
+
+    // Safety: no other writer of the tail exists while the views are alive.
+    let words: &[u64] = unsafe { writer.tail_as() }.unwrap();
+    assert_eq!(words.len() * 8, writer.tail_len());
+    assert_eq!(words[1], 42);
+
+    let triples: &[[u8; 3]] = unsafe { writer.tail_as() }.unwrap();
+    assert_eq!(triples.len(), writer.tail_len() / 3);
+    assert_eq!(&triples[0], b"abc");
+    assert_eq!(&triples[1], b"def");
+
+    assert!(unsafe { writer.tail_as::<()>() }.is_none());
+}
+
+#[test]
+#[cfg(feature = "bytemuck")]
+fn tail_as_unaligned_region() {
+    use std::sync::atomic::AtomicU64;
+
+    // A region aligned to 8 but not to 16 bytes, as is the tail within it.
+    let words: &'static [AtomicU64] = Vec::from_iter((0..0x2001).map(|_| AtomicU64::new(0))).leak();
+    let skip = usize::from((words.as_ptr() as usize).is_multiple_of(16));
+    let file = File::from_static(&words[skip..][..0x2000]);
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let writer = file.configure(&cfg).unwrap();
+    assert!(unsafe { writer.tail_as::<u64>() }.is_some());
+    assert!(unsafe { writer.tail_as::<u128>() }.is_none());
+}
+
+#[test]
+fn configure_page_size() {
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    file.set_len(0x10_0000).unwrap();
+    let recover_from = file.try_clone().unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    cfg.page_size = 0x3000;
+    assert_eq!(cfg.tail_len(0x10_0000), None);
+    cfg.page_size = 0x800;
+    assert_eq!(cfg.tail_len(0x10_0000), None);
+
+    // The head page, a page of entries and a page of data, each padded to 16 KiB.
+    cfg.page_size = 0x4000;
+    assert_eq!(cfg.tail_len(0x10_0000), Some(0x10_0000 - 0xc000));
+
+    let mut writer = file.configure(&cfg).unwrap();
+    assert_eq!(writer.tail_len(), 0x10_0000 - 0xc000);
+    let index = writer.commit(&[0x42; 0x80]).unwrap();
+    let snapshot = writer.snapshot_at(index);
+    drop(writer);
+
+    let file = File::new(recover_from).unwrap();
+    assert_eq!(file.header().flags >> 8, 2);
+
+    let mut cfg = ConfigureFile::default();
+    let recovery = file.recover(&mut cfg).unwrap();
+    assert_eq!(cfg.page_size, 0x4000);
+    assert_eq!(cfg.data, 0x100);
+
+    let mut data = [0; 0x80];
+    recovery.read_exact(&snapshot, &mut data).unwrap();
+    assert_eq!(data, [0x42; 0x80]);
+
+    cfg.page_size = 0x3000;
+    let err = file.configure(&cfg).err();
+    assert_eq!(err, Some(ConfigureError::PageSize { page_size: 0x3000 }));
+}
+
+#[test]
+fn flush_writes_back_commits() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("snapshot");
+    let file = std::fs::File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    file.set_len(0x10_0000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(&[0x42; 0x10]).unwrap();
+    writer.flush().unwrap();
+
+    // A separate open of the path sees the commit.
+    let file = File::new_readonly(std::fs::File::open(&path).unwrap()).unwrap();
+    file.flush().unwrap();
+    let entries = file.read_all().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].1, [0x42; 0x10]);
+}
+
+#[test]
+fn grow_keeps_entries_and_tail() {
+    use std::sync::atomic::Ordering;
+
+    let file = CreateOptions::new().create(env!("CARGO_PKG_NAME"))
+        .expect("to create a memory file");
+    // The head page, one page of entries, one of data and one of tail.
+    file.set_len(0x4000).unwrap();
+
+    let file = File::new(file).unwrap();
+    let mut cfg = ConfigureFile::default();
+
+    file.recover(&mut cfg);
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x8;
+        cfg.data = 0x1000;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.tail()[0].store(0x5eed, Ordering::Relaxed);
+
+    // Invalidated by the entries after it, which nearly fill the ring and wrap around its end.
+    writer.commit(&[0; 0x800]).unwrap();
+    for byte in 1..=4 {
+        writer.commit(&[byte; 0x3f0]).unwrap();
+    }
+
+    assert_eq!(writer.grow(0x2000), Err(GrowError::Shrink));
+    writer.grow(0x10_0000).unwrap();
+
+    // 255 pages after the head page: one of entries, 128 of data and the rest for the tail.
+    assert_eq!(writer.data_capacity(), 0x8_0000);
+    assert_eq!(writer.tail_len(), 0x7_e000);
+    assert_eq!(writer.tail()[0].load(Ordering::Relaxed), 0x5eed);
+
+    for byte in 5..=6 {
+        writer.commit(&[byte; 0x3f0]).unwrap();
+    }
+
+    let mut valid = vec![];
+    writer.valid(&mut valid);
+    valid.sort_by_key(|snapshot| snapshot.offset);
+    assert_eq!(valid.len(), 6);
+
+    for (byte, snapshot) in (1..).zip(&valid) {
+        let mut data = [0; 0x3f0];
+        writer.read_exact(snapshot, &mut data).unwrap();
+        assert_eq!(data, [byte; 0x3f0]);
+    }
+}
+
+#[test]
+fn from_heap_mapping() {
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Memory of the heap, standing in for a mapping created by other means.
+    struct Heap {
+        memory: Box<[AtomicU64]>,
+        flushes: Arc<AtomicUsize>,
+    }
+
+    // Safety: the boxed memory is aligned for `u64`, and kept until dropped.
+    unsafe impl Mapping for Heap {
+        fn as_mut_ptr(&self) -> *mut u8 {
+            self.memory.as_ptr() as *mut u8
+        }
+
+        fn len(&self) -> usize {
+            self.memory.len() * 8
+        }
+
+        fn flush(&self) -> Result<(), std::io::Error> {
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn resize(&self, _: u64) -> Result<Box<dyn Mapping>, std::io::Error> {
+            Err(std::io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    let flushes = Arc::new(AtomicUsize::new(0));
+    let file = File::from_mapping(Heap {
+        memory: (0..0x10_0000 / 8).map(|_| AtomicU64::new(0)).collect(),
+        flushes: flushes.clone(),
+    });
+
+    let mut cfg = ConfigureFile::default();
+    assert!(file.recover(&mut cfg).is_none());
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(&[0x42; 0x10]).unwrap();
+    writer.flush().unwrap();
+    assert_eq!(flushes.load(Ordering::Relaxed), 1);
+
+    assert_eq!(writer.grow(0x20_0000), Err(GrowError::Io(std::io::ErrorKind::Unsupported)));
+
+    let file = writer.finalize().unwrap();
+    assert_eq!(flushes.load(Ordering::Relaxed), 2);
+    let entries = file.read_all().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].1, [0x42; 0x10]);
+}
+
+#[test]
+fn from_static_region() {
+    use std::sync::atomic::AtomicU64;
+
+    let region: &'static [AtomicU64] =
+        Vec::leak((0..0x10_0000 / 8).map(|_| AtomicU64::new(0)).collect());
+    let file = File::from_static(region);
+    let reader = File::from_static(region);
+
+    let mut cfg = ConfigureFile::default();
+    assert!(file.recover(&mut cfg).is_none());
+    cfg.or_insert_with(|cfg| {
+        cfg.entries = 0x4;
+        cfg.data = 0x100;
+    });
+
+    let mut writer = file.configure(&cfg).unwrap();
+    writer.commit(b"Hello, world").unwrap();
+    writer.flush().unwrap();
+    assert_eq!(writer.grow(0x20_0000), Err(GrowError::Io(std::io::ErrorKind::Unsupported)));
+    drop(writer);
+
+    // The region stays valid after the writer is dropped.
+    let entries = reader.read_all().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].1, b"Hello, world");
+}
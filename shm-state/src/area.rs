@@ -1,5 +1,7 @@
 //! Owns a file descriptor with known size.
 use crate::{MapError, Mapper};
+use alloc::sync::Arc;
+use core::ops::Range;
 use core::sync::atomic::AtomicU32;
 
 use shm_fd::{SharedFd, Shm, Stat};
@@ -11,6 +13,9 @@ pub struct AreaFd {
     stat: Stat,
     /// the usable length in the address space representation.
     len: usize,
+    /// Closes the descriptor of a file opened by us, a `SharedFd` never does.
+    #[cfg(feature = "std")]
+    _owned: Option<std::os::fd::OwnedFd>,
 }
 
 /// An owned file descriptor and its corresponding, memory-mapped region.
@@ -18,33 +23,168 @@ pub struct MappedFd {
     area: AreaFd,
     mapper: Mapper,
     mapping: &'static [AtomicU32],
+    /// The length passed to `mmap`, the area may have been resized since.
+    len: usize,
+}
+
+/// A shared handle to a part of a memory-mapped region.
+///
+/// Parts of one mapping are disjoint, each can host an independent structure such as a
+/// [`Ring`](crate::Ring). The region is unmapped when the last handle is dropped.
+#[derive(Clone)]
+pub struct MappedPart {
+    mapfd: Arc<MappedFd>,
+    /// The indices of the words of this part in the mapping.
+    words: Range<usize>,
 }
 
 impl AreaFd {
     pub fn new(fd: SharedFd, shm: &Shm) -> Result<Self, MapError> {
-        // FIXME: return the actual status code?
-        let stat = shm.stat(&fd).map_err(|_| MapError(11))?;
-        let len = usize::try_from(stat.st_size).map_err(|_| MapError(11))?;
-        Ok(AreaFd { fd, stat, len })
+        let (stat, len) = Self::stat_len(&fd, shm)?;
+        Ok(AreaFd {
+            fd,
+            stat,
+            len,
+            #[cfg(feature = "std")]
+            _owned: None,
+        })
+    }
+
+    /// Use an anonymous memory file, closing it when the area is dropped.
+    #[cfg(feature = "std")]
+    pub fn from_memfile(file: memfile::MemFile, shm: &Shm) -> Result<Self, MapError> {
+        Self::from_owned(file.into_file().into(), shm)
+    }
+
+    /// Open the file at `path` for reading and writing, closing it when the area is dropped.
+    #[cfg(feature = "std")]
+    pub fn open(path: impl AsRef<std::path::Path>, shm: &Shm) -> Result<Self, MapError> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            // Errors without a code are those of the path, such as an interior nul byte.
+            .map_err(|err| err.raw_os_error().map_or(MapError::INVALID_OPTIONS, MapError))?;
+        Self::from_owned(file.into(), shm)
+    }
+
+    #[cfg(feature = "std")]
+    fn from_owned(owned: std::os::fd::OwnedFd, shm: &Shm) -> Result<Self, MapError> {
+        use std::os::fd::{AsRawFd, FromRawFd};
+
+        // Safety: the descriptor is open while `owned` is, which we keep with the `SharedFd`.
+        let fd = unsafe { SharedFd::from_raw_fd(owned.as_raw_fd()) };
+        let area = AreaFd::new(fd, shm)?;
+
+        Ok(AreaFd {
+            _owned: Some(owned),
+            ..area
+        })
     }
 
     pub fn len(&self) -> usize {
         self.len
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The status of the file, when the area was created or last resized.
+    pub fn stat(&self) -> &Stat {
+        &self.stat
+    }
+
+    /// Change the length of the file, with `ftruncate` of the `shm` table.
+    ///
+    /// Mappings of the area keep their length, see [`MappedFd::remap`] to map the new length.
+    pub fn resize(&mut self, new_len: usize, shm: &Shm) -> Result<(), MapError> {
+        let ftruncate = shm.vtable().ftruncate.ok_or(MapError::UNSUPPORTED)?;
+        let len = i64::try_from(new_len).map_err(|_| MapError::ADDRESS_OVERFLOW)?;
+
+        if ftruncate(self.fd.as_raw_fd(), len) != 0 {
+            return Err(MapError((shm.vtable().errno)()));
+        }
+
+        let (stat, len) = Self::stat_len(&self.fd, shm)?;
+        self.stat = stat;
+        self.len = len;
+        Ok(())
+    }
+
+    fn stat_len(fd: &SharedFd, shm: &Shm) -> Result<(Stat, usize), MapError> {
+        let stat = shm.stat(fd).map_err(|err| MapError(err.raw_os_error()))?;
+        let len = usize::try_from(stat.st_size).map_err(|_| MapError::ADDRESS_OVERFLOW)?;
+        Ok((stat, len))
+    }
+
+    /// An area of `len` bytes, without a file behind it.
+    #[cfg(test)]
+    pub(crate) fn without_file(len: usize) -> Self {
+        extern crate std;
+        use std::os::unix::io::FromRawFd;
+
+        // Safety: the `SharedFd` never closes the descriptor, and this one is never used.
+        let fd = unsafe { SharedFd::from_raw_fd(-1) };
+        AreaFd {
+            fd,
+            stat: Stat::default(),
+            len,
+            #[cfg(feature = "std")]
+            _owned: None,
+        }
+    }
 }
 
 impl MappedFd {
     /// Create by mapping _all_ memory of the file descriptor at an arbitrary new location.
     pub fn new(mapper: Mapper, area: AreaFd) -> Result<Self, MapError> {
-        let mapping = mapper.mmap_shared(area.fd.as_raw_fd(), area.len())?;
+        let len = area.len();
+        let mapping = mapper.mmap_shared(area.fd.as_raw_fd(), len)?;
 
         Ok(MappedFd {
             area,
             mapper,
             mapping,
+            len,
         })
     }
 
+    /// Resize the file and map it again, at its new length.
+    ///
+    /// Any `Ring` or `Seq` of the mapping must be rebuilt afterwards, as its layout was computed
+    /// for the previous length. The contents of the file are kept. On failure to map, the previous
+    /// mapping is kept while the file may be resized already.
+    pub fn remap(&mut self, new_len: usize, shm: &Shm) -> Result<(), MapError> {
+        self.area.resize(new_len, shm)?;
+
+        let len = self.area.len();
+        let mapping = self.mapper.mmap_shared(self.area.fd.as_raw_fd(), len)?;
+        let old = core::mem::replace(&mut self.mapping, mapping);
+        let old_len = core::mem::replace(&mut self.len, len);
+
+        // Safety: no more references to this region of memory, they borrow from `self`.
+        unsafe { self.mapper.munmap(old, old_len) };
+        Ok(())
+    }
+
+    /// The area that is mapped.
+    pub fn area(&self) -> &AreaFd {
+        &self.area
+    }
+
+    /// Write back the mapping to the file.
+    pub fn sync(&self) -> Result<(), MapError> {
+        self.mapper.msync(self.mapping, self.len)
+    }
+
+    /// Split the mapping into a part before and one after the byte offset `at`.
+    ///
+    /// The offset must be a multiple of `4` and at most the length of the mapping.
+    pub fn split(self, at: usize) -> Result<(MappedPart, MappedPart), MapError> {
+        MappedPart::from(self).split(at)
+    }
+
     /// Get a copy of the inner mapping.
     ///
     /// # Safety
@@ -55,10 +195,173 @@ impl MappedFd {
     }
 }
 
+impl MappedPart {
+    /// The length of this part in bytes.
+    pub fn len(&self) -> usize {
+        self.words.len() * 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Split this part into one before and one after the byte offset `at`, see
+    /// [`MappedFd::split`].
+    pub fn split(self, at: usize) -> Result<(MappedPart, MappedPart), MapError> {
+        if !at.is_multiple_of(4) {
+            return Err(MapError::INVALID_OPTIONS);
+        }
+
+        if at > self.len() {
+            return Err(MapError::TOO_SMALL);
+        }
+
+        let mid = self.words.start + at / 4;
+        let head = MappedPart {
+            mapfd: Arc::clone(&self.mapfd),
+            words: self.words.start..mid,
+        };
+
+        let tail = MappedPart {
+            mapfd: self.mapfd,
+            words: mid..self.words.end,
+        };
+
+        Ok((head, tail))
+    }
+
+    /// Write back the whole mapping this is a part of, see [`MappedFd::sync`].
+    pub fn sync(&self) -> Result<(), MapError> {
+        self.mapfd.sync()
+    }
+
+    /// Get a copy of the words of this part.
+    ///
+    /// # Safety
+    ///
+    /// The caller must not use this after this handle has been dropped.
+    pub(crate) unsafe fn get_unchecked(&self) -> &'static [AtomicU32] {
+        &self.mapfd.get_unchecked()[self.words.clone()]
+    }
+}
+
+impl From<MappedFd> for MappedPart {
+    /// The whole mapping as a single part.
+    fn from(mapfd: MappedFd) -> Self {
+        let words = 0..mapfd.mapping.len();
+
+        MappedPart {
+            mapfd: Arc::new(mapfd),
+            words,
+        }
+    }
+}
+
 impl Drop for MappedFd {
     fn drop(&mut self) {
         let mmap = core::mem::take(&mut self.mapping);
         // Safety: no more references to this region of memory.
-        unsafe { self.mapper.munmap(mmap, self.area.len()) };
+        unsafe { self.mapper.munmap(mmap, self.len) };
     }
 }
+
+#[test]
+fn mapped_fd_unmaps() {
+    use crate::mmap::fault;
+
+    let mapfd = MappedFd::new(fault::mapper(), AreaFd::without_file(1 << 12)).unwrap();
+    assert_eq!(fault::live(), 1);
+    drop(mapfd);
+    assert_eq!(fault::live(), 0);
+
+    fault::fail_mmap(true);
+    let err = MappedFd::new(fault::mapper(), AreaFd::without_file(1 << 12)).err();
+    fault::fail_mmap(false);
+    assert_eq!(err.unwrap().raw_os_error(), Some(12));
+    assert_eq!(fault::live(), 0);
+}
+
+#[test]
+fn mapped_parts_unmap_once() {
+    use crate::mmap::fault;
+
+    let mapfd = MappedFd::new(fault::mapper(), AreaFd::without_file(1 << 12)).unwrap();
+    assert!(mapfd.split(2).err().unwrap().is_invalid_options());
+    assert_eq!(fault::live(), 0);
+
+    let mapfd = MappedFd::new(fault::mapper(), AreaFd::without_file(1 << 12)).unwrap();
+    let (head, tail) = mapfd.split(1 << 10).unwrap();
+    assert_eq!((head.len(), tail.len()), (1 << 10, 3 << 10));
+    assert!(tail.clone().split(4 << 10).err().unwrap().is_too_small());
+
+    let (middle, end) = tail.split(1 << 10).unwrap();
+    assert_eq!((middle.len(), end.len()), (1 << 10, 2 << 10));
+
+    drop(head);
+    drop(end);
+    assert_eq!(fault::live(), 1);
+    drop(middle);
+    assert_eq!(fault::live(), 0);
+}
+
+#[test]
+fn remap_grows_tail() {
+    extern crate std;
+    use crate::{Descriptor, Ring, RingOptions};
+    use core::sync::atomic::Ordering;
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+    let file = memfile::MemFile::create_default("remap_grows_tail").unwrap();
+    file.set_len(1 << 12).unwrap();
+
+    let shm = Shm::new();
+    let fd = unsafe { SharedFd::from_raw_fd(file.into_raw_fd()) };
+    let area = AreaFd::new(fd, &shm).unwrap();
+    let mapper = Mapper::from_shm(&shm).unwrap();
+
+    let mut mapfd = MappedFd::new(mapper, area).unwrap();
+    mapfd.mapping[0x100].store(42, Ordering::Relaxed);
+    mapfd.remap(1 << 13, &shm).unwrap();
+    assert_eq!(mapfd.area().len(), 1 << 13);
+    assert_eq!(mapfd.area().stat().st_size, 1 << 13);
+    assert_eq!(mapfd.mapping[0x100].load(Ordering::Relaxed), 42);
+
+    let opt = RingOptions { nr_descriptors: 4 };
+    let ring = Ring::with_part(mapfd.into(), &opt).unwrap();
+    // Safety: `part` outlives `ring`.
+    let (mut ring, _part) = unsafe { ring.into_parts() };
+    let tail = ring.tail().len();
+    assert_eq!(tail, (1 << 11) - 64 - 4 * 8);
+
+    ring.tail()[tail - 1].store(7, Ordering::Relaxed);
+    let desc = Descriptor {
+        start: ring.tail_offset() + (tail as u64 - 1) * 4,
+        end: ring.tail_offset() + tail as u64 * 4,
+        payload: 0,
+    };
+    assert!(ring.push(desc).is_ok());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn area_from_memfile_and_path() {
+    extern crate std;
+    use std::os::fd::AsRawFd;
+
+    let shm = Shm::new();
+    let file = memfile::MemFile::create_default("area_from_memfile").unwrap();
+    file.set_len(1 << 12).unwrap();
+
+    let path = std::format!("/proc/self/fd/{}", file.as_raw_fd());
+    let opened = AreaFd::open(&path, &shm).unwrap();
+    assert_eq!(opened.len(), 1 << 12);
+    assert_ne!(opened.fd.as_raw_fd(), file.as_raw_fd());
+
+    let area = AreaFd::from_memfile(file, &shm).unwrap();
+    assert_eq!(area.len(), 1 << 12);
+    assert_eq!(area.stat().identity(), opened.stat().identity());
+
+    let err = AreaFd::open("/proc/self/fd/-1", &shm).err().unwrap();
+    assert!(err.raw_os_error().is_some());
+    assert!(AreaFd::open("nul\0byte", &shm).err().unwrap().is_invalid_options());
+}
@@ -0,0 +1,259 @@
+//! A single value of fixed size.
+use crate::{
+    area::{AreaFd, MappedPart},
+    logs::SeqError,
+    ring::{DescriptorIdx, RingMapped},
+    Descriptor, MapError, Mapper, Ring, RingOptions,
+};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// The latest of a value of fixed size, which survives restarts.
+///
+/// A ring of two descriptors, each owning one of two buffers. A new value is written to the buffer
+/// of the descriptor not holding the current value, so that one of them is always frozen. Use
+/// [`Seq`](crate::logs::Seq) for values of varying length.
+pub struct Latest {
+    ring: RingMapped,
+    /// The length of the value in bytes.
+    len: usize,
+    /// The index of the first buffer in the tail of the ring.
+    data_offset: usize,
+    /// The descriptor of the current value, `None` before the first `set` or `restore`.
+    descriptor: Option<DescriptorIdx>,
+    /// Dropped after the copy of the mapping in `ring`.
+    mapfd: MappedPart,
+}
+
+impl Latest {
+    /// Map a ring for values of `len` bytes.
+    pub fn new(mapper: Mapper, area: AreaFd, len: usize) -> Result<Self, MapError> {
+        let ring = Ring::new(mapper, area, &RingOptions { nr_descriptors: 2 })?;
+        // Safety: we drop the `ring` before `mapfd` in all paths, by the order of fields.
+        let (ring, mapfd) = unsafe { ring.into_parts() };
+
+        let non_sharing_count = 256 / 4;
+        // The buffers precede the non-sharing space at the end of the tail.
+        let data_offset = len
+            .div_ceil(4)
+            .checked_mul(2)
+            .and_then(|words| words.checked_add(non_sharing_count))
+            .and_then(|end| ring.tail().len().checked_sub(end))
+            .ok_or(MapError::TOO_SMALL)?;
+
+        Ok(Latest {
+            ring,
+            len,
+            data_offset,
+            descriptor: None,
+            mapfd,
+        })
+    }
+
+    /// Find the most recent value.
+    ///
+    /// Fails with `NoSnapshot` if no value was stored, and with `LayoutChanged` if it was stored
+    /// with a different length.
+    pub fn restore(&mut self) -> Result<(), SeqError> {
+        // Seeds the timestamp of the ring, the next value is marked later than the restored one.
+        self.ring.restore().ok_or(SeqError::NoSnapshot)?;
+        let (idx, descriptor) = self.ring.iter_valid().last().ok_or(SeqError::NoSnapshot)?;
+
+        if descriptor != self.descriptor_for(idx) {
+            return Err(SeqError::LayoutChanged);
+        }
+
+        self.descriptor = Some(idx);
+        Ok(())
+    }
+
+    /// Change the value, which must be exactly as long as configured.
+    pub fn set(&mut self, value: &[u8]) -> Result<(), SeqError> {
+        if value.len() != self.len {
+            return Err(SeqError::InvalidLayout);
+        }
+
+        // The descriptor of the other buffer. The first value may use either.
+        let idx = self.descriptor.map_or(DescriptorIdx(0), Self::other);
+        // Its previous value is no longer frozen, before we modify it.
+        self.ring.invalidate(idx);
+
+        let buffer = self.buffer(idx);
+        let mut iter = value.chunks_exact(4);
+
+        for (word, ch) in buffer.iter().zip(iter.by_ref()) {
+            word.store(u32::from_ne_bytes(ch.try_into().unwrap()), Ordering::Relaxed);
+        }
+
+        let tail = iter.remainder();
+        if !tail.is_empty() {
+            let mut bytes = [0; 4];
+            bytes[..tail.len()].copy_from_slice(tail);
+            buffer[value.len() / 4].store(u32::from_ne_bytes(bytes), Ordering::Relaxed);
+        }
+
+        // Keeps the descriptor of the current value frozen.
+        self.ring.seek(idx);
+        let descriptor = self.descriptor_for(idx);
+        // The buffers are within the tail of the ring.
+        let new_idx = self
+            .ring
+            .push(descriptor)
+            .map_err(|_| SeqError::CapacityOverflow)?;
+        debug_assert_eq!(new_idx, idx);
+
+        self.descriptor = Some(idx);
+        Ok(())
+    }
+
+    /// Retrieve the current value.
+    ///
+    /// Copies at most the length of the value, a shorter buffer receives its start. Fails with
+    /// `NoSnapshot` before the first `set` or `restore`.
+    pub fn get(&self, value: &mut [u8]) -> Result<usize, SeqError> {
+        let idx = self.descriptor.ok_or(SeqError::NoSnapshot)?;
+        let len = value.len().min(self.len);
+        let buffer = self.buffer(idx);
+
+        for (ch, word) in value[..len].chunks_mut(4).zip(buffer) {
+            let bytes = word.load(Ordering::Relaxed).to_ne_bytes();
+            ch.copy_from_slice(&bytes[..ch.len()]);
+        }
+
+        Ok(self.len)
+    }
+
+    /// The length of the value in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the value is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Write back the shared memory to its file, see [`Ring::sync`].
+    pub fn sync(&self) -> Result<(), MapError> {
+        self.mapfd.sync()
+    }
+
+    fn other(idx: DescriptorIdx) -> DescriptorIdx {
+        DescriptorIdx((idx.0 & 1) ^ 1)
+    }
+
+    /// The buffer owned by a descriptor.
+    fn buffer(&self, idx: DescriptorIdx) -> &[AtomicU32] {
+        let words = self.len.div_ceil(4);
+        let start = self.data_offset + (idx.0 & 1) as usize * words;
+        &self.ring.tail()[start..][..words]
+    }
+
+    /// The descriptor of a value in the buffer of a descriptor.
+    fn descriptor_for(&self, idx: DescriptorIdx) -> Descriptor {
+        let words = self.len.div_ceil(4);
+        let start = (self.data_offset + (idx.0 & 1) as usize * words) * 4;
+        // Descriptors record offsets in the mapping, the tail follows the descriptors.
+        let start = self.ring.tail_offset() + start as u64;
+
+        Descriptor {
+            payload: self.len as u64,
+            start,
+            end: start + self.len as u64,
+        }
+    }
+}
+
+#[test]
+fn latest_restores_value() {
+    extern crate std;
+    use shm_fd::{SharedFd, Shm};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let file = memfile::MemFile::create_default("latest_restores_value").unwrap();
+    file.set_len(1 << 12).unwrap();
+
+    let shm = Shm::new();
+    let latest = || {
+        // Safety: the `SharedFd` never closes the descriptor, the file outlives it.
+        let fd = unsafe { SharedFd::from_raw_fd(file.as_raw_fd()) };
+        let area = AreaFd::new(fd, &shm).unwrap();
+        Latest::new(Mapper::from_shm(&shm).unwrap(), area, 6).unwrap()
+    };
+
+    let mut value = latest();
+    assert_eq!(value.restore(), Err(SeqError::NoSnapshot));
+    assert_eq!(value.get(&mut [0; 6]), Err(SeqError::NoSnapshot));
+    assert_eq!(value.set(b"short"), Err(SeqError::InvalidLayout));
+
+    value.set(b"first!").unwrap();
+    value.set(b"second").unwrap();
+
+    let mut buffer = [0; 6];
+    assert_eq!(value.get(&mut buffer), Ok(6));
+    assert_eq!(&buffer, b"second");
+    drop(value);
+
+    let mut value = latest();
+    value.restore().unwrap();
+    assert_eq!(value.get(&mut buffer[..4]), Ok(6));
+    assert_eq!(&buffer[..4], b"seco");
+
+    // The previous value stays frozen while the next one is written.
+    value.set(b"third!").unwrap();
+    assert_eq!(value.ring.depth(), 2);
+    drop(value);
+
+    let mut value = latest();
+    value.restore().unwrap();
+    assert_eq!(value.get(&mut buffer), Ok(6));
+    assert_eq!(&buffer, b"third!");
+}
+
+#[test]
+fn latest_set_after_restore() {
+    extern crate std;
+    use shm_fd::{SharedFd, Shm};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let file = memfile::MemFile::create_default("latest_set_after_restore").unwrap();
+    file.set_len(1 << 12).unwrap();
+
+    let shm = Shm::new();
+    let latest = || {
+        // Safety: the `SharedFd` never closes the descriptor, the file outlives it.
+        let fd = unsafe { SharedFd::from_raw_fd(file.as_raw_fd()) };
+        let area = AreaFd::new(fd, &shm).unwrap();
+        Latest::new(Mapper::from_shm(&shm).unwrap(), area, 4).unwrap()
+    };
+
+    let mut value = latest();
+    value.set(b"0001").unwrap();
+    // Freeze the same value again, its mark grows past any the other descriptor had.
+    for _ in 0..8 {
+        value.ring.seek(DescriptorIdx(0));
+        value.ring.push(value.descriptor_for(DescriptorIdx(0))).unwrap();
+    }
+    drop(value);
+
+    // The value written after restoring is later than all restored ones.
+    let mut value = latest();
+    value.restore().unwrap();
+    value.set(b"0004").unwrap();
+    drop(value);
+
+    let mut value = latest();
+    value.restore().unwrap();
+    let mut buffer = [0; 4];
+    assert_eq!(value.get(&mut buffer), Ok(4));
+    assert_eq!(&buffer, b"0004");
+}
+
+#[test]
+fn latest_too_small() {
+    use crate::mmap::fault;
+
+    let area = AreaFd::without_file(1 << 12);
+    let err = Latest::new(fault::mapper(), area, 1 << 12).err().unwrap();
+    assert!(err.is_too_small());
+    assert_eq!(fault::live(), 0);
+}
@@ -20,19 +20,76 @@
 //! program by suspending modifications while the snapshots take place.
 #![no_std]
 mod area;
+mod latest;
 mod mmap;
 mod ring;
 mod seq;
+mod typed;
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
-pub use area::AreaFd;
-pub use mmap::{Mapper, MapError, VTable};
-pub use ring::{Ring, RingOptions, Descriptor};
+pub use area::{AreaFd, MappedFd, MappedPart};
+pub use mmap::{HugePageSize, MapOptions, Mapper, MapError, VTable};
+pub use ring::{BackupSink, Ring, RingError, RingOptions, Descriptor, DescriptorIdx};
 
 /// Exports the different atomic, restorable checkpoint loggers.
 ///
 /// The performance characteristics and modification methods vary.
 pub mod logs {
-    pub use crate::seq::Seq;
+    pub use crate::latest::Latest;
+    pub use crate::seq::{Seq, SeqError, SeqOptions};
+    pub use crate::typed::{TypedSeq, Value};
+
+    /// The common interface of the loggers, to choose one at runtime.
+    ///
+    /// The loggers also provide these as inherent methods.
+    pub trait Log {
+        type Error;
+
+        /// Change the current value.
+        fn set(&mut self, value: &[u8]) -> Result<(), Self::Error>;
+
+        /// Retrieve the current value into `value`, returning its length.
+        ///
+        /// Copies at most the length of the value, a shorter buffer receives its start.
+        fn get(&self, value: &mut [u8]) -> Result<usize, Self::Error>;
+
+        /// Find the most recent value in the shared memory, returning its length.
+        fn restore(&mut self) -> Result<usize, Self::Error>;
+    }
+
+    impl Log for Seq {
+        type Error = SeqError;
+
+        fn set(&mut self, value: &[u8]) -> Result<(), SeqError> {
+            Seq::set(self, value)
+        }
+
+        fn get(&self, value: &mut [u8]) -> Result<usize, SeqError> {
+            Seq::get(self, value)
+        }
+
+        fn restore(&mut self) -> Result<usize, SeqError> {
+            Ok(Seq::restore(self)? as usize)
+        }
+    }
+
+    impl Log for Latest {
+        type Error = SeqError;
+
+        fn set(&mut self, value: &[u8]) -> Result<(), SeqError> {
+            Latest::set(self, value)
+        }
+
+        fn get(&self, value: &mut [u8]) -> Result<usize, SeqError> {
+            Latest::get(self, value)
+        }
+
+        fn restore(&mut self) -> Result<usize, SeqError> {
+            Latest::restore(self)?;
+            Ok(self.len())
+        }
+    }
 }
@@ -1,30 +1,73 @@
 use alloc::sync::Arc;
 use core::ffi::{c_int, c_void};
 use core::sync::atomic::AtomicU32;
+use shm_fd::Shm;
 
 pub struct VTable {
-    /// Simplified `mmap`.
-    pub mmap: fn(len: usize, prot: c_int, file: c_int) -> *mut c_void,
+    /// Simplified `mmap`, the `flags` are passed in addition to `MAP_SHARED`.
+    pub mmap: fn(len: usize, prot: c_int, flags: c_int, file: c_int) -> *mut c_void,
     pub munmap: fn(*mut c_void, usize) -> c_int,
+    /// Write back a mapping, `msync` with `MS_SYNC`.
+    pub msync: fn(*mut c_void, usize) -> c_int,
     pub errno: fn() -> c_int,
 
     pub prot_read: c_int,
     pub prot_write: c_int,
     pub map_failed: *mut c_void,
+
+    /// The value of `MAP_POPULATE`, `0` if not supported.
+    pub map_populate: c_int,
+    /// The value of `MAP_HUGETLB`, `0` if not supported.
+    pub map_hugetlb: c_int,
+    /// The value of `MAP_HUGE_SHIFT`, the position of the huge page size in the flags.
+    pub map_huge_shift: c_int,
 }
 
 #[derive(Clone)]
 pub struct Mapper {
     inner: Arc<Inner>,
+    options: MapOptions,
+}
+
+/// Options for the mappings of a [`Mapper`], see [`Mapper::with_options`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MapOptions {
+    /// Fault in all pages when mapping, with `MAP_POPULATE`.
+    ///
+    /// This trades a longer startup for predictable access times afterwards, there are no page
+    /// faults on first access to each page. Ignored if the vtable does not support it.
+    pub populate: bool,
+    /// Map the region with huge pages, with `MAP_HUGETLB`.
+    ///
+    /// The file must reside on a `hugetlbfs` and its length be a multiple of the page size.
+    pub huge: Option<HugePageSize>,
+}
+
+/// The size of huge pages to map with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HugePageSize {
+    /// The default huge page size of the system.
+    Default,
+    /// Pages of `1 << shift` bytes, for instance `21` for pages of 2MiB.
+    Shift(u8),
 }
 
-#[derive(Clone, Debug)]
+/// An error mapping a region, or laying out a structure within it.
+///
+/// Positive values are an `errno` reported by the OS. Layout failures, detected before any OS
+/// interaction, use negative values instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct MapError(pub(crate) c_int);
 
 struct Inner {
     vtable: VTable,
 }
 
+// Safety: the vtable holds plain functions, and `map_failed` is only compared against, never
+// dereferenced.
+unsafe impl Send for Inner {}
+unsafe impl Sync for Inner {}
+
 impl Mapper {
     /// Create a `Mapper` from a customized vtable.
     ///
@@ -34,19 +77,25 @@ impl Mapper {
     pub unsafe fn new_unchecked(vtable: VTable) -> Self {
         Mapper {
             inner: Arc::new(Inner { vtable }),
+            options: MapOptions::default(),
         }
     }
 
     #[cfg(feature = "libc")]
     pub fn new() -> Self {
-        fn _mmap_inner(len: usize, prot: c_int, file: c_int) -> *mut c_void {
-            unsafe { libc::mmap(core::ptr::null_mut(), len, prot, libc::MAP_SHARED, file, 0) }
+        fn _mmap_inner(len: usize, prot: c_int, flags: c_int, file: c_int) -> *mut c_void {
+            let flags = libc::MAP_SHARED | flags;
+            unsafe { libc::mmap(core::ptr::null_mut(), len, prot, flags, file, 0) }
         }
 
         fn _munmap(addr: *mut c_void, len: usize) -> c_int {
             unsafe { libc::munmap(addr, len) }
         }
 
+        fn _msync(addr: *mut c_void, len: usize) -> c_int {
+            unsafe { libc::msync(addr, len, libc::MS_SYNC) }
+        }
+
         fn _errno() -> c_int {
             unsafe { *libc::__errno_location() }
         }
@@ -55,23 +104,64 @@ impl Mapper {
             Self::new_unchecked(VTable {
                 mmap: _mmap_inner,
                 munmap: _munmap,
+                msync: _msync,
                 errno: _errno,
                 prot_read: libc::PROT_READ,
                 prot_write: libc::PROT_WRITE,
                 map_failed: libc::MAP_FAILED,
+                map_populate: libc::MAP_POPULATE,
+                map_hugetlb: libc::MAP_HUGETLB,
+                map_huge_shift: libc::MAP_HUGE_SHIFT,
             })
         }
     }
 
+    /// Create a `Mapper` using the functions of an `Shm`.
+    ///
+    /// This allows a single table of functions to be injected for both crates. Returns `None` if
+    /// the table of the `Shm` does not provide `mmap`, `munmap` and `msync`.
+    pub fn from_shm(shm: &Shm) -> Option<Self> {
+        let table = shm.vtable();
+
+        // Safety: `Shm::new_unchecked` requires a matching pair of `mmap` and `munmap` that
+        // returns a null pointer on failure.
+        Some(unsafe {
+            Self::new_unchecked(VTable {
+                mmap: table.mmap?,
+                munmap: table.munmap?,
+                msync: table.msync?,
+                errno: table.errno,
+                prot_read: table.prot_read,
+                prot_write: table.prot_write,
+                map_failed: core::ptr::null_mut(),
+                map_populate: table.map_populate,
+                map_hugetlb: table.map_hugetlb,
+                map_huge_shift: table.map_huge_shift,
+            })
+        })
+    }
+
+    /// Use these options for all following mappings.
+    pub fn with_options(mut self, options: MapOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The options used for mappings.
+    pub fn options(&self) -> &MapOptions {
+        &self.options
+    }
+
     pub fn mmap_shared(&self, file: c_int, len: usize) -> Result<&'static [AtomicU32], MapError> {
         let prot = self.inner.vtable.prot_read | self.inner.vtable.prot_write;
-        let ptr = (self.inner.vtable.mmap)(len, prot, file);
+        let flags = self.map_flags()?;
+        let ptr = (self.inner.vtable.mmap)(len, prot, flags, file);
 
         if ptr == self.inner.vtable.map_failed {
             return Err(MapError((self.inner.vtable.errno)()));
         }
 
-        assert!((ptr as usize) % 4 == 0, "Unaligned mmap address chosen");
+        assert!((ptr as usize).is_multiple_of(4), "Unaligned mmap address chosen");
         let count = len / 4;
 
         // Safety:
@@ -93,8 +183,103 @@ impl Mapper {
     pub unsafe fn munmap(&self, region: *const [AtomicU32], len: usize) {
         (self.inner.vtable.munmap)(region as *mut _, len);
     }
+
+    /// Write back a mapping created with `mmap_shared` to its file.
+    ///
+    /// The same length must be passed that was used to map the region.
+    pub fn msync(&self, region: *const [AtomicU32], len: usize) -> Result<(), MapError> {
+        if (self.inner.vtable.msync)(region as *mut _, len) != 0 {
+            return Err(MapError((self.inner.vtable.errno)()));
+        }
+
+        Ok(())
+    }
+
+    /// The flags of the options, in addition to `MAP_SHARED`.
+    fn map_flags(&self) -> Result<c_int, MapError> {
+        let vtable = &self.inner.vtable;
+        let mut flags = 0;
+
+        if self.options.populate {
+            flags |= vtable.map_populate;
+        }
+
+        match self.options.huge {
+            None => {}
+            // Unlike populating, this changes the requirements on the file. Do not ignore it.
+            Some(_) if vtable.map_hugetlb == 0 => return Err(MapError::INVALID_OPTIONS),
+            Some(HugePageSize::Default) => flags |= vtable.map_hugetlb,
+            // The size is encoded in six bits.
+            Some(HugePageSize::Shift(shift)) if shift < 64 => {
+                flags |= vtable.map_hugetlb | c_int::from(shift) << vtable.map_huge_shift;
+            }
+            Some(HugePageSize::Shift(_)) => return Err(MapError::INVALID_OPTIONS),
+        }
+
+        Ok(flags)
+    }
+}
+
+#[cfg(feature = "libc")]
+impl Default for Mapper {
+    fn default() -> Self {
+        Mapper::new()
+    }
 }
 
+impl MapError {
+    /// The options of the layout are invalid, for instance not a power-of-two.
+    pub(crate) const INVALID_OPTIONS: Self = MapError(-1);
+    /// The requested layout does not fit into the address space of this target.
+    pub(crate) const ADDRESS_OVERFLOW: Self = MapError(-2);
+    /// The region is too small to hold the requested layout.
+    pub(crate) const TOO_SMALL: Self = MapError(-3);
+    /// The table of functions does not provide a required function.
+    pub(crate) const UNSUPPORTED: Self = MapError(-4);
+
+    /// The `errno` of the failed OS call, if any.
+    pub fn raw_os_error(&self) -> Option<c_int> {
+        Some(self.0).filter(|&code| code > 0)
+    }
+
+    /// Check if the options of the layout were invalid.
+    pub fn is_invalid_options(&self) -> bool {
+        *self == Self::INVALID_OPTIONS
+    }
+
+    /// Check if the layout could not be represented in the address space.
+    ///
+    /// This happens with large options on targets with a small pointer width.
+    pub fn is_address_overflow(&self) -> bool {
+        *self == Self::ADDRESS_OVERFLOW
+    }
+
+    /// Check if the region was too small for the layout.
+    pub fn is_too_small(&self) -> bool {
+        *self == Self::TOO_SMALL
+    }
+
+    /// Check if a required function was missing from the table of functions.
+    pub fn is_unsupported(&self) -> bool {
+        *self == Self::UNSUPPORTED
+    }
+}
+
+impl core::fmt::Display for MapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            Self::INVALID_OPTIONS => write!(f, "The options of the layout are invalid"),
+            Self::ADDRESS_OVERFLOW => write!(f, "The layout does not fit into the address space"),
+            Self::TOO_SMALL => write!(f, "The region is too small to hold the layout"),
+            Self::UNSUPPORTED => write!(f, "The table does not provide a required function"),
+            MapError(code) => write!(f, "Mapping the region failed with errno {code}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MapError {}
+
 impl core::ops::Deref for Mapper {
     type Target = VTable;
 
@@ -102,3 +287,160 @@ impl core::ops::Deref for Mapper {
         &self.inner.vtable
     }
 }
+
+/// A mapper backed by the heap, which counts live mappings and can be told to fail.
+///
+/// State is per thread, so that tests running in parallel do not observe each other.
+#[cfg(test)]
+pub(crate) mod fault {
+    extern crate std;
+
+    use super::{Mapper, VTable};
+    use core::cell::Cell;
+    use core::ffi::{c_int, c_void};
+    use std::alloc::{alloc_zeroed, dealloc, Layout};
+
+    std::thread_local! {
+        static LIVE: Cell<usize> = const { Cell::new(0) };
+        static FAIL: Cell<bool> = const { Cell::new(false) };
+        static FLAGS: Cell<c_int> = const { Cell::new(0) };
+        static SYNCS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    const ALIGN: usize = 4096;
+    const ENOMEM: c_int = 12;
+
+    fn mmap(len: usize, _: c_int, flags: c_int, _: c_int) -> *mut c_void {
+        FLAGS.with(|f| f.set(flags));
+
+        if FAIL.with(Cell::get) || len == 0 {
+            return usize::MAX as *mut c_void;
+        }
+
+        let layout = Layout::from_size_align(len, ALIGN).unwrap();
+        LIVE.with(|live| live.set(live.get() + 1));
+        unsafe { alloc_zeroed(layout) as *mut c_void }
+    }
+
+    fn munmap(addr: *mut c_void, len: usize) -> c_int {
+        let layout = Layout::from_size_align(len, ALIGN).unwrap();
+        LIVE.with(|live| live.set(live.get() - 1));
+        unsafe { dealloc(addr as *mut u8, layout) };
+        0
+    }
+
+    fn msync(_: *mut c_void, _: usize) -> c_int {
+        SYNCS.with(|syncs| syncs.set(syncs.get() + 1));
+        0
+    }
+
+    fn errno() -> c_int {
+        ENOMEM
+    }
+
+    pub(crate) fn mapper() -> Mapper {
+        // Safety: allocations stand in for mappings, each freed with the length it was made with.
+        unsafe {
+            Mapper::new_unchecked(VTable {
+                mmap,
+                munmap,
+                msync,
+                errno,
+                prot_read: 1,
+                prot_write: 2,
+                map_failed: usize::MAX as *mut c_void,
+                map_populate: 0x1,
+                map_hugetlb: 0x2,
+                map_huge_shift: 26,
+            })
+        }
+    }
+
+    /// The flags of the last `mmap` call of this thread.
+    pub(crate) fn last_flags() -> c_int {
+        FLAGS.with(Cell::get)
+    }
+
+    /// The number of mappings of this thread which were not unmapped.
+    pub(crate) fn live() -> usize {
+        LIVE.with(Cell::get)
+    }
+
+    /// The number of `msync` calls of this thread.
+    pub(crate) fn syncs() -> usize {
+        SYNCS.with(Cell::get)
+    }
+
+    /// Make all following `mmap` calls of this thread fail.
+    pub(crate) fn fail_mmap(fail: bool) {
+        FAIL.with(|f| f.set(fail));
+    }
+}
+
+#[test]
+fn mapper_options_flags() {
+    let mapper = fault::mapper();
+    let region = mapper.mmap_shared(-1, 1 << 12).unwrap();
+    assert_eq!(fault::last_flags(), 0);
+    unsafe { mapper.munmap(region, 1 << 12) };
+
+    let cases = [
+        (true, None, 0x1),
+        (false, Some(HugePageSize::Default), 0x2),
+        (true, Some(HugePageSize::Shift(21)), 0x3 | 21 << 26),
+    ];
+
+    for (populate, huge, flags) in cases {
+        let mapper = fault::mapper().with_options(MapOptions { populate, huge });
+        let region = mapper.mmap_shared(-1, 1 << 12).unwrap();
+        assert_eq!(fault::last_flags(), flags);
+        unsafe { mapper.munmap(region, 1 << 12) };
+    }
+
+    let mapper = fault::mapper().with_options(MapOptions {
+        populate: false,
+        huge: Some(HugePageSize::Shift(64)),
+    });
+    assert!(mapper.mmap_shared(-1, 1 << 12).unwrap_err().is_invalid_options());
+    assert_eq!(fault::live(), 0);
+}
+
+#[test]
+fn mapper_from_shm() {
+    extern crate std;
+    use core::sync::atomic::Ordering;
+    use std::os::fd::AsRawFd;
+
+    let file = memfile::MemFile::create_default("mapper_from_shm").unwrap();
+    file.set_len(1 << 12).unwrap();
+
+    let mapper = Mapper::from_shm(&Shm::new()).unwrap();
+    let region = mapper.mmap_shared(file.as_raw_fd(), 1 << 12).unwrap();
+    region[0].store(42, Ordering::Relaxed);
+    assert_eq!(region.len(), 1 << 10);
+    mapper.msync(region, 1 << 12).unwrap();
+    unsafe { mapper.munmap(region, 1 << 12) };
+
+    let populated = mapper.clone().with_options(MapOptions {
+        populate: true,
+        huge: None,
+    });
+    let region = populated.mmap_shared(file.as_raw_fd(), 1 << 12).unwrap();
+    assert_eq!(region[0].load(Ordering::Relaxed), 42);
+    unsafe { populated.munmap(region, 1 << 12) };
+
+    let shm = Shm::new();
+    assert!(shm.vtable().ftruncate.is_some());
+    let err = mapper.mmap_shared(-1, 1 << 12).unwrap_err();
+    assert!(err.raw_os_error().is_some());
+}
+
+#[test]
+fn map_error_display() {
+    use alloc::string::ToString;
+
+    assert_eq!(MapError::TOO_SMALL.to_string(), "The region is too small to hold the layout");
+    assert_eq!(MapError(12).to_string(), "Mapping the region failed with errno 12");
+    assert_eq!(MapError(12).raw_os_error(), Some(12));
+    assert_eq!(MapError::INVALID_OPTIONS.raw_os_error(), None);
+}
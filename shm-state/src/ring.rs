@@ -1,5 +1,6 @@
-use crate::area::{AreaFd, MappedFd};
+use crate::area::{AreaFd, MappedFd, MappedPart};
 use crate::{MapError, Mapper};
+use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU32, Ordering};
 
 /// A transaction descriptor  ring-based abstraction.
@@ -9,23 +10,28 @@ use core::sync::atomic::{AtomicU32, Ordering};
 /// only atomicity. The creating program has to opt-in to potentially blocking hazards.
 ///
 /// The producer writes a sequence of descriptors to the ring. Each descriptor comes with an owner
-/// mark, an aligned `u32`, and denotes a slice of memory within the shared memory. The mark is in
-/// an open state when its least bit is `0` and in frozen state otherwise; and monotonically
-/// incremented.
+/// mark, a 64-bit timestamp stored as two aligned `u32` with the low word first, and denotes a
+/// slice of memory within the shared memory. The mark is in an open state when its least bit is
+/// `0` and in frozen state otherwise; and monotonically incremented. Each frozen mark is later than
+/// all marks written before it, the most recent descriptor has the largest mark.
 ///
 /// The producer ensures that all writes to the denoted, page aligned slice as well as the payload
 /// of the descriptor are *sequenced before* the mark is incremented to the next frozen state. And
 /// that the increment away from the frozen state is sequenced before all subsequent writes.
+/// Hence the producer writes to the region of a frozen descriptor only after opening it, with
+/// [`Ring::invalidate`] or by a [`Ring::push`] reusing its slot.
 ///
 /// The consumer _may_ write backups by atomically:
 /// 1. finding a frozen descriptor.
 /// 2. reading the data corresponding *at least* to the indicated slice and writing its backup.
 /// 3. checking that the descriptor is still in the same state as it was found in.
 /// 4. replacing its current backup with the new backup.
+///
+/// See [`Ring::backup_latest`] for an implementation of these steps.
 pub struct Ring {
     mapped: RingMapped,
     /// The mapfd is dropped after the copy of `mapping` in the other field.
-    mapfd: MappedFd,
+    mapfd: MappedPart,
 }
 
 /// Controller over a shared memory region.
@@ -34,7 +40,8 @@ pub(crate) struct RingMapped {
     /// own this region with this pointer and need to do so on `Drop`.
     mapping: &'static [AtomicU32],
     position: u32,
-    generation: u32,
+    /// The mark of the most recent descriptor, written or restored.
+    timestamp: u64,
     layout: Layout,
 }
 
@@ -48,7 +55,8 @@ pub struct RingOptions {
 struct Layout {
     index_descriptors: usize,
     index_descriptors_mask: u32,
-    tail: usize,
+    /// The first word after the descriptors, the tail extends to the end of the mapping.
+    tail_start: usize,
 }
 
 /// User-facing descriptor parameter.
@@ -72,6 +80,31 @@ struct DescriptorInner {
     end: [AtomicU32; 2],
 }
 
+/// The errors of writing descriptors to a [`Ring`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RingError {
+    /// The region of a descriptor ends before it starts, or is not within the tail.
+    BadRange,
+}
+
+impl core::fmt::Display for RingError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RingError::BadRange => write!(f, "The region of the descriptor is not within the tail"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RingError {}
+
+/// Receives the backup of a descriptor's region, see [`Ring::backup_latest`].
+pub trait BackupSink {
+    /// Replace the current backup with the bytes of a region, copied while its descriptor stayed
+    /// frozen.
+    fn write_region(&mut self, payload: u64, bytes: &[u8]);
+}
+
 /// The index of a descriptor.
 ///
 /// Always 'valid', the specific ring will mask the index before use. However, you should only use
@@ -81,8 +114,15 @@ pub struct DescriptorIdx(pub u32);
 
 impl Ring {
     pub fn new(mapper: Mapper, area: AreaFd, options: &RingOptions) -> Result<Self, MapError> {
-        let layout = RingMapped::layout_for(area.len(), options)?;
+        // Validate before mapping. Any failure after mapping must drop `mapfd` to unmap it.
+        RingMapped::layout_for(area.len(), options)?;
         let mapfd = MappedFd::new(mapper, area)?;
+        Self::with_part(mapfd.into(), options)
+    }
+
+    /// Create a ring in a part of a mapping, see [`MappedFd::split`].
+    pub fn with_part(mapfd: MappedPart, options: &RingOptions) -> Result<Self, MapError> {
+        let layout = RingMapped::layout_for(mapfd.len(), options)?;
 
         // Safety: field is not moved from or dropped while the mapping in the other field is used,
         // and that mapping is never passed around further.
@@ -92,7 +132,7 @@ impl Ring {
             mapped: RingMapped {
                 mapping,
                 position: 0,
-                generation: 0,
+                timestamp: 0,
                 layout,
             },
             mapfd,
@@ -106,27 +146,75 @@ impl Ring {
         self.mapped.restore()
     }
 
-    pub fn push(&mut self, descriptor: Descriptor) {
-        self.mapped.push(descriptor);
+    /// Write a descriptor at the next position and freeze it.
+    ///
+    /// Returns the index of the descriptor, to [`Self::invalidate`] it once its region is modified
+    /// again.
+    ///
+    /// Fails with [`RingError::BadRange`] if the region `start..end` is not within the tail,
+    /// nothing is written in that case.
+    pub fn push(&mut self, descriptor: Descriptor) -> Result<DescriptorIdx, RingError> {
+        self.mapped.push(descriptor)
     }
 
     pub fn invalidate(&mut self, idx: DescriptorIdx) -> bool {
         self.mapped.invalidate(idx)
     }
 
-    pub(crate) unsafe fn into_parts(self) -> (RingMapped, MappedFd) {
+    /// Write back the shared memory to its file, with `msync` of the mapper.
+    ///
+    /// Snapshots of the file taken afterwards contain all descriptors pushed before.
+    pub fn sync(&self) -> Result<(), MapError> {
+        self.mapfd.sync()
+    }
+
+    /// Back up the region of the most recent frozen descriptor, as the consumer.
+    ///
+    /// Copies the bytes `start..end` of the mapping and checks that the descriptor is still in the
+    /// state it was found in. Only then is the copy passed to the sink. Returns the descriptor on
+    /// success, `None` if no descriptor is frozen, its region is not within the mapping, or the
+    /// producer modified it while copying.
+    pub fn backup_latest<S: BackupSink>(&self, sink: &mut S) -> Option<DescriptorIdx> {
+        self.mapped.backup_latest(sink)
+    }
+
+    /// All frozen descriptors, ordered by their timestamp with the most recent last.
+    ///
+    /// This does not change the position of the ring, unlike [`Self::restore`].
+    pub fn iter_valid(&self) -> impl Iterator<Item = (DescriptorIdx, Descriptor)> {
+        self.mapped.iter_valid()
+    }
+
+    /// The generation of descriptors, incremented each time a mark wraps around.
+    pub fn generation(&self) -> u32 {
+        self.mapped.generation()
+    }
+
+    /// The number of currently active descriptors.
+    pub fn depth(&self) -> u32 {
+        self.mapped.depth()
+    }
+
+    pub(crate) unsafe fn into_parts(self) -> (RingMapped, MappedPart) {
         (self.mapped, self.mapfd)
     }
 }
 
+fn recombine_u64(atomics: &[AtomicU32; 2]) -> u64 {
+    let base = atomics[0].load(Ordering::Acquire);
+    let top = atomics[1].load(Ordering::Acquire);
+    u64::from(top) << 32 | u64::from(base)
+}
+
 impl RingMapped {
+    #[cfg(test)]
     pub(crate) fn wrap(mapping: &'static [AtomicU32], opt: &RingOptions) -> Result<Self, MapError> {
         let layout = Self::layout_for(core::mem::size_of_val(mapping), opt)?;
         Ok(RingMapped {
             mapping,
             layout,
             position: 0,
-            generation: 0,
+            timestamp: 0,
         })
     }
 
@@ -134,15 +222,69 @@ impl RingMapped {
     ///
     /// Returns this descriptor on success. This is the main restore entry point.
     pub fn restore(&mut self) -> Option<Descriptor> {
-        fn recombine_u64(atomics: &[AtomicU32; 2]) -> u64 {
-            let base = atomics[0].load(Ordering::Acquire);
-            let top = atomics[1].load(Ordering::Acquire);
-            u64::from(top) << 32 | u64::from(base)
+        let (index, max_ts) = self.latest()?;
+        self.position = index;
+        self.timestamp = max_ts;
+
+        Some(self.descriptor_at(index))
+    }
+
+    /// All frozen descriptors, see [`Ring::iter_valid`].
+    pub fn iter_valid(&self) -> impl Iterator<Item = (DescriptorIdx, Descriptor)> {
+        let mut valid: Vec<_> = (0..=self.layout.index_descriptors_mask)
+            .map(|index| (recombine_u64(&self.descriptors()[index as usize].mark), index))
+            .filter(|&(ts, _)| ts & 0x1 != 0)
+            .collect();
+
+        valid.sort_unstable();
+
+        let descriptors: Vec<_> = valid
+            .into_iter()
+            .map(|(_, index)| (DescriptorIdx(index), self.descriptor_at(index)))
+            .collect();
+        descriptors.into_iter()
+    }
+
+    fn descriptor_at(&self, index: u32) -> Descriptor {
+        let target = &self.descriptors()[index as usize];
+
+        Descriptor {
+            payload: recombine_u64(&target.payload),
+            start: recombine_u64(&target.start),
+            end: recombine_u64(&target.end),
         }
+    }
 
+    /// Back up the region of the most recent frozen descriptor, see [`Ring::backup_latest`].
+    pub fn backup_latest<S: BackupSink>(&self, sink: &mut S) -> Option<DescriptorIdx> {
+        // 1. Find a frozen descriptor. Its mark orders the loads of the payload and data after
+        // the producer's writes.
+        let (index, mark) = self.latest()?;
+        let target = &self.descriptors()[index as usize];
+        let payload = recombine_u64(&target.payload);
+        let start = recombine_u64(&target.start);
+        let end = recombine_u64(&target.end);
+
+        // 2. Read the indicated slice.
+        let bytes = self.copy_region(start, end)?;
+
+        // 3. Check that the descriptor is still in the same state, i.e. the data was not modified
+        // while copying.
+        core::sync::atomic::fence(Ordering::Acquire);
+        if recombine_u64(&target.mark) != mark {
+            return None;
+        }
+
+        // 4. Replace the backup.
+        sink.write_region(payload, &bytes);
+        Some(DescriptorIdx(index))
+    }
+
+    /// The index and timestamp of the most recent frozen descriptor.
+    fn latest(&self) -> Option<(u32, u64)> {
         // An _inactive_ descriptor as baseline.
         let mut max_ts = 0;
-        let mut max_desc = None;
+        let mut position = None;
 
         for index in 0..=self.layout.index_descriptors_mask {
             let target = &self.descriptors()[index as usize];
@@ -154,60 +296,73 @@ impl RingMapped {
             }
 
             if max_ts < ts {
-                self.position = index;
+                position = Some(index);
                 max_ts = ts;
             }
         }
 
-        if max_ts > 0 {
-            self.generation = (max_ts >> 32) as u32;
-            let target = &self.descriptors()[self.position as usize];
-
-            max_desc = Some(Descriptor {
-                payload: recombine_u64(&target.payload),
-                start: recombine_u64(&target.start),
-                end: recombine_u64(&target.end),
-            });
-        }
+        position.map(|index| (index, max_ts))
+    }
 
-        max_desc
+    /// Copy the bytes `start..end` of the mapping, `None` if they are not within it.
+    fn copy_region(&self, start: u64, end: u64) -> Option<Vec<u8>> {
+        let start = usize::try_from(start).ok()?;
+        let end = usize::try_from(end).ok()?;
+        let len = end.checked_sub(start)?;
+
+        let words = self.mapping.get(start / 4..end.div_ceil(4))?;
+        let mut bytes: Vec<u8> = words
+            .iter()
+            .flat_map(|word| word.load(Ordering::Relaxed).to_ne_bytes())
+            .collect();
+
+        bytes.drain(..start % 4);
+        bytes.truncate(len);
+        Some(bytes)
     }
 
-    pub fn push(&mut self, descriptor: Descriptor) -> DescriptorIdx {
-        fn split_u64(v: u64) -> [AtomicU32; 2] {
-            [v as u32, (v >> 32) as u32].map(AtomicU32::new)
+    pub fn push(&mut self, descriptor: Descriptor) -> Result<DescriptorIdx, RingError> {
+        fn split_u64(target: &[AtomicU32; 2], v: u64) {
+            target[0].store(v as u32, Ordering::Relaxed);
+            target[1].store((v >> 32) as u32, Ordering::Relaxed);
         }
 
-        let (_, new_mark) = self.invalidate_inner(DescriptorIdx(self.position));
+        // Consumers copy the region, it must be within the memory we hand out for data.
+        let tail_end = core::mem::size_of_val(self.mapping) as u64;
+        if descriptor.start > descriptor.end
+            || descriptor.start < self.tail_offset()
+            || descriptor.end > tail_end
+        {
+            return Err(RingError::BadRange);
+        }
+
+        let (_, open_mark) = self.invalidate_inner(DescriptorIdx(self.position));
         let index = self.position & self.layout.index_descriptors_mask;
         let target = &self.descriptors()[index as usize];
 
-        let inner = DescriptorInner {
-            mark: [AtomicU32::new(new_mark), AtomicU32::new(self.generation)],
-            payload: split_u64(descriptor.payload),
-            start: split_u64(descriptor.start),
-            end: split_u64(descriptor.end),
-        };
+        // Later than every descriptor we know of, and than all prior marks of this one.
+        let new_mark = (self.timestamp.max(open_mark) + 1) | 1;
 
-        for (t, v) in target.payload.iter().zip(inner.payload) {
-            t.store(v.into_inner(), Ordering::Relaxed);
-        }
+        split_u64(&target.payload, descriptor.payload);
+        split_u64(&target.start, descriptor.start);
+        split_u64(&target.end, descriptor.end);
 
-        for (t, v) in target.start.iter().zip(inner.start) {
-            t.store(v.into_inner(), Ordering::Relaxed);
-        }
-
-        for (t, v) in target.end.iter().zip(inner.end) {
-            t.store(v.into_inner(), Ordering::Relaxed);
-        }
+        // The generation first, the descriptor stays open until the low word is stored.
+        target.mark[1].store((new_mark >> 32) as u32, Ordering::Relaxed);
 
         // Ensure the sequencing with regards to buffer modification.
-        target.mark[0].store(new_mark | 1, Ordering::Release);
+        target.mark[0].store(new_mark as u32, Ordering::Release);
+        self.timestamp = new_mark;
 
         // Next descriptor will be written at next position.
         let buf_idx = DescriptorIdx(self.position);
         self.position = self.position.wrapping_add(1);
-        buf_idx
+        Ok(buf_idx)
+    }
+
+    /// Write the next descriptor at `idx`, instead of after the previous one.
+    pub(crate) fn seek(&mut self, idx: DescriptorIdx) {
+        self.position = idx.0;
     }
 
     /// Mark a descriptor as no longer valid.
@@ -218,22 +373,45 @@ impl RingMapped {
         old & 0x1 != 0
     }
 
-    fn invalidate_inner(&mut self, idx: DescriptorIdx) -> (u32, u32) {
+    /// The generation of descriptors, incremented each time a mark wraps around.
+    ///
+    /// This is the high word of the mark of the most recent descriptor, the timestamp by which
+    /// `restore` finds it.
+    pub fn generation(&self) -> u32 {
+        (self.timestamp >> 32) as u32
+    }
+
+    /// The number of currently active descriptors.
+    ///
+    /// A consumer that backs up all active descriptors has seen the whole surviving history once
+    /// it has seen this many distinct descriptors.
+    pub fn depth(&self) -> u32 {
+        let active = self.descriptors()[..=self.layout.index_descriptors_mask as usize]
+            .iter()
+            .filter(|desc| desc.mark[0].load(Ordering::Acquire) & 0x1 != 0)
+            .count();
+        // The descriptor count is a `u32`, so is the count of active ones.
+        active as u32
+    }
+
+    fn invalidate_inner(&mut self, idx: DescriptorIdx) -> (u64, u64) {
         let index = idx.0 & self.layout.index_descriptors_mask;
         let target = &self.descriptors()[index as usize];
 
-        let old_mark = target.mark[0].load(Ordering::Acquire);
+        let old_mark = recombine_u64(&target.mark);
         // Maybe we add _two_ here, if the mark is still in 'used' state.
-        // But surely the lowest bit is unset afterwards and old_mark < new_mark (in a wrapping
-        // sense of this relation). This marks the buffer as owned by the producer.
+        // But surely the lowest bit is unset afterwards and old_mark < new_mark. This marks the
+        // buffer as owned by the producer.
         let new_mark = (old_mark | 1).wrapping_add(1);
-        target.mark[0].store(new_mark, Ordering::Release);
 
-        // If we wrapped, increase the generation for a consistent timestamp.
-        if new_mark < old_mark {
-            let new_gen = target.mark[0].load(Ordering::Acquire) + 1;
-            self.generation = self.generation.max(new_gen);
-        }
+        // The low word first, the descriptor is open before the generation is bumped by a wrap of
+        // the low word.
+        target.mark[0].store(new_mark as u32, Ordering::Release);
+        target.mark[1].store((new_mark >> 32) as u32, Ordering::Release);
+        // A release store does not order later writes. The fence keeps the producer's writes to the
+        // region from becoming visible before the open mark, which pairs with the acquire fence in
+        // `backup_latest`.
+        core::sync::atomic::fence(Ordering::Release);
 
         (old_mark, new_mark)
     }
@@ -247,9 +425,17 @@ impl RingMapped {
         }
     }
 
-    /// Return the unused remaining part of memory.
+    /// Return the unused remaining part of memory, following the descriptors.
     pub fn tail(&self) -> &[AtomicU32] {
-        &self.mapping[..self.layout.tail]
+        &self.mapping[self.layout.tail_start..]
+    }
+
+    /// The byte offset of the tail in the mapping.
+    ///
+    /// Descriptors record regions by their offset in the mapping, a region of the tail starts at
+    /// this offset or later.
+    pub fn tail_offset(&self) -> u64 {
+        self.layout.tail_start as u64 * 4
     }
 
     fn layout_for(len: usize, options: &RingOptions) -> Result<Layout, MapError> {
@@ -258,49 +444,292 @@ impl RingMapped {
         let non_sharing_count = 256 / 4;
 
         if !options.nr_descriptors.is_power_of_two() {
-            return Err(MapError(11));
+            return Err(MapError::INVALID_OPTIONS);
         }
 
-        let descriptor_elements = (options.nr_descriptors as usize)
-            .checked_mul(8)
-            .ok_or(MapError(11))?;
+        let descriptor_elements = usize::try_from(options.nr_descriptors)
+            .ok()
+            .and_then(|count| count.checked_mul(8))
+            .ok_or(MapError::ADDRESS_OVERFLOW)?;
 
         // Place descriptors right after header.
         let index_descriptors = non_sharing_count;
         let usable_elements = usable_elements
             .checked_sub(non_sharing_count)
-            .ok_or(MapError(11))?;
-        let tail = usable_elements
+            .ok_or(MapError::TOO_SMALL)?;
+        usable_elements
             .checked_sub(descriptor_elements)
-            .ok_or(MapError(11))?;
+            .ok_or(MapError::TOO_SMALL)?;
+        let tail_start = index_descriptors + descriptor_elements;
 
         Ok(Layout {
             index_descriptors,
             index_descriptors_mask: options.nr_descriptors - 1,
-            tail,
+            tail_start,
         })
     }
 }
 
 #[test]
 fn primitive_ring_ops() {
-    const INIT: AtomicU32 = AtomicU32::new(0);
-    static REGION: [AtomicU32; 1 << 10] = [INIT; 1 << 10];
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
 
     let desc = Descriptor {
-        start: 0,
-        end: 0xabab,
+        start: 0x400,
+        end: 0xab0,
         payload: 0xdead_beef,
     };
 
     let mut ring = RingMapped::wrap(&REGION, &RingOptions { nr_descriptors: 16 }).unwrap();
 
-    ring.push(desc);
-
-    drop(ring);
+    ring.push(desc).unwrap();
 
     let mut ring = RingMapped::wrap(&REGION, &RingOptions { nr_descriptors: 16 }).unwrap();
 
     let found = ring.restore();
     assert_eq!(found, Some(desc));
 }
+
+#[test]
+fn generation_and_depth_across_wrap() {
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    let desc = Descriptor {
+        start: 0x400,
+        end: 0x410,
+        payload: 0,
+    };
+
+    let mut ring = RingMapped::wrap(&REGION, &RingOptions { nr_descriptors: 4 }).unwrap();
+    assert_eq!(ring.generation(), 0);
+    assert_eq!(ring.depth(), 0);
+
+    // Force the mark of the first descriptor to wrap on its next use.
+    ring.descriptors()[0].mark[0].store(u32::MAX, Ordering::Relaxed);
+    assert_eq!(ring.depth(), 1);
+
+    let first = ring.push(desc).unwrap();
+    assert_eq!(ring.generation(), 1);
+    assert_eq!(ring.depth(), 1);
+
+    ring.push(Descriptor { payload: 1, ..desc }).unwrap();
+    ring.push(Descriptor { payload: 2, ..desc }).unwrap();
+    assert_eq!(ring.depth(), 3);
+
+    assert!(ring.invalidate(first));
+    assert_eq!(ring.depth(), 2);
+
+    let mut ring = RingMapped::wrap(&REGION, &RingOptions { nr_descriptors: 4 }).unwrap();
+    assert!(ring.restore().is_some());
+    assert_eq!(ring.generation(), 1);
+    assert_eq!(ring.depth(), 2);
+}
+
+#[test]
+fn iter_valid_oldest_first() {
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    let desc = |payload| Descriptor {
+        start: 0x400,
+        end: 0x410,
+        payload,
+    };
+
+    let mut ring = RingMapped::wrap(&REGION, &RingOptions { nr_descriptors: 4 }).unwrap();
+    assert_eq!(ring.iter_valid().count(), 0);
+
+    // Wraps around, the first slot holds the most recent descriptor.
+    let first = ring.push(desc(0)).unwrap();
+    for payload in 1..5 {
+        ring.push(desc(payload)).unwrap();
+    }
+
+    assert!(ring.invalidate(DescriptorIdx(2)));
+
+    let valid: Vec<_> = ring.iter_valid().collect();
+    assert_eq!(
+        valid,
+        [
+            (DescriptorIdx(1), desc(1)),
+            (DescriptorIdx(3), desc(3)),
+            (first, desc(4)),
+        ]
+    );
+}
+
+#[test]
+fn backup_latest_confirms_mark() {
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    struct Backup(Vec<(u64, Vec<u8>)>);
+
+    impl BackupSink for Backup {
+        fn write_region(&mut self, payload: u64, bytes: &[u8]) {
+            self.0.push((payload, bytes.to_vec()));
+        }
+    }
+
+    let mut ring = RingMapped::wrap(&REGION, &RingOptions { nr_descriptors: 16 }).unwrap();
+    let mut backup = Backup(Vec::new());
+    assert_eq!(ring.backup_latest(&mut backup), None);
+
+    // The data is in the last 16 words of the tail, the region starts within the first of them.
+    let tail = ring.tail().len();
+    for (word, value) in ring.tail()[tail - 16..].iter().zip(1u32..) {
+        word.store(value, Ordering::Relaxed);
+    }
+
+    let desc = Descriptor {
+        payload: 7,
+        start: ring.tail_offset() + (tail as u64 - 16) * 4 + 2,
+        end: ring.tail_offset() + tail as u64 * 4,
+    };
+
+    let idx = ring.push(desc).unwrap();
+    assert_eq!(ring.backup_latest(&mut backup), Some(idx));
+
+    let expected: Vec<u8> = (1u32..=16).flat_map(u32::to_ne_bytes).skip(2).collect();
+    assert_eq!(backup.0, [(7, expected)]);
+
+    // A descriptor that is no longer frozen is not backed up.
+    assert!(ring.invalidate(idx));
+    assert_eq!(ring.backup_latest(&mut backup), None);
+
+    assert_eq!(backup.0.len(), 1);
+}
+
+#[test]
+fn restore_latest_across_mark_wrap() {
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    let desc = |payload| Descriptor {
+        start: 0x400,
+        end: 0x410,
+        payload,
+    };
+
+    let mut ring = RingMapped::wrap(&REGION, &RingOptions { nr_descriptors: 4 }).unwrap();
+    // A frozen descriptor just below the wrap of the low word of its mark.
+    ring.descriptors()[0].start[0].store(0x400, Ordering::Relaxed);
+    ring.descriptors()[0].end[0].store(0x410, Ordering::Relaxed);
+    ring.descriptors()[0].mark[0].store(u32::MAX - 2, Ordering::Relaxed);
+    assert_eq!(ring.restore(), Some(desc(0)));
+    assert_eq!(ring.generation(), 0);
+
+    // The marks of the second and third descriptor wrap around.
+    for payload in 1..=3 {
+        ring.push(desc(payload)).unwrap();
+    }
+
+    assert_eq!(ring.generation(), 1);
+
+    let mut ring = RingMapped::wrap(&REGION, &RingOptions { nr_descriptors: 4 }).unwrap();
+    assert_eq!(ring.restore(), Some(desc(3)));
+    assert_eq!(ring.generation(), 1);
+
+    let valid: Vec<_> = ring.iter_valid().map(|(_, desc)| desc.payload).collect();
+    assert_eq!(valid, [1, 2, 3]);
+}
+
+#[test]
+fn layout_errors() {
+    let opt = |nr_descriptors| RingOptions { nr_descriptors };
+
+    assert!(RingMapped::layout_for(1 << 12, &opt(3)).err().unwrap().is_invalid_options());
+    assert!(RingMapped::layout_for(0, &opt(16)).err().unwrap().is_too_small());
+    // Header fits, but the descriptors do not.
+    assert!(RingMapped::layout_for(256 + 16 * 32 - 4, &opt(16)).err().unwrap().is_too_small());
+    assert!(RingMapped::layout_for(256 + 16 * 32, &opt(16)).is_ok());
+}
+
+#[test]
+#[cfg(target_pointer_width = "64")]
+fn layout_large_descriptor_count() {
+    let opt = RingOptions { nr_descriptors: 1 << 31 };
+    assert!(RingMapped::layout_for(1 << 12, &opt).err().unwrap().is_too_small());
+}
+
+#[test]
+#[cfg(target_pointer_width = "32")]
+fn layout_large_descriptor_count() {
+    let opt = RingOptions { nr_descriptors: 1 << 31 };
+    assert!(RingMapped::layout_for(1 << 12, &opt).err().unwrap().is_address_overflow());
+}
+
+#[test]
+fn ring_new_errors_unmap() {
+    use crate::mmap::fault;
+
+    let opt = RingOptions { nr_descriptors: 16 };
+
+    for _ in 0..16 {
+        // Layout failures are found before mapping.
+        let err = Ring::new(fault::mapper(), AreaFd::without_file(256), &opt).err();
+        assert!(err.unwrap().is_too_small());
+        assert_eq!(fault::live(), 0);
+
+        fault::fail_mmap(true);
+        let err = Ring::new(fault::mapper(), AreaFd::without_file(1 << 12), &opt).err();
+        fault::fail_mmap(false);
+        assert!(err.unwrap().raw_os_error().is_some());
+        assert_eq!(fault::live(), 0);
+    }
+
+    let ring = Ring::new(fault::mapper(), AreaFd::without_file(1 << 12), &opt).unwrap();
+    assert_eq!(fault::live(), 1);
+    drop(ring);
+    assert_eq!(fault::live(), 0);
+}
+
+#[test]
+fn ring_push_invalidate() {
+    use crate::mmap::fault;
+
+    let opt = RingOptions { nr_descriptors: 4 };
+    let mut ring = Ring::new(fault::mapper(), AreaFd::without_file(1 << 12), &opt).unwrap();
+
+    let desc = |payload| Descriptor {
+        start: 0x400,
+        end: 0x410,
+        payload,
+    };
+
+    let first = ring.push(desc(0)).unwrap();
+    let second = ring.push(desc(1)).unwrap();
+    assert_ne!(first, second);
+
+    assert!(ring.invalidate(second));
+    assert!(!ring.invalidate(second));
+    assert_eq!(ring.restore(), Some(desc(0)));
+}
+
+#[test]
+fn push_rejects_bad_range() {
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    let mut ring = RingMapped::wrap(&REGION, &RingOptions { nr_descriptors: 4 }).unwrap();
+    // The tail follows the header and the descriptors, up to the end of the region.
+    let start = ring.tail_offset();
+    assert_eq!(start, 256 + 4 * 32);
+    let end = start + ring.tail().len() as u64 * 4;
+    assert_eq!(end, 1 << 12);
+
+    let desc = |start, end| Descriptor {
+        start,
+        end,
+        payload: 0,
+    };
+
+    assert_eq!(ring.push(desc(start + 0x10, start + 0x8)), Err(RingError::BadRange));
+    assert_eq!(ring.push(desc(start, end + 1)), Err(RingError::BadRange));
+    assert_eq!(ring.push(desc(end + 4, end + 4)), Err(RingError::BadRange));
+    // Regions of the header or the descriptors are not within the tail.
+    assert_eq!(ring.push(desc(0, 0x10)), Err(RingError::BadRange));
+    assert_eq!(ring.push(desc(start - 4, start)), Err(RingError::BadRange));
+    // Nothing was written by the rejected descriptors.
+    assert_eq!(ring.iter_valid().count(), 0);
+
+    assert_eq!(ring.push(desc(start + 0x10, start + 0x10)), Ok(DescriptorIdx(0)));
+    assert_eq!(ring.push(desc(start, end)), Ok(DescriptorIdx(1)));
+    assert_eq!(ring.restore(), Some(desc(start, end)));
+}
@@ -1,17 +1,15 @@
 //! A primitive sequential log.
 use crate::{
-    area::MappedFd,
+    area::MappedPart,
     ring::{DescriptorIdx, RingMapped},
-    Descriptor, Ring,
+    Descriptor, MapError, Ring,
 };
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 pub struct Seq {
     inner: SeqInner,
-    // Hmpf, if we used `Arc` for this and kept it within the `SeqInner.ring` then we wouldn't have
-    // this problem. Also it would solve the safety complexity. But an allocation..
-    #[allow(dead_code)]
-    mapfd: MappedFd,
+    /// Dropped after the copy of the mapping in `inner`.
+    mapfd: MappedPart,
 }
 
 pub struct SeqOptions {
@@ -19,13 +17,19 @@ pub struct SeqOptions {
     ///
     /// Must be a power-of-two, larger than 4.
     pub buffer: usize,
+    /// Reserve two buffers and alternate between them.
+    ///
+    /// Without this a value must fit into half of the buffer, so that writing it never overwrites
+    /// the previous value. With this a value may use the whole buffer, at the cost of twice the
+    /// memory. The buffer holding a value is recorded in the `start` of its descriptor.
+    pub double_buffer: bool,
 }
 
 #[derive(Clone, Copy)]
 struct Layout {
     data_offset: usize,
     buffer_mask: u32,
-    tail: usize,
+    double_buffer: bool,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -40,8 +44,30 @@ pub enum SeqError {
     NoSnapshot,
     /// The capacity of the buffer could not fit the provided data.
     CapacityOverflow,
+    /// During `restore`, the values were found to be written with different `SeqOptions`.
+    LayoutChanged,
+}
+
+impl core::fmt::Display for SeqError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SeqError::InvalidLayout => write!(f, "The layout or value violates the invariants"),
+            SeqError::UnfittingLayout => write!(f, "The ring is too small to hold the layout"),
+            SeqError::BadArchitectureLayout => {
+                write!(f, "The layout can not be represented on this architecture")
+            }
+            SeqError::NoSnapshot => write!(f, "No value was found to restore"),
+            SeqError::CapacityOverflow => write!(f, "The value does not fit the buffer"),
+            SeqError::LayoutChanged => {
+                write!(f, "The value was written with different options")
+            }
+        }
+    }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for SeqError {}
+
 struct SeqInner {
     ring: RingMapped,
     layout: Layout,
@@ -69,9 +95,36 @@ impl Seq {
         self.inner.set(seq)
     }
 
-    pub fn get(&mut self, seq: &mut [u8]) -> Result<usize, SeqError> {
+    /// Change the current value to `new` only if it still equals `expected`.
+    ///
+    /// Returns whether the value was changed. This is best-effort and not an atomic
+    /// compare-and-swap: the comparison loads the buffer as any `get` does, nothing keeps another
+    /// writer of the shared memory from modifying it between the comparison and the update.
+    pub fn compare_and_set(&mut self, expected: &[u8], new: &[u8]) -> Result<bool, SeqError> {
+        self.inner.compare_and_set(expected, new)
+    }
+
+    /// Retrieve the current value into `seq`, returning its length.
+    ///
+    /// Only loads from the mapping, a reader may share the `Seq` behind a reference.
+    pub fn get(&self, seq: &mut [u8]) -> Result<usize, SeqError> {
         self.inner.get(seq)
     }
+
+    /// Write back the shared memory to its file, see [`Ring::sync`].
+    pub fn sync(&self) -> Result<(), MapError> {
+        self.mapfd.sync()
+    }
+
+    /// The length of the current value, as of the last `set` or `restore`.
+    pub fn len(&self) -> usize {
+        self.inner.len as usize
+    }
+
+    /// Check if the current value is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 impl SeqInner {
@@ -93,12 +146,27 @@ impl SeqInner {
     /// match the variant `NoSnapshot` as a signal to initialize from scratch instead of an error.
     pub fn restore(&mut self) -> Result<u32, SeqError> {
         let last_descriptor = self.ring.restore().ok_or(SeqError::NoSnapshot)?;
+
+        // Zero if written before the tag was stored, assume the layout matches.
+        let tag = self.tag().load(Ordering::Relaxed);
+        if tag != 0 && tag != self.layout.tag() {
+            return Err(SeqError::LayoutChanged);
+        }
+
         let offset_len = last_descriptor.payload;
 
-        let begin = offset_len >> 32;
+        let mut begin = offset_len >> 32;
         let len = offset_len as u32;
 
-        if len > self.layout.buffer_mask / 2 {
+        if self.layout.double_buffer {
+            begin = last_descriptor
+                .start
+                .checked_sub(self.data_start())
+                .filter(|&begin| begin == 0 || begin == self.layout.buffer_len())
+                .ok_or(SeqError::InvalidLayout)?;
+        }
+
+        if u64::from(len) > self.layout.max_len() {
             return Err(SeqError::InvalidLayout);
         }
 
@@ -115,17 +183,24 @@ impl SeqInner {
         // Guarantees we do not overwrite the previous value, which means one valid value is
         // preserved even when this update does not complete for any reason (crash, scheduled
         // away).
-        if len > self.layout.buffer_mask / 2 {
+        if u64::from(len) > self.layout.max_len() {
             return Err(SeqError::InvalidLayout);
         }
 
-        let begin = self.begin;
-        let mut pos = self.begin;
+        let begin = if self.layout.double_buffer {
+            // The other buffer. The first value may use either.
+            self.begin ^ self.layout.buffer_len()
+        } else {
+            // Behind the previous value, in whole words.
+            (self.begin + u64::from(self.len).next_multiple_of(4)) & u64::from(self.layout.buffer_mask)
+        };
+
+        let mut pos = begin;
         let mut iter = seq.chunks_exact(4);
-        let data = &self.ring.tail()[self.layout.data_offset..];
+        let data = self.data();
 
-        while let Some(ch) = iter.next() {
-            let idx = pos & u64::from(self.layout.buffer_mask);
+        for ch in iter.by_ref() {
+            let idx = pos & self.layout.data_mask();
             let val = u32::from_ne_bytes(ch.try_into().unwrap());
             data[(idx >> 2) as usize].store(val, Ordering::Relaxed);
             pos += 4;
@@ -134,7 +209,7 @@ impl SeqInner {
         let tail = iter.remainder();
 
         if !tail.is_empty() {
-            let idx = pos & u64::from(self.layout.buffer_mask);
+            let idx = pos & self.layout.data_mask();
             let mut bytes = [0; 4];
             bytes[..tail.len().min(4)].copy_from_slice(tail);
             let val = u32::from_ne_bytes(bytes);
@@ -143,12 +218,27 @@ impl SeqInner {
 
         // Yes, we are shifting bits out but the buffer can not be larger than u32::MAX so these
         // bits are necessarily unused / masked away on access.
+        // Published with the descriptor below.
+        self.tag().store(self.layout.tag(), Ordering::Relaxed);
+
         let offset_len = (begin << 32) | u64::from(len);
-        let new_idx = self.ring.push(Descriptor {
-            start: 0,
-            end: self.layout.tail as u64,
-            payload: offset_len,
-        });
+        let (start, end) = if self.layout.double_buffer {
+            let start = self.data_start() + begin;
+            (start, start + u64::from(len))
+        } else {
+            let start = self.data_start();
+            (start, start + self.layout.buffer_len())
+        };
+
+        // The data is within the tail of the ring, so is any region of it.
+        let new_idx = self
+            .ring
+            .push(Descriptor {
+                start,
+                end,
+                payload: offset_len,
+            })
+            .map_err(|_| SeqError::CapacityOverflow)?;
 
         self.begin = begin;
         self.len = len;
@@ -167,36 +257,46 @@ impl SeqInner {
         Ok(())
     }
 
+    /// Change the current value if it equals `expected`, see [`Seq::compare_and_set`].
+    pub fn compare_and_set(&mut self, expected: &[u8], new: &[u8]) -> Result<bool, SeqError> {
+        if expected.len() != self.len as usize {
+            return Ok(false);
+        }
+
+        let mut current = alloc::vec![0; expected.len()];
+        self.get(&mut current)?;
+
+        if current != expected {
+            return Ok(false);
+        }
+
+        self.set(new)?;
+        Ok(true)
+    }
+
     /// Retrieve the current value.
-    pub fn get(&mut self, seq: &mut [u8]) -> Result<usize, SeqError> {
-        let mut iter = seq.chunks_exact_mut(4);
-        let mut range = 0..self.len;
-        let data = &self.ring.tail()[self.layout.data_offset..];
-
-        while range.len() > 4 {
-            if let Some(ch) = iter.next() {
-                let idx =
-                    (self.begin + u64::from(range.start)) & u64::from(self.layout.buffer_mask);
-                let bytes = data[(idx >> 2) as usize]
-                    .load(Ordering::Relaxed)
-                    .to_ne_bytes();
-                ch.copy_from_slice(&bytes);
-            } else {
-                break;
-            }
+    ///
+    /// Copies at most the length of the value, a shorter buffer receives its start. Returns the
+    /// length of the whole value.
+    pub fn get(&self, seq: &mut [u8]) -> Result<usize, SeqError> {
+        let len = seq.len().min(self.len as usize);
+        let mut pos = self.begin;
+        let mut iter = seq[..len].chunks_exact_mut(4);
+        let data = self.data();
 
-            range.start = range.start + 4;
+        for ch in iter.by_ref() {
+            let idx = pos & self.layout.data_mask();
+            let bytes = data[(idx >> 2) as usize].load(Ordering::Relaxed).to_ne_bytes();
+            ch.copy_from_slice(&bytes);
+            pos += 4;
         }
 
-        if !range.is_empty() {
-            let idx = (self.begin + u64::from(range.start)) & u64::from(self.layout.buffer_mask);
-            let bytes = data[(idx >> 2) as usize]
-                .load(Ordering::Relaxed)
-                .to_ne_bytes();
+        let tail = iter.into_remainder();
 
-            let tail = iter.into_remainder();
-            let tail_len = tail.len().min(4);
-            tail.copy_from_slice(&bytes[..tail_len]);
+        if !tail.is_empty() {
+            let idx = pos & self.layout.data_mask();
+            let bytes = data[(idx >> 2) as usize].load(Ordering::Relaxed).to_ne_bytes();
+            tail.copy_from_slice(&bytes[..tail.len()]);
         }
 
         Ok(self.len as usize)
@@ -215,33 +315,82 @@ impl SeqInner {
             u32::try_from(options.buffer - 1).map_err(|_| SeqError::BadArchitectureLayout)?;
 
         let non_sharing_count = 256 / 4;
+        let buffers = if options.double_buffer { 2 } else { 1 };
 
+        // The data precedes the non-sharing space at the end of the tail.
+        // The first word of the non-sharing space holds the tag of the layout.
         let data_offset = cnt
             .checked_sub(non_sharing_count)
-            .ok_or(SeqError::UnfittingLayout)?;
-
-        let tail = data_offset
-            .checked_sub(options.buffer / 4)
+            .and_then(|end| end.checked_sub((options.buffer / 4).checked_mul(buffers)?))
             .ok_or(SeqError::UnfittingLayout)?;
 
         Ok(Layout {
             data_offset,
-            tail,
             buffer_mask,
+            double_buffer: options.double_buffer,
         })
     }
+
+    /// The byte offset of the data in the mapping, as recorded in descriptors.
+    fn data_start(&self) -> u64 {
+        self.ring.tail_offset() + self.layout.data_offset as u64 * 4
+    }
+
+    /// The words holding the values.
+    fn data(&self) -> &[AtomicU32] {
+        let words = (self.layout.data_mask() + 1) / 4;
+        &self.ring.tail()[self.layout.data_offset..][..words as usize]
+    }
+
+    /// The word identifying the layout of the values, see `Layout::tag`.
+    fn tag(&self) -> &AtomicU32 {
+        let words = (self.layout.data_mask() + 1) / 4;
+        &self.ring.tail()[self.layout.data_offset + words as usize]
+    }
+}
+
+impl Layout {
+    /// Identifies the options of the layout, never zero.
+    ///
+    /// The low byte is the power of two of the buffer size, the bit `0x100` is set if values are
+    /// double buffered.
+    fn tag(&self) -> u32 {
+        let double = if self.double_buffer { 0x100 } else { 0 };
+        self.buffer_mask.count_ones() | double
+    }
+
+    /// The length of one buffer in bytes.
+    fn buffer_len(&self) -> u64 {
+        u64::from(self.buffer_mask) + 1
+    }
+
+    /// The mask of byte offsets into the data, covering both buffers if double buffered.
+    fn data_mask(&self) -> u64 {
+        if self.double_buffer {
+            2 * self.buffer_len() - 1
+        } else {
+            u64::from(self.buffer_mask)
+        }
+    }
+
+    /// The length of the largest value, such that a new value never overwrites the previous one.
+    fn max_len(&self) -> u64 {
+        if self.double_buffer {
+            self.buffer_len()
+        } else {
+            u64::from(self.buffer_mask / 2)
+        }
+    }
 }
 
 #[test]
 fn simple_seq() {
     use crate::ring::{RingMapped, RingOptions};
-    use core::sync::atomic::AtomicU32;
 
-    const INIT: AtomicU32 = AtomicU32::new(0);
-    static REGION: [AtomicU32; 1 << 10] = [INIT; 1 << 10];
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
 
     let ropt = RingOptions { nr_descriptors: 2 };
-    let sopt = SeqOptions { buffer: 1 << 7 };
+    let sopt = SeqOptions { buffer: 1 << 7, double_buffer: false };
 
     let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
     let mut seq = SeqInner::wrap(ring, &sopt).unwrap();
@@ -262,3 +411,316 @@ fn simple_seq() {
     assert_eq!(seq.get(&mut buffer), Ok(HELLO.len()));
     assert_eq!(buffer, HELLO);
 }
+
+#[test]
+fn seq_data_after_descriptors() {
+    use crate::ring::{RingMapped, RingOptions};
+
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    // The descriptors take half of the region, the data must not overlap them.
+    let ropt = RingOptions { nr_descriptors: 64 };
+    let sopt = SeqOptions { buffer: 1 << 9, double_buffer: false };
+
+    let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+    let mut seq = SeqInner::wrap(ring, &sopt).unwrap();
+    assert!(seq.data_start() >= 256 + 64 * 32);
+
+    let value = [0xff; 200];
+    seq.set(&value).unwrap();
+    seq.set(&value).unwrap();
+    assert_eq!(seq.ring.iter_valid().count(), 1);
+
+    let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+    let mut seq = SeqInner::wrap(ring, &sopt).unwrap();
+    assert_eq!(seq.restore(), Ok(200));
+
+    let mut buffer = [0; 200];
+    assert_eq!(seq.get(&mut buffer), Ok(200));
+    assert_eq!(buffer, value);
+}
+
+#[test]
+fn seq_new_errors_unmap() {
+    use crate::area::AreaFd;
+    use crate::mmap::fault;
+    use crate::ring::RingOptions;
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+
+    // The ring is mapped, but its tail can not hold the buffer.
+    for _ in 0..16 {
+        let ring = Ring::new(fault::mapper(), AreaFd::without_file(1 << 12), &ropt).unwrap();
+        assert_eq!(fault::live(), 1);
+
+        let err = Seq::new(ring, &SeqOptions { buffer: 1 << 20, double_buffer: false }).err();
+        assert_eq!(err, Some(SeqError::UnfittingLayout));
+        assert_eq!(fault::live(), 0);
+    }
+
+    let ring = Ring::new(fault::mapper(), AreaFd::without_file(1 << 12), &ropt).unwrap();
+    let seq = Seq::new(ring, &SeqOptions { buffer: 1 << 7, double_buffer: false }).unwrap();
+    assert_eq!(fault::live(), 1);
+    drop(seq);
+    assert_eq!(fault::live(), 0);
+}
+
+#[test]
+fn seq_get_shared() {
+    use crate::area::AreaFd;
+    use crate::mmap::fault;
+    use crate::ring::RingOptions;
+    use alloc::sync::Arc;
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+    let ring = Ring::new(fault::mapper(), AreaFd::without_file(1 << 12), &ropt).unwrap();
+    let mut seq = Seq::new(ring, &SeqOptions { buffer: 1 << 7, double_buffer: false }).unwrap();
+
+    const HELLO: &[u8] = b"Hello, world!";
+    seq.set(HELLO).unwrap();
+
+    let seq = Arc::new(seq);
+    let reader = Arc::clone(&seq);
+    assert_eq!(reader.len(), HELLO.len());
+
+    let mut buffer = [0; HELLO.len()];
+    assert_eq!(reader.get(&mut buffer), Ok(HELLO.len()));
+    assert_eq!(buffer, HELLO);
+}
+
+#[test]
+fn double_buffer_full_value() {
+    use crate::ring::{RingMapped, RingOptions};
+
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+    let single = SeqOptions { buffer: 1 << 7, double_buffer: false };
+    let sopt = SeqOptions { buffer: 1 << 7, double_buffer: true };
+
+    let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+    let mut seq = SeqInner::wrap(ring, &sopt).unwrap();
+
+    // Just under the whole buffer, which does not fit without double buffering.
+    let first = [0x5a; (1 << 7) - 1];
+    let second = [0xa5; (1 << 7) - 3];
+    seq.set(&first).unwrap();
+    seq.set(&second).unwrap();
+    assert_eq!(seq.set(&[0; (1 << 7) + 1]), Err(SeqError::InvalidLayout));
+
+    let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+    let mut seq = SeqInner::wrap(ring, &sopt).unwrap();
+    assert_eq!(seq.restore(), Ok(second.len() as u32));
+
+    let mut buffer = [0; (1 << 7) - 3];
+    assert_eq!(seq.get(&mut buffer), Ok(second.len()));
+    assert_eq!(buffer, second);
+
+    // Alternates back to the first buffer.
+    seq.set(&first).unwrap();
+    let mut buffer = [0; (1 << 7) - 1];
+    assert_eq!(seq.get(&mut buffer), Ok(first.len()));
+    assert_eq!(buffer, first);
+
+    let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+    let mut seq = SeqInner::wrap(ring, &single).unwrap();
+    assert_eq!(seq.set(&first), Err(SeqError::InvalidLayout));
+}
+
+#[test]
+fn seq_get_lengths() {
+    use crate::ring::{RingMapped, RingOptions};
+
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+    let sopt = SeqOptions { buffer: 1 << 7, double_buffer: false };
+
+    let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+    let mut seq = SeqInner::wrap(ring, &sopt).unwrap();
+
+    const VALUE: &[u8; 16] = b"0123456789abcdef";
+
+    for len in [4, 7, 8, 12, 13, 16] {
+        seq.set(&VALUE[..len]).unwrap();
+
+        let mut buffer = [0xff; 20];
+        assert_eq!(seq.get(&mut buffer[..len]), Ok(len));
+        assert_eq!(&buffer[..len], &VALUE[..len]);
+
+        // A longer buffer is not written past the value.
+        let mut buffer = [0xff; 20];
+        assert_eq!(seq.get(&mut buffer), Ok(len));
+        assert_eq!(&buffer[..len], &VALUE[..len]);
+        assert!(buffer[len..].iter().all(|&b| b == 0xff));
+
+        // A shorter one receives the start of the value.
+        let mut buffer = [0xff; 3];
+        assert_eq!(seq.get(&mut buffer), Ok(len));
+        assert_eq!(&buffer, &VALUE[..3]);
+    }
+}
+
+#[test]
+fn seq_set_clean_trailing_word() {
+    use crate::ring::{RingMapped, RingOptions};
+
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+    let sopt = SeqOptions { buffer: 1 << 7, double_buffer: false };
+
+    let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+    let mut seq = SeqInner::wrap(ring, &sopt).unwrap();
+
+    // Long values cover every word of the buffer.
+    for _ in 0..4 {
+        seq.set(&[0xff; 63]).unwrap();
+    }
+
+    seq.set(b"abcde").unwrap();
+
+    let mut buffer = [0x11; 8];
+    assert_eq!(seq.get(&mut buffer), Ok(5));
+    assert_eq!(&buffer, b"abcde\x11\x11\x11");
+
+    // The trailing word was stored whole, without bytes of the longer values.
+    let idx = (seq.begin + 4) & seq.layout.data_mask();
+    let word = seq.data()[(idx >> 2) as usize].load(Ordering::Relaxed);
+    assert_eq!(word.to_ne_bytes(), [b'e', 0, 0, 0]);
+}
+
+#[test]
+fn seq_restore_layout_changed() {
+    use crate::ring::{RingMapped, RingOptions};
+
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+    let sopt = |buffer, double_buffer| SeqOptions { buffer, double_buffer };
+
+    let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+    let mut seq = SeqInner::wrap(ring, &sopt(1 << 7, false)).unwrap();
+    seq.set(b"Hello, world!").unwrap();
+
+    for changed in [sopt(1 << 6, false), sopt(1 << 8, false), sopt(1 << 7, true)] {
+        let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+        let mut seq = SeqInner::wrap(ring, &changed).unwrap();
+        assert_eq!(seq.restore(), Err(SeqError::LayoutChanged));
+    }
+
+    let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+    let mut seq = SeqInner::wrap(ring, &sopt(1 << 7, false)).unwrap();
+    assert_eq!(seq.restore(), Ok(13));
+}
+
+#[test]
+fn sync_calls_msync() {
+    use crate::area::AreaFd;
+    use crate::mmap::fault;
+    use crate::ring::RingOptions;
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+    let ring = Ring::new(fault::mapper(), AreaFd::without_file(1 << 12), &ropt).unwrap();
+    assert_eq!(fault::syncs(), 0);
+    ring.sync().unwrap();
+    assert_eq!(fault::syncs(), 1);
+
+    let mut seq = Seq::new(ring, &SeqOptions { buffer: 1 << 7, double_buffer: false }).unwrap();
+    seq.set(b"Hello, world!").unwrap();
+    seq.sync().unwrap();
+    assert_eq!(fault::syncs(), 2);
+}
+
+#[test]
+fn seq_and_ring_share_mapping() {
+    use crate::area::{AreaFd, MappedFd};
+    use crate::mmap::fault;
+    use crate::ring::RingOptions;
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+    let desc = Descriptor {
+        start: 0x400,
+        end: 0x410,
+        payload: 7,
+    };
+
+    let mapfd = MappedFd::new(fault::mapper(), AreaFd::without_file(1 << 13)).unwrap();
+    let (head, tail) = mapfd.split(1 << 12).unwrap();
+
+    let mut ring = Ring::with_part(head.clone(), &ropt).unwrap();
+    ring.push(desc).unwrap();
+
+    let ring_seq = Ring::with_part(tail.clone(), &ropt).unwrap();
+    let mut seq = Seq::new(ring_seq, &SeqOptions { buffer: 1 << 7, double_buffer: false }).unwrap();
+    seq.set(b"Hello, world!").unwrap();
+    drop(seq);
+
+    // Both survive, independent of each other, in the same mapping.
+    assert_eq!(Ring::with_part(head, &ropt).unwrap().restore(), Some(desc));
+    let ring_seq = Ring::with_part(tail, &ropt).unwrap();
+    let mut seq = Seq::new(ring_seq, &SeqOptions { buffer: 1 << 7, double_buffer: false }).unwrap();
+    assert_eq!(seq.restore(), Ok(13));
+
+    assert_eq!(fault::live(), 1);
+    drop(ring);
+    drop(seq);
+    assert_eq!(fault::live(), 0);
+}
+
+#[test]
+fn seq_compare_and_set() {
+    use crate::ring::{RingMapped, RingOptions};
+
+    static REGION: [AtomicU32; 1 << 10] = [const { AtomicU32::new(0) }; 1 << 10];
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+    let sopt = SeqOptions { buffer: 1 << 7, double_buffer: false };
+
+    let ring = RingMapped::wrap(&REGION, &ropt).unwrap();
+    let mut seq = SeqInner::wrap(ring, &sopt).unwrap();
+    seq.set(b"first").unwrap();
+
+    // Neither a different value, nor a prefix of the value matches.
+    assert_eq!(seq.compare_and_set(b"other", b"second"), Ok(false));
+    assert_eq!(seq.compare_and_set(b"fir", b"second"), Ok(false));
+
+    let mut buffer = [0; 8];
+    assert_eq!(seq.get(&mut buffer), Ok(5));
+    assert_eq!(&buffer[..5], b"first");
+
+    assert_eq!(seq.compare_and_set(b"first", b"second"), Ok(true));
+    assert_eq!(seq.get(&mut buffer), Ok(6));
+    assert_eq!(&buffer[..6], b"second");
+
+    // A value that does not fit is an error, even when the comparison matches.
+    let large = [0; 1 << 7];
+    assert_eq!(seq.compare_and_set(b"second", &large), Err(SeqError::InvalidLayout));
+}
+
+#[test]
+fn logs_behind_dyn_log() {
+    use crate::area::AreaFd;
+    use crate::logs::{Latest, Log};
+    use crate::mmap::fault;
+    use crate::ring::RingOptions;
+    use alloc::{boxed::Box, vec::Vec};
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+    let ring = Ring::new(fault::mapper(), AreaFd::without_file(1 << 12), &ropt).unwrap();
+    let seq = Seq::new(ring, &SeqOptions { buffer: 1 << 7, double_buffer: false }).unwrap();
+    let latest = Latest::new(fault::mapper(), AreaFd::without_file(1 << 12), 5).unwrap();
+
+    let logs: Vec<Box<dyn Log<Error = SeqError>>> = alloc::vec![Box::new(seq), Box::new(latest)];
+
+    for mut log in logs {
+        assert_eq!(log.restore(), Err(SeqError::NoSnapshot));
+        log.set(b"Hello").unwrap();
+
+        let mut buffer = [0; 5];
+        assert_eq!(log.get(&mut buffer), Ok(5));
+        assert_eq!(&buffer, b"Hello");
+    }
+
+    assert_eq!(fault::live(), 0);
+}
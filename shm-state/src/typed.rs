@@ -0,0 +1,150 @@
+//! A sequential log of typed values.
+use crate::logs::{Seq, SeqError};
+use alloc::vec;
+use core::marker::PhantomData;
+
+/// A value of fixed length, stored by its bytes in a [`TypedSeq`].
+///
+/// Implemented for the integer types and byte arrays. With the `bytemuck` feature it is instead
+/// implemented for all `bytemuck::Pod` types.
+pub trait Value: Sized {
+    /// The length in bytes of every value.
+    const LEN: usize;
+
+    /// Write the bytes of the value into `bytes`, which is `LEN` long.
+    fn to_bytes(&self, bytes: &mut [u8]);
+
+    /// Read a value from `bytes`, which is `LEN` long. `None` if they are not a valid value.
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+/// A [`Seq`] of values of one type.
+///
+/// Converts values to and from their bytes, and checks that restored values have the length of
+/// the type.
+pub struct TypedSeq<T> {
+    seq: Seq,
+    value: PhantomData<fn(T) -> T>,
+}
+
+impl<T: Value> TypedSeq<T> {
+    pub fn new(seq: Seq) -> Self {
+        TypedSeq {
+            seq,
+            value: PhantomData,
+        }
+    }
+
+    /// Find the most recent value, see [`Seq::restore`].
+    ///
+    /// Fails with `LayoutChanged` if the value does not have the length of `T`.
+    pub fn restore(&mut self) -> Result<(), SeqError> {
+        if self.seq.restore()? as usize != T::LEN {
+            return Err(SeqError::LayoutChanged);
+        }
+
+        Ok(())
+    }
+
+    /// Change the current value.
+    pub fn set(&mut self, value: &T) -> Result<(), SeqError> {
+        let mut bytes = vec![0; T::LEN];
+        value.to_bytes(&mut bytes);
+        self.seq.set(&bytes)
+    }
+
+    /// Retrieve the current value.
+    ///
+    /// Returns `None` if there is no value of the length of `T`, or its bytes are not valid.
+    pub fn get(&self) -> Option<T> {
+        if self.seq.len() != T::LEN {
+            return None;
+        }
+
+        let mut bytes = vec![0; T::LEN];
+        self.seq.get(&mut bytes).ok()?;
+        T::from_bytes(&bytes)
+    }
+
+    pub fn into_inner(self) -> Seq {
+        self.seq
+    }
+}
+
+#[cfg(feature = "bytemuck")]
+impl<T: bytemuck::Pod> Value for T {
+    const LEN: usize = core::mem::size_of::<T>();
+
+    fn to_bytes(&self, bytes: &mut [u8]) {
+        bytes.copy_from_slice(bytemuck::bytes_of(self));
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        bytemuck::try_pod_read_unaligned(bytes).ok()
+    }
+}
+
+macro_rules! value_native_endian {
+    ($($ty:ty),*) => {
+        $(
+            #[cfg(not(feature = "bytemuck"))]
+            impl Value for $ty {
+                const LEN: usize = core::mem::size_of::<$ty>();
+
+                fn to_bytes(&self, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&self.to_ne_bytes());
+                }
+
+                fn from_bytes(bytes: &[u8]) -> Option<Self> {
+                    Some(<$ty>::from_ne_bytes(bytes.try_into().ok()?))
+                }
+            }
+        )*
+    };
+}
+
+value_native_endian!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+#[cfg(not(feature = "bytemuck"))]
+impl<const N: usize> Value for [u8; N] {
+    const LEN: usize = N;
+
+    fn to_bytes(&self, bytes: &mut [u8]) {
+        bytes.copy_from_slice(self);
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        bytes.try_into().ok()
+    }
+}
+
+#[test]
+fn typed_seq_checks_length() {
+    use crate::area::AreaFd;
+    use crate::logs::SeqOptions;
+    use crate::mmap::fault;
+    use crate::{Ring, RingOptions};
+
+    let ropt = RingOptions { nr_descriptors: 2 };
+    let ring = Ring::new(fault::mapper(), AreaFd::without_file(1 << 12), &ropt).unwrap();
+    let seq = Seq::new(ring, &SeqOptions { buffer: 1 << 7, double_buffer: false }).unwrap();
+
+    let mut typed = TypedSeq::<u64>::new(seq);
+    assert_eq!(typed.get(), None);
+    assert_eq!(typed.restore(), Err(SeqError::NoSnapshot));
+
+    typed.set(&0x0123_4567_89ab_cdef).unwrap();
+    assert_eq!(typed.get(), Some(0x0123_4567_89ab_cdef));
+    assert_eq!(typed.restore(), Ok(()));
+
+    let mut typed = TypedSeq::<[u8; 8]>::new(typed.into_inner());
+    assert_eq!(typed.restore(), Ok(()));
+    assert_eq!(typed.get(), Some(0x0123_4567_89ab_cdef_u64.to_ne_bytes()));
+
+    // A value of another length is not one of the type.
+    let mut seq = typed.into_inner();
+    seq.set(b"abc").unwrap();
+    let mut typed = TypedSeq::<u64>::new(seq);
+    assert_eq!(typed.get(), None);
+    assert_eq!(typed.restore(), Err(SeqError::LayoutChanged));
+}
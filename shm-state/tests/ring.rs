@@ -1,7 +1,7 @@
-use shm_state::Mapper;
-
 #[test]
 #[cfg(feature = "libc")]
 fn setup() {
-    let map = Mapper::new();
+    use shm_state::Mapper;
+
+    let _map = Mapper::new();
 }
@@ -1,5 +1,5 @@
 use shmfd_test_validate::Env;
-use shmfd_test_executables::SHM_PRIMES_SNAPSHOT;
+use shmfd_test_executables::{SHM_PRIMES_SNAPSHOT, SHM_RESTORE};
 
 use std::process::Command;
 
@@ -14,3 +14,28 @@ fn primes_snapshot() {
         cmd
     }).success();
 }
+
+#[test]
+fn primes_snapshot_restore_v1() {
+    let dir = tempfile::tempdir().unwrap();
+    let backup = dir.path().join("backup");
+
+    let env = Env::new();
+    env.shared_fd({
+        let mut cmd = Command::new(SHM_RESTORE);
+        cmd.args(["--snapshot", "restore-v1"]);
+        cmd.arg(&backup);
+        cmd.args([SHM_PRIMES_SNAPSHOT, "1", "10000"]);
+        cmd
+    }).success();
+
+    // The backup was persisted by a snapshot or the final write back.
+    let backup = std::fs::File::open(&backup).unwrap();
+    let file = shm_snapshot::File::new_readonly(std::os::fd::AsRawFd::as_raw_fd(&backup)).unwrap();
+
+    let mut cfg = shm_snapshot::ConfigureFile::default();
+    let recovered = file.try_recover(&mut cfg).unwrap();
+    let mut valid = vec![];
+    recovered.valid(&mut valid);
+    assert!(!valid.is_empty(), "No snapshot in the backup");
+}
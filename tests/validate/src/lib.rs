@@ -1,17 +1,23 @@
 use std::os::fd::{AsRawFd, IntoRawFd, RawFd};
 use std::os::unix::process::CommandExt;
+use std::sync::{Mutex, MutexGuard};
 
 use assert_cmd::{assert::Assert, Command};
 use memfile::MemFile;
 
 pub struct Env {
     file: MemFile,
-    #[allow(dead_code)]
     placeholder: RawFd,
+    /// Held while the placeholder occupies file descriptor 3.
+    _serial: MutexGuard<'static, ()>,
 }
 
+/// Tests of one binary run in parallel, but only one can hold file descriptor 3 at a time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
 impl Env {
     pub fn new() -> Self {
+        let _serial = SERIAL.lock().unwrap_or_else(|poison| poison.into_inner());
         let placeholder =
             MemFile::create_default("placeholder").expect("failed to initialized shm-file");
         assert_eq!(placeholder.as_raw_fd(), 3);
@@ -20,6 +26,7 @@ impl Env {
         Env {
             file,
             placeholder: placeholder.into_raw_fd(),
+            _serial,
         }
     }
 
@@ -48,3 +55,10 @@ impl Env {
         cmd.assert()
     }
 }
+
+impl Drop for Env {
+    fn drop(&mut self) {
+        // Free file descriptor 3 for the next test, before the lock is released.
+        unsafe { libc::close(self.placeholder) };
+    }
+}